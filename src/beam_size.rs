@@ -0,0 +1,118 @@
+use crate::*;
+
+/// How the vertical emittance is given.  A coupling ratio kappa shares the natural emittance
+/// between the planes, with ex = e0 / (1 + kappa) and ey = kappa e0 / (1 + kappa), while an
+/// emittance leaves the horizontal one at the natural emittance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VerticalEmittance {
+    Coupling(f64),
+    Emittance(f64),
+}
+
+/// RMS beam size and divergence in each plane, including the dispersive contribution of the
+/// energy spread.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BeamSize {
+    pub sigma_x: f64,
+    pub sigma_px: f64,
+    pub sigma_y: f64,
+    pub sigma_py: f64,
+}
+
+impl BeamSize {
+    /// Beam size from the optics of the horizontal and vertical modes, the emittance of each
+    /// and the energy spread.
+    pub fn from_optics(optics: &ModeOptics, emittances: [f64; 2], e_spread: f64) -> Self {
+        let plane = |k: usize| {
+            let (beta, alpha) = (optics.beta[k][k], optics.alpha[k][k]);
+            let gamma = (1.0 + alpha * alpha) / beta;
+            let (eta, eta_p) = (optics.eta[2 * k], optics.eta[2 * k + 1]);
+            [
+                (emittances[k] * beta + (eta * e_spread).powi(2)).sqrt(),
+                (emittances[k] * gamma + (eta_p * e_spread).powi(2)).sqrt(),
+            ]
+        };
+        let [sigma_x, sigma_px] = plane(0);
+        let [sigma_y, sigma_py] = plane(1);
+        Self {
+            sigma_x,
+            sigma_px,
+            sigma_y,
+            sigma_py,
+        }
+    }
+}
+
+impl OpticsPoint {
+    pub fn beam_size(&self, emittances: [f64; 2], e_spread: f64) -> BeamSize {
+        BeamSize::from_optics(&self.optics, emittances, e_spread)
+    }
+}
+
+impl Line {
+    /// Horizontal and vertical emittances from the natural emittance and the vertical one.
+    pub fn emittances(&self, vertical: VerticalEmittance) -> [f64; 2] {
+        match vertical {
+            VerticalEmittance::Coupling(kappa) => [
+                self.nat_emitt_x / (1.0 + kappa),
+                kappa * self.nat_emitt_x / (1.0 + kappa),
+            ],
+            VerticalEmittance::Emittance(emittance) => [self.nat_emitt_x, emittance],
+        }
+    }
+
+    /// Beam size at the entrance of each element of a cell, followed by the end of the cell.
+    pub fn beam_sizes(&self, vertical: VerticalEmittance) -> Option<Vec<BeamSize>> {
+        let modes = self.normal_modes.as_ref()?;
+        let emittances = self.emittances(vertical);
+        Some(
+            modes
+                .optics
+                .iter()
+                .map(|optics| BeamSize::from_optics(optics, emittances, self.e_spread))
+                .collect(),
+        )
+    }
+}
+
+// A CSV field in double quotes, with any quotes inside it doubled.
+fn quote_csv(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+/// The optics table of `optics_table` with the beam size at each point, as comma separated
+/// values.  Element names are quoted.
+pub fn optics_table_csv(
+    line: &Line,
+    n_slices: usize,
+    vertical: VerticalEmittance,
+) -> Option<String> {
+    let emittances = line.emittances(vertical);
+    let mut retval = String::from(
+        "s,element,name,beta_x,alpha_x,beta_y,alpha_y,eta_x,eta_px,eta_y,eta_py,\
+         sigma_x,sigma_px,sigma_y,sigma_py\n",
+    );
+    for point in optics_table(line, n_slices)? {
+        let o = &point.optics;
+        let size = point.beam_size(emittances, line.e_spread);
+        retval.push_str(&format!(
+            "{:.6},{},{},{:.6},{:.6},{:.6},{:.6},{:e},{:e},{:e},{:e},{:e},{:e},{:e},{:e}\n",
+            point.s,
+            point.element,
+            quote_csv(&line.line[point.element].name),
+            o.beta[0][0],
+            o.alpha[0][0],
+            o.beta[1][1],
+            o.alpha[1][1],
+            o.eta[0],
+            o.eta[1],
+            o.eta[2],
+            o.eta[3],
+            size.sigma_x,
+            size.sigma_px,
+            size.sigma_y,
+            size.sigma_py
+        ));
+    }
+    Some(retval)
+}
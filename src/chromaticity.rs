@@ -0,0 +1,166 @@
+use crate::matching::set_element_knob;
+use crate::*;
+use ndarray::{Array1, Array2, s};
+use num_complex::Complex64;
+
+/// The first order driving terms, which are linear in the sextupole strengths.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DrivingTerm {
+    H21000,
+    H30000,
+    H10110,
+    H10020,
+    H10200,
+    H20001,
+    H00201,
+    H10002,
+}
+
+impl DrivingTerms {
+    pub fn get(&self, term: DrivingTerm) -> Complex64 {
+        match term {
+            DrivingTerm::H21000 => self.h21000,
+            DrivingTerm::H30000 => self.h30000,
+            DrivingTerm::H10110 => self.h10110,
+            DrivingTerm::H10020 => self.h10020,
+            DrivingTerm::H10200 => self.h10200,
+            DrivingTerm::H20001 => self.h20001,
+            DrivingTerm::H00201 => self.h00201,
+            DrivingTerm::H10002 => self.h10002,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChromaticitySettings {
+    /// Names of the sextupole families, each made up of every element with that name.
+    pub families: Vec<String>,
+    pub target: [f64; 2],
+    /// Driving terms to minimise, with their weights.  When empty the change in the family
+    /// strengths is minimised instead.
+    pub driving_terms: Vec<(DrivingTerm, f64)>,
+    /// Tracking used to find the chromaticity from the off-momentum closed orbits.  The cavities
+    /// are always off.
+    pub tracking: TrackingSettings,
+}
+
+const CHROMATIC_DELTAS: [f64; 5] = [-2e-4, -1e-4, 0.0, 1e-4, 2e-4];
+const MAX_ITERATIONS: usize = 10;
+const TOLERANCE: f64 = 1e-6;
+
+// The linear chromaticity of the tracked off-momentum tunes.
+fn tracked_chromaticity(line: &Line, tracking: &TrackingSettings) -> Option<[f64; 2]> {
+    let tracking = TrackingSettings {
+        cavities: false,
+        ..tracking.clone()
+    };
+    let chromatic = chromatic_tunes(line, &CHROMATIC_DELTAS, &tracking).ok()?;
+    Some([chromatic.coefficients[0][1], chromatic.coefficients[1][1]])
+}
+
+// The tracked chromaticities followed by the real and imaginary parts of the weighted driving
+// terms.
+fn chromatic_response(line: &Line, settings: &ChromaticitySettings) -> Option<Vec<f64>> {
+    let mut retval = tracked_chromaticity(line, &settings.tracking)?.to_vec();
+    if !settings.driving_terms.is_empty() {
+        let terms = driving_terms(line)?;
+        for (term, weight) in settings.driving_terms.iter() {
+            let value = weight * terms.get(*term);
+            retval.push(value.re);
+            retval.push(value.im);
+        }
+    }
+    Some(retval)
+}
+
+// The change in the family strengths that minimises |h + B d|^2 + epsilon |d|^2 subject to
+// A d = target - xi, with the Lagrange multipliers of the two chromaticity constraints appended
+// to the unknowns.
+fn correction_step(
+    response: &Array2<f64>,
+    base: &[f64],
+    settings: &ChromaticitySettings,
+) -> Option<Array1<f64>> {
+    let n = response.ncols();
+    let a_mat = response.slice(s![0..2, ..]);
+    let b_mat = response.slice(s![2.., ..]);
+    let mut hessian = b_mat.t().dot(&b_mat);
+    let epsilon = if settings.driving_terms.is_empty() {
+        1.0
+    } else {
+        1e-9 * hessian.diag().sum().max(1.0) / n as f64
+    };
+    hessian += &(epsilon * Array2::<f64>::eye(n));
+    let gradient = b_mat.t().dot(&Array1::from(base[2..].to_vec()));
+
+    let mut system = Array2::<f64>::zeros((n + 2, n + 2));
+    let mut rhs = Array1::<f64>::zeros(n + 2);
+    for i in 0..n {
+        for j in 0..n {
+            system[[i, j]] = hessian[[i, j]];
+        }
+        for k in 0..2 {
+            system[[i, n + k]] = a_mat[[k, i]];
+            system[[n + k, i]] = a_mat[[k, i]];
+        }
+        rhs[i] = -gradient[i];
+    }
+    for k in 0..2 {
+        rhs[n + k] = settings.target[k] - base[k];
+    }
+    Some(solve(&system, &rhs)?.slice(s![0..n]).to_owned())
+}
+
+/// Sets the chromaticity, found from the tracked off-momentum tunes, to `settings.target` with
+/// the sextupole families.  The response of the chromaticity and the first order driving terms to
+/// each family is found once, and the constrained least squares correction is repeated until the
+/// tracked chromaticity is within 1e-6 of the target.
+pub fn correct_chromaticity(
+    line: &Line,
+    settings: &ChromaticitySettings,
+) -> Result<MatchResult, MatchError> {
+    let n = settings.families.len();
+    let mut initial = Vec::with_capacity(n);
+    for family in settings.families.iter() {
+        let ele = line.line.iter().find(|ele| &ele.name == family);
+        initial.push(ele.ok_or(MatchError)?.k[2]);
+    }
+
+    let with_strengths = |values: &[f64]| {
+        let mut elements = line.line.clone();
+        for (family, value) in settings.families.iter().zip(values.iter()) {
+            set_element_knob(&mut elements, family, 2, *value);
+        }
+        Line::from_elements(elements, line.periodicity, line.energy)
+    };
+
+    let mut base = chromatic_response(line, settings).ok_or(MatchError)?;
+    let mut response = Array2::<f64>::zeros((base.len(), n));
+    for j in 0..n {
+        let mut values = initial.clone();
+        values[j] += 1.0;
+        let shifted = chromatic_response(&with_strengths(&values), settings).ok_or(MatchError)?;
+        for (i, (a, b)) in shifted.iter().zip(base.iter()).enumerate() {
+            response[[i, j]] = a - b;
+        }
+    }
+
+    let mut values = initial;
+    for iteration in 0..MAX_ITERATIONS {
+        let step = correction_step(&response, &base, settings).ok_or(MatchError)?;
+        for (value, d) in values.iter_mut().zip(step.iter()) {
+            *value += d;
+        }
+        let corrected = with_strengths(&values);
+        base = chromatic_response(&corrected, settings).ok_or(MatchError)?;
+        if (0..2).all(|k| (base[k] - settings.target[k]).abs() < TOLERANCE) {
+            return Ok(MatchResult {
+                values,
+                cost: base[2..].iter().map(|x| x * x).sum(),
+                line: corrected,
+                iterations: iteration + 1,
+            });
+        }
+    }
+    Err(MatchError)
+}
@@ -0,0 +1,136 @@
+use crate::*;
+use ndarray::{Array1, Array2};
+use std::error::Error;
+use std::fmt;
+
+const MAX_ITERATIONS: usize = 50;
+const TOLERANCE: f64 = 1e-12;
+const DIFF_STEP: f64 = 1e-8;
+
+#[derive(Debug)]
+pub struct ClosedOrbitError;
+
+impl Error for ClosedOrbitError {}
+
+impl fmt::Display for ClosedOrbitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Closed orbit search did not converge")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrbitDimension {
+    FourD,
+    SixD,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClosedOrbit {
+    pub orbit: Vec<[f64; 6]>,
+    pub line_matrix: Array2<f64>,
+    pub total_matrix: Array2<f64>,
+    pub iterations: usize,
+}
+
+/// Finds the closed orbit of the line using the linear transfer matrices of the elements.  In 4D
+/// the momentum offset is fixed at `delta`, even through cavities, and the longitudinal position
+/// is left free, while in 6D both longitudinal coordinates are solved for (which requires RF).
+pub fn find_closed_orbit(
+    line: &Line,
+    dimension: OrbitDimension,
+    delta: f64,
+) -> Result<ClosedOrbit, ClosedOrbitError> {
+    find_closed_orbit_with(line, dimension, delta, Element::transport)
+}
+
+/// As `find_closed_orbit`, but with the map through each element given by `transport`.
+pub fn find_closed_orbit_with<F>(
+    line: &Line,
+    dimension: OrbitDimension,
+    delta: f64,
+    transport: F,
+) -> Result<ClosedOrbit, ClosedOrbitError>
+where
+    F: Fn(&Element, &mut [f64; 6]),
+{
+    let n_vars = match dimension {
+        OrbitDimension::FourD => 4,
+        OrbitDimension::SixD => 6,
+    };
+    // In 4D the momentum offset is held at its value at the start of the cell, so that cavities
+    // do not change it.
+    let step = |ele: &Element, coords: &mut [f64; 6]| {
+        let delta = coords[5];
+        transport(ele, coords);
+        if dimension == OrbitDimension::FourD {
+            coords[5] = delta;
+        }
+    };
+    let one_cell = |coords: &[f64; 6]| {
+        let mut retval = *coords;
+        for ele in line.line.iter() {
+            step(ele, &mut retval);
+        }
+        retval
+    };
+
+    let mut guess = [0.0; 6];
+    guess[5] = delta;
+
+    for iteration in 0..MAX_ITERATIONS {
+        let mapped = one_cell(&guess);
+        if mapped.iter().any(|x| !x.is_finite()) {
+            return Err(ClosedOrbitError);
+        }
+        let residual = Array1::from_iter((0..n_vars).map(|i| mapped[i] - guess[i]));
+        if residual.iter().all(|x| x.abs() < TOLERANCE) {
+            let line_matrix = jacobian(&one_cell, &guess);
+            let total_matrix = apply_matrix_n_times(&line_matrix, line.periodicity);
+            let mut orbit = Vec::with_capacity(line.line.len() + 1);
+            let mut coords = guess;
+            orbit.push(coords);
+            for ele in line.line.iter() {
+                step(ele, &mut coords);
+                orbit.push(coords);
+            }
+            return Ok(ClosedOrbit {
+                orbit,
+                line_matrix,
+                total_matrix,
+                iterations: iteration,
+            });
+        }
+
+        let matrix = jacobian(&one_cell, &guess);
+        let mut system = Array2::<f64>::zeros((n_vars, n_vars));
+        for i in 0..n_vars {
+            for j in 0..n_vars {
+                system[[i, j]] = matrix[[i, j]] - if i == j { 1.0 } else { 0.0 };
+            }
+        }
+        let step = solve(&system, &residual).ok_or(ClosedOrbitError)?;
+        for i in 0..n_vars {
+            guess[i] -= step[i];
+        }
+    }
+
+    Err(ClosedOrbitError)
+}
+
+fn jacobian<F>(map: &F, coords: &[f64; 6]) -> Array2<f64>
+where
+    F: Fn(&[f64; 6]) -> [f64; 6],
+{
+    let mut retval = Array2::zeros((6, 6));
+    for j in 0..6 {
+        let mut plus = *coords;
+        let mut minus = *coords;
+        plus[j] += DIFF_STEP;
+        minus[j] -= DIFF_STEP;
+        let (plus, minus) = (map(&plus), map(&minus));
+        for i in 0..6 {
+            retval[[i, j]] = (plus[i] - minus[i]) / (2.0 * DIFF_STEP);
+        }
+    }
+    retval
+}
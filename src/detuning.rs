@@ -0,0 +1,514 @@
+use crate::normal_modes::optics_inside_elements;
+use crate::*;
+use ndarray::{Array1, Array2};
+use num_complex::Complex64;
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DetuningError {
+    /// The line has no stable linear optics.
+    NoNormalModes,
+    /// The closed orbit, on or off momentum, was not found.
+    ClosedOrbit,
+    /// A particle was lost while its tunes were being tracked.
+    ParticleLost,
+    /// Fewer than four distinct momentum offsets were given for the cubic fit.
+    SingularFit,
+}
+
+impl Error for DetuningError {}
+
+impl fmt::Display for DetuningError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoNormalModes => write!(f, "The line has no stable linear optics"),
+            Self::ClosedOrbit => write!(f, "Closed orbit finder did not converge"),
+            Self::ParticleLost => write!(f, "A particle was lost during tracking"),
+            Self::SingularFit => write!(f, "At least four distinct momentum offsets are needed"),
+        }
+    }
+}
+
+impl From<ClosedOrbitError> for DetuningError {
+    fn from(_: ClosedOrbitError) -> Self {
+        Self::ClosedOrbit
+    }
+}
+
+/// Tune shifts with Courant-Snyder action, where x = sqrt(2 J beta) cos(phi).
+#[derive(Debug, Clone, Copy)]
+pub struct Detuning {
+    pub dqx_djx: f64,
+    pub dqx_djy: f64,
+    pub dqy_djy: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DetuningSettings {
+    pub x_max: f64,
+    pub y_max: f64,
+    /// Number of amplitudes in each plane, spread evenly out to `x_max` and `y_max`.
+    pub n_amplitudes: usize,
+    pub n_turns: usize,
+    pub tracking: TrackingSettings,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChromaticTunes {
+    pub deltas: Vec<f64>,
+    /// Full ring tunes in x and y at each momentum offset.
+    pub tunes: Vec<[f64; 2]>,
+    /// Coefficients of the cubic fit Q(delta) = c0 + c1 delta + c2 delta^2 + c3 delta^3 in each
+    /// plane, so that `coefficients[0][1]` is the linear chromaticity in x.
+    pub coefficients: [[f64; 4]; 2],
+}
+
+const THIN_SLICES: usize = 8;
+const CHROMATIC_SLICES: usize = 16;
+
+// Thin kicks making up the elements of the full ring with a non-zero value of k[index].  Elements
+// with drift-like linear optics are split into `THIN_SLICES` kicks, other elements are treated as a
+// single kick at their centre.
+pub(crate) struct Kick {
+    pub strength: f64,
+    pub beta: [f64; 2],
+    pub phase: [f64; 2],
+    pub eta: f64,
+    /// Index of the element in the full ring.
+    pub element: usize,
+}
+
+pub(crate) fn ring_kicks(line: &Line, modes: &NormalModes, index: usize) -> Vec<Kick> {
+    let n_eles = line.line.len();
+    let mut cell_kicks = vec![];
+    for (i, ele) in line.line.iter().enumerate() {
+        if ele.k[index] == 0.0 {
+            continue;
+        }
+        let (a, b) = (&modes.optics[i], &modes.optics[i + 1]);
+        if ele.k[0] == 0.0 && ele.k[1] == 0.0 && ele.length > 0.0 {
+            let step = ele.length / THIN_SLICES as f64;
+            for slice in 0..THIN_SLICES {
+                let s = (slice as f64 + 0.5) * step;
+                let optics = |k: usize| {
+                    let (beta, alpha) = (a.beta[k][k], a.alpha[k][k]);
+                    let gamma = (1.0 + alpha * alpha) / beta;
+                    let dphase = s.atan2(beta - alpha * s);
+                    (beta - 2.0 * alpha * s + gamma * s * s, a.phase[k] + dphase)
+                };
+                let ((beta_x, phase_x), (beta_y, phase_y)) = (optics(0), optics(1));
+                cell_kicks.push(Kick {
+                    strength: ele.k[index] * step,
+                    beta: [beta_x, beta_y],
+                    phase: [phase_x, phase_y],
+                    eta: a.eta[0] + a.eta[1] * s,
+                    element: i,
+                });
+            }
+        } else {
+            cell_kicks.push(Kick {
+                strength: ele.k[index] * ele.effective_length(),
+                beta: [
+                    0.5 * (a.beta[0][0] + b.beta[0][0]),
+                    0.5 * (a.beta[1][1] + b.beta[1][1]),
+                ],
+                phase: [
+                    0.5 * (a.phase[0] + b.phase[0]),
+                    0.5 * (a.phase[1] + b.phase[1]),
+                ],
+                eta: 0.5 * (a.eta[0] + b.eta[0]),
+                element: i,
+            });
+        }
+    }
+
+    let cell_phase = modes.optics[n_eles].phase;
+    (0..line.periodicity)
+        .flat_map(|cell| {
+            cell_kicks.iter().map(move |kick| Kick {
+                strength: kick.strength,
+                beta: kick.beta,
+                phase: std::array::from_fn(|k| kick.phase[k] + cell as f64 * cell_phase[k]),
+                eta: kick.eta,
+                element: kick.element + cell * n_eles,
+            })
+        })
+        .collect()
+}
+
+// Periodic solution of u'' + K u = F at the points of one cell, in order along the line, from the
+// source F integrated over each point, where mu is the phase advance of the cell.
+fn periodic_response(sources: &[f64], beta: &[f64], phase: &[f64], mu: f64) -> Vec<f64> {
+    let terms: Vec<Complex64> = sources
+        .iter()
+        .zip(beta.iter().zip(phase.iter()))
+        .map(|(f, (b, p))| f * b.sqrt() * Complex64::from_polar(1.0, *p))
+        .collect();
+    let mut following: Complex64 = terms.iter().sum();
+    let mut preceding = Complex64::new(0.0, 0.0);
+    let shift = Complex64::from_polar(1.0, mu);
+    let mut retval = Vec::with_capacity(terms.len());
+    for (term, (b, p)) in terms.iter().zip(beta.iter().zip(phase.iter())) {
+        let sum = following + shift * preceding;
+        let response = (Complex64::from_polar(1.0, -p - 0.5 * mu) * sum).re;
+        retval.push(b.sqrt() * response / (2.0 * (0.5 * mu).sin()));
+        following -= term;
+        preceding += term;
+    }
+    retval
+}
+
+// Sum of w_i w_j cos(2 |phi_i - phi_j| - 2 pi Q) over the pairs of points of the full ring, from
+// the points of one cell in order along the line, where mu is the phase advance of the cell.
+fn ring_double_sum(weights: &[f64], phase: &[f64], mu: f64, periodicity: usize) -> f64 {
+    let z = Complex64::from_polar(1.0, 2.0 * mu);
+    let after: Complex64 = (0..periodicity).map(|cell| z.powu(cell as u32)).sum();
+    let before = z * after;
+    let ring_mu = periodicity as f64 * mu;
+    let terms: Vec<Complex64> = weights
+        .iter()
+        .zip(phase.iter())
+        .map(|(w, p)| w * Complex64::from_polar(1.0, 2.0 * p))
+        .collect();
+    let mut following: Complex64 = terms.iter().sum();
+    let mut preceding = Complex64::new(0.0, 0.0);
+    let mut sum = 0.0;
+    for (term, (w, p)) in terms.iter().zip(weights.iter().zip(phase.iter())) {
+        let rotation = Complex64::from_polar(1.0, -2.0 * p - ring_mu);
+        sum += (w * rotation * (after * following + before * preceding)).re;
+        following -= term;
+        preceding += term;
+    }
+    periodicity as f64 * sum
+}
+
+// A slice of an element of one cell with a field, with the optics at its centre.  Thin elements
+// are a single slice with their integrated strengths over unit length.
+pub(crate) struct FieldSlice {
+    /// Index of the element in the cell.
+    pub element: usize,
+    pub length: f64,
+    pub h: f64,
+    pub k1: f64,
+    pub b3: f64,
+    pub b4: f64,
+    pub optics: ModeOptics,
+}
+
+// Elements of one cell with a bend, gradient, sextupole or octupole field, each split into
+// `CHROMATIC_SLICES` slices.
+pub(crate) fn field_slices(line: &Line) -> Option<Vec<FieldSlice>> {
+    let mut locations = vec![];
+    let mut fields = vec![];
+    for (i, ele) in line.line.iter().enumerate() {
+        if ele.k[..4].iter().all(|k| *k == 0.0) {
+            continue;
+        }
+        if ele.length > 0.0 {
+            let step = ele.length / CHROMATIC_SLICES as f64;
+            for slice in 0..CHROMATIC_SLICES {
+                locations.push((i, (slice as f64 + 0.5) * step));
+                fields.push([step, ele.k[0] / ele.length, ele.k[1], ele.k[2], ele.k[3]]);
+            }
+        } else {
+            locations.push((i, 0.0));
+            fields.push([1.0, 0.0, 0.0, ele.k[2], ele.k[3]]);
+        }
+    }
+    let optics = optics_inside_elements(line, &locations)?;
+    Some(
+        optics
+            .into_iter()
+            .zip(locations.iter().zip(fields.iter()))
+            .map(
+                |(optics, (&(element, _), &[length, h, k1, b3, b4]))| FieldSlice {
+                    element,
+                    length,
+                    h,
+                    k1,
+                    b3,
+                    b4,
+                    optics,
+                },
+            )
+            .collect(),
+    )
+}
+
+/// First order chromaticity from the linear optics, sampled at `CHROMATIC_SLICES` points through
+/// each element.
+pub fn analytic_chromaticity(line: &Line) -> Option<[f64; 2]> {
+    let mut retval = [0.0; 2];
+    for slice in field_slices(line)? {
+        let (beta_x, beta_y) = (slice.optics.beta[0][0], slice.optics.beta[1][1]);
+        let sext = 2.0 * slice.b3 * slice.optics.eta[0];
+        let focusing = slice.h * slice.h + slice.k1;
+        retval[0] += beta_x * (sext - focusing) * slice.length;
+        retval[1] += beta_y * (slice.k1 - sext) * slice.length;
+    }
+    Some(retval.map(|x| x * line.periodicity as f64 / (4.0 * PI)))
+}
+
+/// Second order chromaticity, the coefficient of delta^2 in the tunes, from second order
+/// perturbation theory in the off-momentum focusing.  The quadrupoles and bends focus as
+/// 1 / (1 + delta), and the sextupoles and octupoles see the orbit eta_1 delta + eta_2 delta^2,
+/// with the second order dispersion eta_2 driven by the bends, the quadrupoles and the
+/// sextupoles.  The optics is sampled at `CHROMATIC_SLICES` points through each element.  Orders
+/// beyond the second are only found from tracking, by the cubic fit of `chromatic_tunes`.
+pub fn analytic_second_order_chromaticity(line: &Line) -> Option<[f64; 2]> {
+    let modes = line.normal_modes.as_ref()?;
+    let mu = modes.optics[line.line.len()].phase;
+    let slices = field_slices(line)?;
+    let beta: [Vec<f64>; 2] = [0, 1].map(|k| slices.iter().map(|s| s.optics.beta[k][k]).collect());
+    let phase: [Vec<f64>; 2] = [0, 1].map(|k| slices.iter().map(|s| s.optics.phase[k]).collect());
+    let eta1: Vec<f64> = slices.iter().map(|s| s.optics.eta[0]).collect();
+
+    // eta_2'' + (h^2 + k1) eta_2 = (h^2 + k1) eta_1 - h - b3 eta_1^2
+    let sources: Vec<f64> = slices
+        .iter()
+        .zip(eta1.iter())
+        .map(|(s, eta)| s.length * ((s.h * s.h + s.k1) * eta - s.h - s.b3 * eta * eta))
+        .collect();
+    let eta2 = periodic_response(&sources, &beta[0], &phase[0], mu[0]);
+
+    // The change in focusing is delta K_1 + delta^2 K_2 in each plane.
+    let mut first = [vec![], vec![]];
+    let mut second = [0.0; 2];
+    for (i, slice) in slices.iter().enumerate() {
+        let (ds, h, k1, b3, b4) = (slice.length, slice.h, slice.k1, slice.b3, slice.b4);
+        let (e1, e2) = (eta1[i], eta2[i]);
+        let focusing = h * h + k1;
+        let octupole = 3.0 * b4 * e1 * e1;
+        let k_x = [
+            2.0 * b3 * e1 - focusing,
+            focusing + 2.0 * b3 * (e2 - e1) + octupole,
+        ];
+        let k_y = [k1 - 2.0 * b3 * e1, -k1 - 2.0 * b3 * (e2 - e1) - octupole];
+        for (k, [k_1, k_2]) in [k_x, k_y].into_iter().enumerate() {
+            first[k].push(beta[k][i] * k_1 * ds);
+            second[k] += beta[k][i] * k_2 * ds;
+        }
+    }
+
+    let periodicity = line.periodicity as f64;
+    Some([0, 1].map(|k| {
+        let ring_mu = periodicity * mu[k];
+        periodicity * second[k] / (4.0 * PI)
+            - ring_double_sum(&first[k], &phase[k], mu[k], line.periodicity)
+                / (16.0 * PI * ring_mu.sin())
+    }))
+}
+
+/// Amplitude dependent tune shifts from first order octupole and second order sextupole
+/// perturbation theory.
+pub fn analytic_detuning(line: &Line) -> Option<Detuning> {
+    let modes = line.normal_modes.as_ref()?;
+    let mut retval = Detuning {
+        dqx_djx: 0.0,
+        dqx_djy: 0.0,
+        dqy_djy: 0.0,
+    };
+
+    for oct in ring_kicks(line, modes, 3) {
+        let [bx, by] = oct.beta;
+        retval.dqx_djx += 3.0 * oct.strength * bx * bx / (8.0 * PI);
+        retval.dqx_djy -= 3.0 * oct.strength * bx * by / (4.0 * PI);
+        retval.dqy_djy += 3.0 * oct.strength * by * by / (8.0 * PI);
+    }
+
+    let [qx, qy] = [modes.tunes[0], modes.tunes[1]];
+    let term = |phase: f64, tune: f64| (phase - PI * tune).cos() / (PI * tune).sin();
+    let sexts = ring_kicks(line, modes, 2);
+    for a in sexts.iter() {
+        for b in sexts.iter() {
+            // In terms of the MAD strengths K2 L = 2 b_3 L.
+            let m = 4.0 * a.strength * b.strength;
+            let (bxa, bxb, bya, byb) = (a.beta[0], b.beta[0], a.beta[1], b.beta[1]);
+            let px = (a.phase[0] - b.phase[0]).abs();
+            let py = (a.phase[1] - b.phase[1]).abs();
+            let t1 = term(px, qx);
+            let t3 = term(3.0 * px, 3.0 * qx);
+            let t_sum = term(px + 2.0 * py, qx + 2.0 * qy);
+            let t_diff = term(px - 2.0 * py, qx - 2.0 * qy);
+
+            retval.dqx_djx -= m * (bxa * bxb).powf(1.5) * (3.0 * t1 + t3) / (64.0 * PI);
+            retval.dqx_djy +=
+                m * (bxa * bxb).sqrt() * bya * (2.0 * bxb * t1 - byb * t_sum + byb * t_diff)
+                    / (32.0 * PI);
+            retval.dqy_djy -=
+                m * (bxa * bxb).sqrt() * bya * byb * (4.0 * t1 + t_sum + t_diff) / (64.0 * PI);
+        }
+    }
+
+    Some(retval)
+}
+
+fn fit_line(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len() as f64;
+    let (mean_x, mean_y) = (x.iter().sum::<f64>() / n, y.iter().sum::<f64>() / n);
+    let covariance: f64 = x
+        .iter()
+        .zip(y)
+        .map(|(a, b)| (a - mean_x) * (b - mean_y))
+        .sum();
+    let variance: f64 = x.iter().map(|a| (a - mean_x).powi(2)).sum();
+    covariance / variance
+}
+
+fn unwrap_tune(tune: f64, reference: f64) -> f64 {
+    tune - (tune - reference).round()
+}
+
+/// Amplitude dependent tune shifts from tracking, found by fitting the NAFF tunes of particles
+/// launched at the start of the line with increasing amplitude in each plane.  The other plane is
+/// given a small amplitude so that its tune can still be found.  The tracking is transverse, with
+/// the cavities off.
+pub fn tracked_detuning(
+    line: &Line,
+    settings: &DetuningSettings,
+) -> Result<Detuning, DetuningError> {
+    let tracker = Tracker::new(
+        line,
+        TrackingSettings {
+            cavities: false,
+            ..settings.tracking.clone()
+        },
+    );
+    let closed_orbit = find_closed_orbit_with(line, OrbitDimension::FourD, 0.0, |ele, coords| {
+        tracker.track_element(ele, coords);
+    })?;
+    let orbit = closed_orbit.orbit[0];
+    let twiss = match &line.normal_modes {
+        Some(modes) => {
+            let optics = &modes.optics[0];
+            [
+                optics.beta[0][0],
+                optics.alpha[0][0],
+                optics.beta[1][1],
+                optics.alpha[1][1],
+            ]
+        }
+        None => return Err(DetuningError::NoNormalModes),
+    };
+    let gamma_x = (1.0 + twiss[1] * twiss[1]) / twiss[0];
+    let gamma_y = (1.0 + twiss[3] * twiss[3]) / twiss[2];
+
+    let n = settings.n_amplitudes.max(2);
+    let scan = |plane: usize| -> Result<(Vec<f64>, Vec<[f64; 2]>), DetuningError> {
+        let mut actions = vec![];
+        let mut tunes = vec![];
+        for i in 1..=n {
+            let scale = i as f64 / n as f64;
+            let (x, y) = if plane == 0 {
+                (scale * settings.x_max, 1e-2 * settings.y_max)
+            } else {
+                (1e-2 * settings.x_max, scale * settings.y_max)
+            };
+            let mut particle = orbit;
+            particle[0] += x;
+            particle[2] += y;
+            let (mut history, lost) = tracker.track_turns_recorded(&mut particle, settings.n_turns);
+            if lost.is_some() {
+                return Err(DetuningError::ParticleLost);
+            }
+            for coords in history.iter_mut() {
+                for (c, o) in coords.iter_mut().zip(orbit.iter()) {
+                    *c -= o;
+                }
+            }
+            let q = naff_tunes(&history, twiss);
+            let reference = tunes.first().copied().unwrap_or(q);
+            tunes.push([
+                unwrap_tune(q[0], reference[0]),
+                unwrap_tune(q[1], reference[1]),
+            ]);
+            actions.push(if plane == 0 {
+                0.5 * gamma_x * x * x
+            } else {
+                0.5 * gamma_y * y * y
+            });
+        }
+        Ok((actions, tunes))
+    };
+
+    let (jx, x_scan) = scan(0)?;
+    let (jy, y_scan) = scan(1)?;
+    let column = |scan: &[[f64; 2]], k: usize| scan.iter().map(|q| q[k]).collect::<Vec<f64>>();
+
+    Ok(Detuning {
+        dqx_djx: fit_line(&jx, &column(&x_scan, 0)),
+        dqx_djy: fit_line(&jy, &column(&y_scan, 0)),
+        dqy_djy: fit_line(&jy, &column(&y_scan, 1)),
+    })
+}
+
+fn ring_tune(matrix: &Array2<f64>, plane: usize, periodicity: usize) -> f64 {
+    let i = 2 * plane;
+    let cos_mu = 0.5 * (matrix[[i, i]] + matrix[[i + 1, i + 1]]);
+    let mut mu = cos_mu.clamp(-1.0, 1.0).acos();
+    if matrix[[i, i + 1]] < 0.0 {
+        mu = 2.0 * PI - mu;
+    }
+    periodicity as f64 * mu / (2.0 * PI)
+}
+
+/// Tunes as a function of momentum offset from the linearised map about the off-momentum closed
+/// orbit found by tracking, with a cubic fit giving the chromaticities up to third order.  The fit
+/// needs at least four distinct momentum offsets.  The cavities are off, so that the momentum
+/// offset stays fixed.
+pub fn chromatic_tunes(
+    line: &Line,
+    deltas: &[f64],
+    tracking: &TrackingSettings,
+) -> Result<ChromaticTunes, DetuningError> {
+    let mut distinct = deltas.to_vec();
+    distinct.sort_by(f64::total_cmp);
+    distinct.dedup();
+    if distinct.len() < 4 {
+        return Err(DetuningError::SingularFit);
+    }
+    let tracker = Tracker::new(
+        line,
+        TrackingSettings {
+            cavities: false,
+            ..tracking.clone()
+        },
+    );
+    let mut tunes = Vec::with_capacity(deltas.len());
+    for &delta in deltas.iter() {
+        let closed_orbit =
+            find_closed_orbit_with(line, OrbitDimension::FourD, delta, |ele, coords| {
+                tracker.track_element(ele, coords);
+            })?;
+        tunes.push([
+            ring_tune(&closed_orbit.line_matrix, 0, line.periodicity),
+            ring_tune(&closed_orbit.line_matrix, 1, line.periodicity),
+        ]);
+    }
+
+    let mut coefficients = [[0.0; 4]; 2];
+    for (plane, coeffs) in coefficients.iter_mut().enumerate() {
+        let mut normal = Array2::<f64>::zeros((4, 4));
+        let mut rhs = Array1::<f64>::zeros(4);
+        for (delta, q) in deltas.iter().zip(tunes.iter()) {
+            for i in 0..4 {
+                rhs[i] += delta.powi(i as i32) * q[plane];
+                for j in 0..4 {
+                    normal[[i, j]] += delta.powi((i + j) as i32);
+                }
+            }
+        }
+        let fit = solve(&normal, &rhs).ok_or(DetuningError::SingularFit)?;
+        for (c, f) in coeffs.iter_mut().zip(fit.iter()) {
+            *c = *f;
+        }
+    }
+
+    Ok(ChromaticTunes {
+        deltas: deltas.to_vec(),
+        tunes,
+        coefficients,
+    })
+}
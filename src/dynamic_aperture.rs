@@ -0,0 +1,233 @@
+use crate::*;
+use rayon::prelude::*;
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DaError {
+    /// `DaSettings::element` is not an element of the line.
+    InvalidElement,
+    /// The closed orbit at one of the momentum offsets was not found.
+    ClosedOrbit,
+}
+
+impl Error for DaError {}
+
+impl fmt::Display for DaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidElement => write!(f, "Dynamic aperture element is not in the line"),
+            Self::ClosedOrbit => write!(f, "Closed orbit finder did not converge"),
+        }
+    }
+}
+
+impl From<ClosedOrbitError> for DaError {
+    fn from(_: ClosedOrbitError) -> Self {
+        Self::ClosedOrbit
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum DaGrid {
+    /// `n_x` points across [-x_max, x_max] and `n_y` points across (0, y_max].
+    Cartesian {
+        x_max: f64,
+        y_max: f64,
+        n_x: usize,
+        n_y: usize,
+    },
+    /// `n_angles` rays across [0, pi], each with `n_radii` points out to the ellipse with
+    /// semi-axes `x_max` and `y_max`.
+    Polar {
+        x_max: f64,
+        y_max: f64,
+        n_angles: usize,
+        n_radii: usize,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct DaSettings {
+    pub grid: DaGrid,
+    pub n_turns: usize,
+    pub deltas: Vec<f64>,
+    pub element: usize,
+    pub tracking: TrackingSettings,
+}
+
+#[derive(Debug, Clone)]
+pub struct DaParticle {
+    pub x: f64,
+    pub y: f64,
+    pub delta: f64,
+    pub loss_turn: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DaBoundary {
+    pub delta: f64,
+    pub points: Vec<[f64; 2]>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DynamicAperture {
+    pub particles: Vec<DaParticle>,
+    pub boundaries: Vec<DaBoundary>,
+}
+
+impl DynamicAperture {
+    /// Area enclosed by the boundary and the line y = 0 at each momentum offset.
+    pub fn areas(&self) -> Vec<f64> {
+        self.boundaries
+            .iter()
+            .map(|boundary| {
+                let (first, last) = match (boundary.points.first(), boundary.points.last()) {
+                    (Some(first), Some(last)) => (first, last),
+                    _ => return 0.0,
+                };
+                let mut polygon = vec![[first[0], 0.0]];
+                polygon.extend(boundary.points.iter().copied());
+                polygon.push([last[0], 0.0]);
+                let twice_area: f64 = polygon
+                    .iter()
+                    .zip(polygon.iter().cycle().skip(1))
+                    .map(|(a, b)| a[0] * b[1] - b[0] * a[1])
+                    .sum();
+                0.5 * twice_area.abs()
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn grid_points(grid: &DaGrid) -> Vec<[f64; 2]> {
+    grid_rays(grid)
+        .into_iter()
+        .flat_map(|ray| ray.points)
+        .collect()
+}
+
+struct Ray {
+    origin: [f64; 2],
+    points: Vec<[f64; 2]>,
+}
+
+fn grid_rays(grid: &DaGrid) -> Vec<Ray> {
+    match *grid {
+        DaGrid::Cartesian {
+            x_max,
+            y_max,
+            n_x,
+            n_y,
+        } => (0..n_x)
+            .map(|i| {
+                let x = if n_x == 1 {
+                    0.0
+                } else {
+                    -x_max + 2.0 * x_max * i as f64 / (n_x - 1) as f64
+                };
+                Ray {
+                    origin: [x, 0.0],
+                    points: (1..=n_y)
+                        .map(|j| [x, y_max * j as f64 / n_y as f64])
+                        .collect(),
+                }
+            })
+            .collect(),
+        DaGrid::Polar {
+            x_max,
+            y_max,
+            n_angles,
+            n_radii,
+        } => (0..n_angles)
+            .map(|i| {
+                let theta = if n_angles == 1 {
+                    PI / 2.0
+                } else {
+                    PI * i as f64 / (n_angles - 1) as f64
+                };
+                Ray {
+                    origin: [0.0, 0.0],
+                    points: (1..=n_radii)
+                        .map(|j| {
+                            let r = j as f64 / n_radii as f64;
+                            [r * x_max * theta.cos(), r * y_max * theta.sin()]
+                        })
+                        .collect(),
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Tracks the grid of initial amplitudes, about the closed orbit at the entrance of
+/// `settings.element`, for `settings.n_turns` turns of the full ring at each momentum offset.  The
+/// boundary at each momentum offset is made up of the last surviving point along each ray of the
+/// grid before the first loss.  The closed orbit is the transverse one at fixed momentum, found
+/// with the cavities off.
+pub fn dynamic_aperture(line: &Line, settings: &DaSettings) -> Result<DynamicAperture, DaError> {
+    if settings.element >= line.line.len() {
+        return Err(DaError::InvalidElement);
+    }
+    let tracker = Tracker::new(line, settings.tracking.clone());
+    let orbit_tracker = Tracker::new(
+        line,
+        TrackingSettings {
+            cavities: false,
+            ..settings.tracking.clone()
+        },
+    );
+    let rays = grid_rays(&settings.grid);
+
+    let mut particles = vec![];
+    let mut boundaries = vec![];
+    for &delta in settings.deltas.iter() {
+        let closed_orbit =
+            find_closed_orbit_with(line, OrbitDimension::FourD, delta, |ele, coords| {
+                orbit_tracker.track_element(ele, coords);
+            })?;
+        let orbit = closed_orbit.orbit[settings.element];
+
+        let initial: Vec<[f64; 2]> = rays.iter().flat_map(|ray| ray.points.clone()).collect();
+        let results: Vec<DaParticle> = initial
+            .par_iter()
+            .map(|&[x, y]| {
+                let mut particle = orbit;
+                particle[0] += x;
+                particle[2] += y;
+                DaParticle {
+                    x,
+                    y,
+                    delta,
+                    loss_turn: tracker.track_turns_from(
+                        settings.element,
+                        &mut particle,
+                        settings.n_turns,
+                    ),
+                }
+            })
+            .collect();
+
+        let mut offset = 0;
+        let mut points = vec![];
+        for ray in rays.iter() {
+            let ray_results = &results[offset..offset + ray.points.len()];
+            offset += ray.points.len();
+            points.push(
+                ray_results
+                    .iter()
+                    .take_while(|p| p.loss_turn.is_none())
+                    .last()
+                    .map_or(ray.origin, |p| [p.x, p.y]),
+            );
+        }
+        boundaries.push(DaBoundary { delta, points });
+        particles.extend(results);
+    }
+
+    Ok(DynamicAperture {
+        particles,
+        boundaries,
+    })
+}
@@ -1,9 +1,10 @@
+use crate::{Misalignment, Multipoles};
 use core::f64;
 use ndarray::Array2;
 use std::fmt::{Display, Error, Formatter};
 
 const ERADIUS_TIMES_RESTMASS: f64 = 0.959976365e-9;
-const C_Q: f64 = 3.83193864121903e-13;
+pub(crate) const C_Q: f64 = 3.83193864121903e-13;
 
 #[derive(Debug)]
 pub enum EleType {
@@ -26,6 +27,10 @@ pub struct Element {
     pub _voltage: f64,
     pub _harmonic: f64,
     pub _lag: f64,
+    pub kick: [f64; 2],
+    pub n_slices: usize,
+    pub misalignment: Misalignment,
+    pub multipoles: Multipoles,
     pub r_matrix: Array2<f64>,
     pub eta_prop_matrix: Array2<f64>,
 }
@@ -73,6 +78,10 @@ impl Default for Element {
             _voltage: 0.0,
             _harmonic: 0.0,
             _lag: 0.0,
+            kick: [0.0; 2],
+            n_slices: 1,
+            misalignment: Misalignment::default(),
+            multipoles: Multipoles::default(),
             eta_prop_matrix: make_eta_prop_matrix(&r_matrix),
             r_matrix,
         }
@@ -87,11 +96,88 @@ impl Element {
             self.length / self.k[0]
         }
     }
+
+    /// Transports `coords` through the linear transfer matrix of the element, including any
+    /// misalignment.  Corrector kicks are applied as a thin kick in the middle of the element,
+    /// treating the second half as a drift.
+    pub fn transport(&self, coords: &mut [f64; 6]) {
+        let misaligned = !self.misalignment.is_zero();
+        if misaligned {
+            self.shift_in(coords);
+        }
+        let mut retval = [0.0; 6];
+        for (i, val) in retval.iter_mut().enumerate() {
+            *val = (0..6).map(|j| self.r_matrix[[i, j]] * coords[j]).sum();
+        }
+        retval[0] += self.kick[0] * self.length / 2.0;
+        retval[1] += self.kick[0];
+        retval[2] += self.kick[1] * self.length / 2.0;
+        retval[3] += self.kick[1];
+        if let Some(kick) = self.multipole_orbit_kick() {
+            retval[0] += kick[0] * self.length / 2.0;
+            retval[1] += kick[0];
+            retval[2] += kick[1] * self.length / 2.0;
+            retval[3] += kick[1];
+        }
+        if misaligned {
+            self.roll_error_kick(&mut retval);
+            self.shift_out(&mut retval);
+        }
+        *coords = retval;
+    }
+
+    /// Recomputes the transfer matrices after a change of the length, the strengths in `k`, the
+    /// misalignment or the multipoles.  The RF focusing of a cavity is kept.
+    pub fn update_matrices(&mut self) {
+        let rf_focusing = self.r_matrix[[5, 4]];
+        self.r_matrix = self.magnet_matrix(self.length);
+        if matches!(element_type(self), EleType::EleTypeCav) {
+            self.r_matrix[[5, 4]] = rf_focusing;
+        }
+        self.eta_prop_matrix = make_eta_prop_matrix(&self.r_matrix);
+    }
+
+    /// Transfer matrix of the first `length` of the element.  Any RF focusing is scaled with the
+    /// fraction of the element.
+    pub fn partial_matrix(&self, length: f64) -> Array2<f64> {
+        if self.length == 0.0 {
+            return self.r_matrix.clone();
+        }
+        let mut retval = self.magnet_matrix(length);
+        retval[[5, 4]] = length / self.length * self.r_matrix[[5, 4]];
+        retval
+    }
+
+    // Transfer matrix through `length` of the magnet, with any gradient fed down from higher
+    // multipoles as a thin lens in the middle, rolled into the reference frame.
+    fn magnet_matrix(&self, length: f64) -> Array2<f64> {
+        let fraction = if self.length == 0.0 {
+            1.0
+        } else {
+            length / self.length
+        };
+        let half = |l: f64| make_sbend(self.name.clone(), l, fraction * self.k[0] / 2.0, self.k[1]);
+        let slice_length = if self.length == 0.0 { 1.0 } else { length };
+        let matrix = match self.feed_down_matrix(slice_length) {
+            Some(lens) if length > 0.0 => {
+                let half = half(length / 2.0).r_matrix;
+                half.dot(&lens).dot(&half)
+            }
+            Some(lens) => lens,
+            None => make_sbend(self.name.clone(), length, fraction * self.k[0], self.k[1]).r_matrix,
+        };
+        self.roll_into_reference(matrix)
+    }
 }
 
 pub fn element_type(ele: &Element) -> EleType {
     if ele._voltage != 0.0 || ele._harmonic != 0.0 || ele._lag != 0.0 {
         EleType::EleTypeCav
+    } else if !ele.multipoles.is_zero() && ele.k == [0.0; 4] {
+        EleType::EleTypeMult
+    } else if ele.length == 0.0 && (ele.k[2] != 0.0 || ele.k[3] != 0.0) {
+        // A thin sextupole or octupole, with integrated strengths in k.
+        EleType::EleTypeMult
     } else if ele.length == 0.0 {
         EleType::EleTypeMarker
     } else if ele.k[0] == 0.0 && ele.k[1] == 0.0 && ele.k[2] == 0.0 && ele.k[3] == 0.0 {
@@ -256,7 +342,11 @@ pub fn make_sbend(name: String, length: f64, angle: f64, k1: f64) -> Element {
 
     if omega_x_sqr == 0.0 {
         r_matrix[[0, 1]] = length;
-        r_matrix[[4, 0]] = h * length;
+        r_matrix[[0, 5]] = h * length.powi(2) / 2.0;
+        r_matrix[[1, 5]] = h * length;
+        r_matrix[[4, 0]] = r_matrix[[1, 5]];
+        r_matrix[[4, 1]] = r_matrix[[0, 5]];
+        r_matrix[[4, 5]] = h.powi(2) * length.powi(3) / 6.0;
     } else if omega_x_sqr > 0.0 {
         r_matrix[[0, 0]] = omega_x_l.cos();
         r_matrix[[0, 1]] = omega_x_l.sin() / omega_x;
@@ -266,7 +356,7 @@ pub fn make_sbend(name: String, length: f64, angle: f64, k1: f64) -> Element {
         r_matrix[[1, 5]] = (h / omega_x) * omega_x_l.sin();
         r_matrix[[4, 0]] = r_matrix[[1, 5]];
         r_matrix[[4, 1]] = r_matrix[[0, 5]];
-        r_matrix[[4, 5]] = -h.powi(2) * (omega_x_l - omega_x_l.sin()) / omega_x.powi(3);
+        r_matrix[[4, 5]] = h.powi(2) * (omega_x_l - omega_x_l.sin()) / omega_x.powi(3);
     } else {
         r_matrix[[0, 0]] = omega_x_l.cosh();
         r_matrix[[0, 1]] = omega_x_l.sinh() / omega_x;
@@ -280,8 +370,7 @@ pub fn make_sbend(name: String, length: f64, angle: f64, k1: f64) -> Element {
     }
 
     if omega_y_sqr == 0.0 {
-        r_matrix[[0, 1]] = length;
-        r_matrix[[4, 0]] = h * length;
+        r_matrix[[2, 3]] = length;
     } else if omega_y_sqr < 0.0 {
         r_matrix[[2, 2]] = omega_y_l.cos();
         r_matrix[[2, 3]] = omega_y_l.sin() / omega_y;
@@ -326,7 +415,7 @@ pub fn get_line_matrix(line: &[Element]) -> Array2<f64> {
     let mut retval: Array2<f64> = Array2::eye(6);
 
     for ele in line {
-        retval = retval.dot(&ele.r_matrix);
+        retval = ele.r_matrix.dot(&retval);
     }
 
     retval
@@ -373,8 +462,8 @@ fn fmt_f64(num: f64, width: usize, precision: usize, exp_pad: usize) -> String {
     // Safe to `unwrap` as `num` is guaranteed to contain `'e'`
     let exp = num.split_off(num.find('e').unwrap());
 
-    let (sign, exp) = if exp.starts_with("e-") {
-        ('-', &exp[2..])
+    let (sign, exp) = if let Some(stripped) = exp.strip_prefix("e-") {
+        ('-', stripped)
     } else {
         ('+', &exp[1..])
     };
@@ -0,0 +1,158 @@
+use crate::element::C_Q;
+use crate::*;
+use ndarray::{Array1, Array2, s};
+use num_complex::Complex64;
+
+const N_SLICES: usize = 16;
+
+/// Equilibrium emittances of the two transverse normal modes of a coupled ring, from the
+/// radiation integrals of each mode.  Coupling and vertical dispersion come from the linear
+/// transfer matrices, so rolled elements and skew gradients are included.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModeEmittances {
+    pub emittances: [f64; 2],
+    pub damping_partitions: [f64; 2],
+    /// Radiation integrals I4 and I5 of each mode for one cell.
+    pub i4: [f64; 2],
+    pub i5: [f64; 2],
+}
+
+impl ModeEmittances {
+    /// Ratio of the emittance of the vertical mode to that of the horizontal one.
+    pub fn ratio(&self) -> f64 {
+        self.emittances[1] / self.emittances[0]
+    }
+}
+
+// Integrands of I4 and I5 of each mode at a point of a bend with curvature `h` and gradient `k1`.
+// The dispersion is split between the modes by the amplitude a_k = -i E_k^† S eta, giving the
+// mode's share of the horizontal dispersion, 2 Re(a_k E_k,x), and its curly H, 2 |a_k|^2.
+fn integrands(vecs: &[Array1<Complex64>; 2], eta: &Array1<f64>, h: f64, k1: f64) -> [[f64; 2]; 2] {
+    let s_eta = symplectic_form(4).dot(eta);
+    std::array::from_fn(|k| {
+        let projection: Complex64 = vecs[k]
+            .iter()
+            .zip(s_eta.iter())
+            .map(|(v, x)| v.conj() * x)
+            .sum();
+        let amplitude = -Complex64::i() * projection;
+        let eta_x = 2.0 * (amplitude * vecs[k][0]).re;
+        [
+            h * (h * h + 2.0 * k1) * eta_x,
+            h.abs().powi(3) * 2.0 * amplitude.norm_sqr(),
+        ]
+    })
+}
+
+fn transverse(matrix: &Array2<f64>) -> (Array2<Complex64>, Array2<f64>, Array1<f64>) {
+    let m4 = matrix.slice(s![0..4, 0..4]).to_owned();
+    (
+        m4.mapv(|x| Complex64::new(x, 0.0)),
+        m4,
+        matrix.slice(s![0..4, 5]).to_owned(),
+    )
+}
+
+/// Emittances of the two transverse normal modes, or `None` if the transverse motion is
+/// unstable.  The bends are integrated through in slices.
+pub fn mode_emittances(line: &Line) -> Option<ModeEmittances> {
+    let (_, m4, dispersion) = transverse(&line.line_matrix);
+    let modes = symplectic_eigenmodes(&m4)?;
+    let mut vecs: [Array1<Complex64>; 2] = [modes[0].1.clone(), modes[1].1.clone()];
+    let mut eta = solve(&(Array2::eye(4) - &m4), &dispersion)?;
+
+    let mut i4 = [0.0; 2];
+    let mut i5 = [0.0; 2];
+    for ele in line.line.iter() {
+        if ele.k[0] != 0.0 && ele.length != 0.0 {
+            let h = ele.k[0] / ele.length;
+            let step = ele.length / N_SLICES as f64;
+            for n in 0..=N_SLICES {
+                let (c_matrix, matrix, column) = transverse(&ele.partial_matrix(n as f64 * step));
+                let local_vecs = vecs.clone().map(|vec| c_matrix.dot(&vec));
+                let local_eta = matrix.dot(&eta) + column;
+                // Simpson's rule.
+                let weight = if n == 0 || n == N_SLICES {
+                    1.0
+                } else if n % 2 == 1 {
+                    4.0
+                } else {
+                    2.0
+                } * step
+                    / 3.0;
+                for (k, [f4, f5]) in integrands(&local_vecs, &local_eta, h, ele.k[1])
+                    .iter()
+                    .enumerate()
+                {
+                    i4[k] += weight * f4;
+                    i5[k] += weight * f5;
+                }
+            }
+        }
+        let (c_matrix, matrix, column) = transverse(&ele.r_matrix);
+        vecs = vecs.map(|vec| c_matrix.dot(&vec));
+        eta = matrix.dot(&eta) + column;
+    }
+
+    let i2 = synch_rad_integral_2(&line.line);
+    let damping_partitions = i4.map(|i4| 1.0 - i4 / i2);
+    let emittances =
+        std::array::from_fn(|k| C_Q * line.gamma0.powi(2) * i5[k] / (damping_partitions[k] * i2));
+    Some(ModeEmittances {
+        emittances,
+        damping_partitions,
+        i4,
+        i5,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct EmittanceTuning {
+    pub line: Line,
+    /// Skew gradient of each family, as `ElementParameter::Skew(2)`.
+    pub strengths: Vec<f64>,
+    pub emittances: ModeEmittances,
+    pub iterations: usize,
+}
+
+/// Sets the skew quadrupole gradient of the families matching `patterns`, within
+/// +/- `max_strength`, to bring the ratio of the mode emittances to `target_ratio`.
+pub fn tune_emittance_ratio(
+    line: &Line,
+    patterns: &[&str],
+    target_ratio: f64,
+    max_strength: f64,
+) -> Result<EmittanceTuning, MatchError> {
+    let skew = ElementParameter::Skew(2);
+    let mut initial = Vec::with_capacity(patterns.len());
+    for pattern in patterns.iter() {
+        let index = *line.find(pattern).first().ok_or(MatchError)?;
+        initial.push(line.line[index].parameter(skew));
+    }
+
+    let build = |values: &[f64]| {
+        let mut retval = line.clone();
+        for (pattern, value) in patterns.iter().zip(values.iter()) {
+            retval.set_family_parameter(pattern, skew, *value);
+        }
+        retval
+    };
+    // The ratio grows with the square of the coupling, so its square root is matched to keep
+    // the residual linear in the strengths and its slope finite when they start at zero.
+    let residuals = |values: &[f64]| {
+        let emittances = mode_emittances(&build(values))?;
+        Some(vec![
+            emittances.ratio().max(0.0).sqrt() - target_ratio.sqrt(),
+        ])
+    };
+    let bounds = vec![(-max_strength, max_strength); patterns.len()];
+    let fit = levenberg_marquardt(residuals, &initial, &bounds, 100).ok_or(MatchError)?;
+
+    let tuned = build(&fit.values);
+    Ok(EmittanceTuning {
+        emittances: mode_emittances(&tuned).ok_or(MatchError)?,
+        line: tuned,
+        strengths: fit.values,
+        iterations: fit.iterations,
+    })
+}
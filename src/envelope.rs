@@ -0,0 +1,168 @@
+use crate::element::C_Q;
+use crate::line::C;
+use crate::*;
+use ndarray::{Array1, Array2};
+use num_complex::Complex64;
+
+const CLASSICAL_ELECTRON_RADIUS: f64 = 2.8179403262e-15;
+const N_SLICES: usize = 8;
+
+/// Equilibrium beam found with the envelope method of Ohmi, Hirata and Oide, where the damping
+/// and quantum excitation of the radiation in each bend are added to the linear one-turn map and
+/// the beam matrix is the fixed point of Sigma = M Sigma M^T + D.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    /// Emittances of the three normal modes, ordered as in `NormalModes`.
+    pub emittances: [f64; 3],
+    /// Damping times of the three normal modes in seconds.
+    pub damping_times: [f64; 3],
+    /// Beam matrix at the entrance of each element of a cell, followed by the end of the cell.
+    pub sigma: Vec<Array2<f64>>,
+}
+
+impl Envelope {
+    /// RMS horizontal and vertical beam sizes at the entrance of each element of a cell,
+    /// followed by the end of the cell.
+    pub fn beam_sizes(&self) -> Vec<[f64; 2]> {
+        self.sigma
+            .iter()
+            .map(|sigma| [sigma[[0, 0]].sqrt(), sigma[[2, 2]].sqrt()])
+            .collect()
+    }
+
+    pub fn energy_spread(&self) -> f64 {
+        self.sigma[0][[5, 5]].sqrt()
+    }
+
+    pub fn bunch_length(&self) -> f64 {
+        self.sigma[0][[4, 4]].sqrt()
+    }
+}
+
+// Linear map of the radiation emitted over `length` of a bend with curvature `h` and gradient
+// `k1`, in the frame of the element.  Relative to the energy, the loss per unit length is
+// C_E (1 + delta)^2 |B|^2 (1 + h x), and the transverse momenta are reduced in proportion.
+fn radiation_map(h: f64, k1: f64, length: f64, c_e: f64) -> Array2<f64> {
+    let mut retval = Array2::eye(6);
+    let loss = c_e * h * h * length;
+    retval[[1, 1]] -= loss;
+    retval[[3, 3]] -= loss;
+    retval[[5, 5]] -= 2.0 * loss;
+    retval[[5, 0]] -= c_e * h * (h * h + 2.0 * k1) * length;
+    retval
+}
+
+// Transfer matrix of each element of the cell including the radiation, and the diffusion added
+// through it, referred to the exit of the element.
+fn radiating_elements(line: &Line) -> Option<Vec<(Array2<f64>, Array2<f64>)>> {
+    let gamma = line.gamma0;
+    let c_e = 2.0 / 3.0 * CLASSICAL_ELECTRON_RADIUS * gamma.powi(3);
+    let c_d = 4.0 / 3.0 * C_Q * CLASSICAL_ELECTRON_RADIUS * gamma.powi(5);
+
+    let mut retval = Vec::with_capacity(line.line.len());
+    for ele in line.line.iter() {
+        if ele.k[0] == 0.0 || ele.length == 0.0 {
+            retval.push((ele.r_matrix.clone(), Array2::zeros((6, 6))));
+            continue;
+        }
+        let h = ele.k[0] / ele.length;
+        let step = ele.length / N_SLICES as f64;
+        let roll = roll_matrix(ele.misalignment.roll);
+        let radiation = roll
+            .t()
+            .dot(&radiation_map(h, ele.k[1], step, c_e))
+            .dot(&roll);
+        let mut diffusion_kick = Array2::zeros((6, 6));
+        diffusion_kick[[5, 5]] = c_d * h.abs().powi(3) * step;
+        let diffusion_kick = roll.t().dot(&diffusion_kick).dot(&roll);
+
+        // The radiation of each slice is applied as a thin kick at its middle.
+        let mut matrix: Array2<f64> = Array2::eye(6);
+        let mut diffusion = Array2::zeros((6, 6));
+        let mut previous = Array2::eye(6);
+        for n in 0..N_SLICES {
+            let middle = ele.partial_matrix((n as f64 + 0.5) * step);
+            let end = ele.partial_matrix((n + 1) as f64 * step);
+            let first_half = middle.dot(&invert(&previous)?);
+            let second_half = end.dot(&invert(&middle)?);
+            for (piece, kick) in [
+                (&first_half, None),
+                (&radiation, Some(&diffusion_kick)),
+                (&second_half, None),
+            ] {
+                matrix = piece.dot(&matrix);
+                diffusion = piece.dot(&diffusion).dot(&piece.t());
+                if let Some(kick) = kick {
+                    diffusion += kick;
+                }
+            }
+            previous = end;
+        }
+        retval.push((matrix, diffusion));
+    }
+    Some(retval)
+}
+
+// Solves Sigma = M Sigma M^T + D as a linear system in the 36 elements of Sigma.
+fn solve_envelope(matrix: &Array2<f64>, diffusion: &Array2<f64>) -> Option<Array2<f64>> {
+    let mut system = Array2::eye(36);
+    for (i, j, k, l) in itertools::iproduct!(0..6, 0..6, 0..6, 0..6) {
+        system[[6 * i + j, 6 * k + l]] -= matrix[[i, k]] * matrix[[j, l]];
+    }
+    let rhs = Array1::from_iter(diffusion.iter().copied());
+    let solution = solve(&system, &rhs)?;
+    let sigma = Array2::from_shape_vec((6, 6), solution.to_vec()).ok()?;
+    Some((&sigma + &sigma.t()) / 2.0)
+}
+
+/// Equilibrium beam matrix of a ring with RF, or `None` if there is no longitudinal focusing or
+/// the motion is unstable.
+pub fn envelope(line: &Line) -> Option<Envelope> {
+    let modes = line.normal_modes.as_ref().filter(|m| m.dimension == 6)?;
+    let elements = radiating_elements(line)?;
+
+    let mut matrix: Array2<f64> = Array2::eye(6);
+    let mut diffusion = Array2::zeros((6, 6));
+    for (m, d) in elements.iter() {
+        matrix = m.dot(&matrix);
+        diffusion = m.dot(&diffusion).dot(&m.t()) + d;
+    }
+    let mut sigma = solve_envelope(&matrix, &diffusion)?;
+
+    // With z conjugate to -delta the symplectic form has the sign of its longitudinal block
+    // flipped, and mode k of the beam has emittance E_k^† S Sigma S^T E_k.
+    let mut s_matrix = symplectic_form(6);
+    s_matrix[[4, 5]] = -1.0;
+    s_matrix[[5, 4]] = 1.0;
+    let projected = s_matrix.dot(&sigma).dot(&s_matrix.t());
+    let projected = projected.mapv(|x| Complex64::new(x, 0.0));
+    let emittances = std::array::from_fn(|k| {
+        let vec = &modes.eigenvectors[k];
+        let conj = vec.mapv(|x| x.conj());
+        conj.dot(&projected.dot(vec)).re
+    });
+
+    // Each mode is matched to the damped eigenvalue nearest its undamped one.
+    let damped = eigenvalues(&matrix)?;
+    let revolution_period = line.total_length / C;
+    let damping_times = modes.eigenvalues.map(|undamped| {
+        let nearest = damped
+            .iter()
+            .min_by(|a, b| (*a - undamped).norm().total_cmp(&(*b - undamped).norm()))
+            .map_or(1.0, |lambda| lambda.norm());
+        -revolution_period / (line.periodicity as f64 * nearest.ln())
+    });
+
+    let mut sigmas = Vec::with_capacity(elements.len() + 1);
+    sigmas.push(sigma.clone());
+    for (m, d) in elements.iter() {
+        sigma = m.dot(&sigma).dot(&m.t()) + d;
+        sigmas.push(sigma.clone());
+    }
+
+    Some(Envelope {
+        emittances,
+        damping_times,
+        sigma: sigmas,
+    })
+}
@@ -0,0 +1,208 @@
+use crate::*;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ElementParameter {
+    Length,
+    /// Bending angle in radians.
+    Angle,
+    K1,
+    B3,
+    B4,
+    Voltage,
+    KickX,
+    KickY,
+    Dx,
+    Dy,
+    Ds,
+    Roll,
+    /// Normal multipole component b_n of the given order n, from 1 to `MAX_MULTIPOLE_ORDER`.
+    Normal(usize),
+    /// Skew multipole component a_n of the given order n, from 1 to `MAX_MULTIPOLE_ORDER`.
+    Skew(usize),
+}
+
+impl ElementParameter {
+    /// Whether the parameter exists, which fails only for multipoles of an order outside
+    /// 1..=`MAX_MULTIPOLE_ORDER`.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Self::Normal(n) | Self::Skew(n) => (1..=MAX_MULTIPOLE_ORDER).contains(n),
+            _ => true,
+        }
+    }
+}
+
+impl Element {
+    /// The value of a parameter, which is zero for a multipole of an invalid order.
+    pub fn parameter(&self, parameter: ElementParameter) -> f64 {
+        if !parameter.is_valid() {
+            return 0.0;
+        }
+        match parameter {
+            ElementParameter::Length => self.length,
+            ElementParameter::Angle => self.k[0],
+            ElementParameter::K1 => self.k[1],
+            ElementParameter::B3 => self.k[2],
+            ElementParameter::B4 => self.k[3],
+            ElementParameter::Voltage => self._voltage,
+            ElementParameter::KickX => self.kick[0],
+            ElementParameter::KickY => self.kick[1],
+            ElementParameter::Dx => self.misalignment.dx,
+            ElementParameter::Dy => self.misalignment.dy,
+            ElementParameter::Ds => self.misalignment.ds,
+            ElementParameter::Roll => self.misalignment.roll,
+            ElementParameter::Normal(n) => self.multipoles.normal[n - 1],
+            ElementParameter::Skew(n) => self.multipoles.skew[n - 1],
+        }
+    }
+
+    /// Sets a parameter and recomputes the transfer matrices.  A multipole of an invalid order is
+    /// ignored.
+    pub fn set_parameter(&mut self, parameter: ElementParameter, value: f64) {
+        if !parameter.is_valid() {
+            return;
+        }
+        match parameter {
+            ElementParameter::Length => self.length = value,
+            ElementParameter::Angle => self.k[0] = value,
+            ElementParameter::K1 => self.k[1] = value,
+            ElementParameter::B3 => self.k[2] = value,
+            ElementParameter::B4 => self.k[3] = value,
+            ElementParameter::Voltage => self._voltage = value,
+            ElementParameter::KickX => self.kick[0] = value,
+            ElementParameter::KickY => self.kick[1] = value,
+            ElementParameter::Dx => self.misalignment.dx = value,
+            ElementParameter::Dy => self.misalignment.dy = value,
+            ElementParameter::Ds => self.misalignment.ds = value,
+            ElementParameter::Roll => self.misalignment.roll = value,
+            ElementParameter::Normal(n) => self.multipoles.normal[n - 1] = value,
+            ElementParameter::Skew(n) => self.multipoles.skew[n - 1] = value,
+        }
+        self.update_matrices();
+    }
+}
+
+/// Matches `name` against a glob pattern, where `*` matches any sequence of characters, `?` any
+/// single character and `[abc]` or `[a-c]` any one of a set of characters.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    glob_match_from(&pattern, &name)
+}
+
+fn glob_match_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|i| glob_match_from(&pattern[1..], &name[i..])),
+        Some('?') => !name.is_empty() && glob_match_from(&pattern[1..], &name[1..]),
+        Some('[') => {
+            let Some(close) = pattern.iter().position(|&c| c == ']') else {
+                return name.first() == Some(&'[') && glob_match_from(&pattern[1..], &name[1..]);
+            };
+            let Some(&c) = name.first() else {
+                return false;
+            };
+            let set = &pattern[1..close];
+            let mut found = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == '-' {
+                    found |= set[i] <= c && c <= set[i + 2];
+                    i += 3;
+                } else {
+                    found |= set[i] == c;
+                    i += 1;
+                }
+            }
+            found && glob_match_from(&pattern[close + 1..], &name[1..])
+        }
+        Some(p) => name.first() == Some(p) && glob_match_from(&pattern[1..], &name[1..]),
+    }
+}
+
+impl Line {
+    /// Indices of the elements whose names match the glob `pattern`.
+    pub fn find(&self, pattern: &str) -> Vec<usize> {
+        self.line
+            .iter()
+            .enumerate()
+            .filter(|(_, ele)| glob_match(pattern, &ele.name))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// The indices of every instance of each element name in the line.
+    pub fn families(&self) -> BTreeMap<String, Vec<usize>> {
+        let mut retval: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (i, ele) in self.line.iter().enumerate() {
+            retval.entry(ele.name.clone()).or_default().push(i);
+        }
+        retval
+    }
+
+    /// Position of the entrance of every element, followed by the end of the line.
+    pub fn s_positions(&self) -> Vec<f64> {
+        let mut s = 0.0;
+        let mut retval = Vec::with_capacity(self.line.len() + 1);
+        retval.push(s);
+        for ele in self.line.iter() {
+            s += ele.length;
+            retval.push(s);
+        }
+        retval
+    }
+
+    /// Positions of the entrances of the elements matching the glob `pattern`.
+    pub fn find_s(&self, pattern: &str) -> Vec<f64> {
+        let s = self.s_positions();
+        self.find(pattern).iter().map(|&i| s[i]).collect()
+    }
+
+    /// Sets a parameter on every element matching the glob `pattern` and recomputes the line,
+    /// returning the number of elements changed, which is zero for an invalid parameter.
+    pub fn set_family_parameter(
+        &mut self,
+        pattern: &str,
+        parameter: ElementParameter,
+        value: f64,
+    ) -> usize {
+        if !parameter.is_valid() {
+            return 0;
+        }
+        self.modify_family(pattern, |ele| ele.set_parameter(parameter, value))
+    }
+
+    /// Multiplies a parameter of every element matching the glob `pattern` by `factor` and
+    /// recomputes the line, returning the number of elements changed, which is zero for an
+    /// invalid parameter.
+    pub fn scale_family_parameter(
+        &mut self,
+        pattern: &str,
+        parameter: ElementParameter,
+        factor: f64,
+    ) -> usize {
+        if !parameter.is_valid() {
+            return 0;
+        }
+        self.modify_family(pattern, |ele| {
+            ele.set_parameter(parameter, factor * ele.parameter(parameter))
+        })
+    }
+
+    fn modify_family<F>(&mut self, pattern: &str, modify: F) -> usize
+    where
+        F: Fn(&mut Element),
+    {
+        let indices = self.find(pattern);
+        if indices.is_empty() {
+            return 0;
+        }
+        let mut elements = self.line.clone();
+        for &i in indices.iter() {
+            modify(&mut elements[i]);
+        }
+        *self = Line::from_elements(elements, self.periodicity, self.energy);
+        indices.len()
+    }
+}
@@ -0,0 +1,136 @@
+use crate::*;
+use std::f64::consts::PI;
+
+/// Global survey coordinates in the convention of MAD, where Z is the initial beam direction, Y
+/// is vertical, theta is the azimuth in the horizontal plane, phi the elevation and psi the roll.
+/// A positive bending angle turns the beam towards negative X.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FloorPosition {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub theta: f64,
+    pub phi: f64,
+    pub psi: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct FloorPoint {
+    pub s: f64,
+    /// Index of the element around the ring whose entrance this is.
+    pub element: usize,
+    pub position: FloorPosition,
+}
+
+/// Difference between the position at the end of the ring and at its start.  Angles are wrapped
+/// to (-pi, pi], so all components vanish for a ring that closes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FloorClosure {
+    pub offset: [f64; 3],
+    pub angle: [f64; 3],
+}
+
+type Matrix3 = [[f64; 3]; 3];
+
+fn multiply(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut retval = [[0.0; 3]; 3];
+    for (i, row) in retval.iter_mut().enumerate() {
+        for (j, val) in row.iter_mut().enumerate() {
+            *val = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    retval
+}
+
+fn orientation(position: &FloorPosition) -> Matrix3 {
+    let (st, ct) = position.theta.sin_cos();
+    let (sp, cp) = position.phi.sin_cos();
+    let (ss, cs) = position.psi.sin_cos();
+    let theta = [[ct, 0.0, st], [0.0, 1.0, 0.0], [-st, 0.0, ct]];
+    let phi = [[1.0, 0.0, 0.0], [0.0, cp, sp], [0.0, -sp, cp]];
+    let psi = [[cs, -ss, 0.0], [ss, cs, 0.0], [0.0, 0.0, 1.0]];
+    multiply(&multiply(&theta, &phi), &psi)
+}
+
+fn position_from(v: [f64; 3], w: &Matrix3) -> FloorPosition {
+    FloorPosition {
+        x: v[0],
+        y: v[1],
+        z: v[2],
+        theta: w[0][2].atan2(w[2][2]),
+        phi: w[1][2].atan2(w[0][2].hypot(w[2][2])),
+        psi: w[1][0].atan2(w[1][1]),
+    }
+}
+
+/// Survey coordinates at the entrance of every element around the full ring, followed by the end
+/// of the ring, starting from `start`.
+pub fn floor_coordinates(line: &Line, start: FloorPosition) -> Vec<FloorPoint> {
+    let n_ring = line.line.len() * line.periodicity;
+    let mut v = [start.x, start.y, start.z];
+    let mut w = orientation(&start);
+    let mut s = 0.0;
+
+    let mut retval = Vec::with_capacity(n_ring + 1);
+    retval.push(FloorPoint {
+        s,
+        element: 0,
+        position: start,
+    });
+    for i in 0..n_ring {
+        let ele = &line.line[i % line.line.len()];
+        let angle = ele.k[0];
+        let (displacement, rotation) = if angle == 0.0 {
+            ([0.0, 0.0, ele.length], None)
+        } else {
+            let rho = ele.length / angle;
+            let (sa, ca) = angle.sin_cos();
+            (
+                [rho * (ca - 1.0), 0.0, rho * sa],
+                Some([[ca, 0.0, -sa], [0.0, 1.0, 0.0], [sa, 0.0, ca]]),
+            )
+        };
+
+        for (j, val) in v.iter_mut().enumerate() {
+            *val += (0..3).map(|k| w[j][k] * displacement[k]).sum::<f64>();
+        }
+        if let Some(rotation) = rotation {
+            w = multiply(&w, &rotation);
+        }
+        s += ele.length;
+
+        // The azimuth is kept continuous, so it changes by -2 pi around a full ring.
+        let mut position = position_from(v, &w);
+        let previous = retval[i].position.theta;
+        position.theta = previous + wrap_angle(position.theta - previous);
+        retval.push(FloorPoint {
+            s,
+            element: (i + 1) % n_ring,
+            position,
+        });
+    }
+    retval
+}
+
+fn wrap_angle(angle: f64) -> f64 {
+    let wrapped = angle.rem_euclid(2.0 * PI);
+    if wrapped > PI {
+        wrapped - 2.0 * PI
+    } else {
+        wrapped
+    }
+}
+
+/// How far the ring is from closing on itself when surveyed from `start`.
+pub fn floor_closure(line: &Line, start: FloorPosition) -> FloorClosure {
+    let points = floor_coordinates(line, start);
+    let end = points.last().map_or(start, |point| point.position);
+    FloorClosure {
+        offset: [end.x - start.x, end.y - start.y, end.z - start.z],
+        angle: [
+            wrap_angle(end.theta - start.theta),
+            wrap_angle(end.phi - start.phi),
+            wrap_angle(end.psi - start.psi),
+        ],
+    }
+}
@@ -0,0 +1,332 @@
+use crate::dynamic_aperture::grid_points;
+use crate::*;
+use ndarray::{Array1, Array2};
+use num_complex::Complex64;
+use rayon::prelude::*;
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt;
+
+const GOLDEN_RATIO: f64 = 0.618_033_988_749_894_9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FmaError {
+    /// `FmaSettings::element` is not an element of the line.
+    InvalidElement,
+    /// The closed orbit at the momentum offset was not found.
+    ClosedOrbit,
+}
+
+impl Error for FmaError {}
+
+impl fmt::Display for FmaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidElement => write!(f, "Frequency map element is not in the line"),
+            Self::ClosedOrbit => write!(f, "Closed orbit finder did not converge"),
+        }
+    }
+}
+
+impl From<ClosedOrbitError> for FmaError {
+    fn from(_: ClosedOrbitError) -> Self {
+        Self::ClosedOrbit
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FmaSettings {
+    pub grid: DaGrid,
+    /// Number of turns in each of the two windows.
+    pub n_turns: usize,
+    pub delta: f64,
+    pub element: usize,
+    pub tracking: TrackingSettings,
+}
+
+#[derive(Debug, Clone)]
+pub struct FmaTunes {
+    pub qx: f64,
+    pub qy: f64,
+    pub dqx: f64,
+    pub dqy: f64,
+    pub diffusion: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct FmaPoint {
+    pub x: f64,
+    pub y: f64,
+    pub tunes: Option<FmaTunes>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FrequencyMap {
+    pub points: Vec<FmaPoint>,
+}
+
+impl FrequencyMap {
+    /// The map as comma separated values, one line per initial condition.  Lost particles have
+    /// empty tune columns.
+    pub fn to_csv(&self) -> String {
+        let mut retval = String::from("x,y,qx,qy,dqx,dqy,diffusion\n");
+        for point in self.points.iter() {
+            match &point.tunes {
+                Some(t) => retval.push_str(&format!(
+                    "{:e},{:e},{:.10},{:.10},{:e},{:e},{:.4}\n",
+                    point.x, point.y, t.qx, t.qy, t.dqx, t.dqy, t.diffusion
+                )),
+                None => retval.push_str(&format!("{:e},{:e},,,,,\n", point.x, point.y)),
+            }
+        }
+        retval
+    }
+}
+
+const NAFF_LINES: usize = 4;
+const NAFF_REFINEMENTS: usize = 20;
+const NAFF_TOLERANCE: f64 = 1e-10;
+
+// Projection of a complex turn by turn signal onto the frequency `freq`, with a Hanning window
+// normalised so that a pure line gives its complex amplitude.
+fn hanning_projection(signal: &[Complex64], freq: f64) -> Complex64 {
+    let n = signal.len() as f64;
+    signal
+        .iter()
+        .enumerate()
+        .map(|(i, z)| {
+            let window = 1.0 - (2.0 * PI * i as f64 / n).cos();
+            z * Complex64::from_polar(window, -2.0 * PI * freq * i as f64)
+        })
+        .sum::<Complex64>()
+        / n
+}
+
+fn hanning_amplitude(signal: &[Complex64], freq: f64) -> f64 {
+    hanning_projection(signal, freq).norm()
+}
+
+// Refines the peak of the Hanning windowed spectrum within one bin of `coarse` with a golden
+// section search.
+fn refine_peak(signal: &[Complex64], coarse: f64) -> f64 {
+    let n = signal.len();
+    let (mut lo, mut hi) = (coarse - 1.0 / n as f64, coarse + 1.0 / n as f64);
+    let mut a = hi - GOLDEN_RATIO * (hi - lo);
+    let mut b = lo + GOLDEN_RATIO * (hi - lo);
+    let (mut fa, mut fb) = (hanning_amplitude(signal, a), hanning_amplitude(signal, b));
+    while hi - lo > 1e-12 {
+        if fa > fb {
+            hi = b;
+            b = a;
+            fb = fa;
+            a = hi - GOLDEN_RATIO * (hi - lo);
+            fa = hanning_amplitude(signal, a);
+        } else {
+            lo = a;
+            a = b;
+            fa = fb;
+            b = lo + GOLDEN_RATIO * (hi - lo);
+            fb = hanning_amplitude(signal, b);
+        }
+    }
+    0.5 * (lo + hi)
+}
+
+// The peak of the Hanning windowed spectrum, found on a coarse grid and then refined.
+fn peak_frequency(signal: &[Complex64]) -> f64 {
+    let n = signal.len();
+    let coarse = (0..n)
+        .map(|i| i as f64 / n as f64)
+        .max_by(|a, b| hanning_amplitude(signal, *a).total_cmp(&hanning_amplitude(signal, *b)))
+        .unwrap_or(0.0);
+    refine_peak(signal, coarse)
+}
+
+fn harmonic(freq: f64, i: usize) -> Complex64 {
+    Complex64::from_polar(1.0, 2.0 * PI * freq * i as f64)
+}
+
+// Complex amplitudes of the lines at `freqs` that best fit the signal under the Hanning window,
+// which makes the lines orthogonal as in the Gram-Schmidt step of NAFF.
+fn line_amplitudes(signal: &[Complex64], freqs: &[f64]) -> Option<Vec<Complex64>> {
+    let n = freqs.len();
+    let lines: Vec<Vec<Complex64>> = freqs
+        .iter()
+        .map(|f| (0..signal.len()).map(|i| harmonic(*f, i)).collect())
+        .collect();
+    let gram = Array2::from_shape_fn((n, n), |(j, k)| hanning_projection(&lines[k], freqs[j]));
+    let projections = Array1::from_shape_fn(n, |j| hanning_projection(signal, freqs[j]));
+    solve_complex(&gram, &projections).map(|a| a.to_vec())
+}
+
+// The signal with all but line `skip` subtracted.
+fn subtract_lines(
+    signal: &[Complex64],
+    lines: &[(f64, Complex64)],
+    skip: Option<usize>,
+) -> Vec<Complex64> {
+    let mut retval = signal.to_vec();
+    for (k, (f, a)) in lines.iter().enumerate() {
+        if Some(k) != skip {
+            for (i, z) in retval.iter_mut().enumerate() {
+                *z -= a * harmonic(*f, i);
+            }
+        }
+    }
+    retval
+}
+
+/// Frequencies, in [0, 1), and complex amplitudes of up to `n_lines` lines of a complex turn by
+/// turn signal, found by NAFF.  Each line is the peak of the spectrum once the lines found before
+/// it have been subtracted from the signal, with the amplitudes of all the lines fitted together.
+/// After each new line, the frequency of every line is refined with the others subtracted, so
+/// that nearby lines, such as those driven close to a resonance, do not pull the peaks.
+pub fn naff(signal: &[Complex64], n_lines: usize) -> Vec<(f64, Complex64)> {
+    let fit = |freqs: &[f64]| -> Option<Vec<(f64, Complex64)>> {
+        let amplitudes = line_amplitudes(signal, freqs)?;
+        Some(freqs.iter().copied().zip(amplitudes).collect())
+    };
+    let mut lines = vec![];
+    for _ in 0..n_lines {
+        let freq = peak_frequency(&subtract_lines(signal, &lines, None));
+        // Finding a line again means that what is left of the signal is noise.
+        if lines
+            .iter()
+            .any(|(f, _)| (f - freq).abs() < 0.5 / signal.len() as f64)
+        {
+            break;
+        }
+        let mut freqs: Vec<f64> = lines.iter().map(|(f, _)| *f).collect();
+        freqs.push(freq);
+        match fit(&freqs) {
+            Some(fitted) => lines = fitted,
+            None => break,
+        }
+        for _ in 0..NAFF_REFINEMENTS {
+            let freqs: Vec<f64> = (0..lines.len())
+                .map(|k| refine_peak(&subtract_lines(signal, &lines, Some(k)), lines[k].0))
+                .collect();
+            let change = freqs
+                .iter()
+                .zip(lines.iter())
+                .fold(0.0, |acc: f64, (f, (g, _))| acc.max((f - g).abs()));
+            match fit(&freqs) {
+                Some(fitted) => lines = fitted,
+                None => break,
+            }
+            if change < NAFF_TOLERANCE {
+                break;
+            }
+        }
+    }
+    lines
+        .into_iter()
+        .map(|(f, a)| (f.rem_euclid(1.0), a))
+        .collect()
+}
+
+/// The main frequency, in units of the sampling frequency and in [0, 1), of a complex turn by
+/// turn signal, as the strongest line found by `naff`.
+pub fn naff_tune(signal: &[Complex64]) -> f64 {
+    naff(signal, NAFF_LINES)
+        .into_iter()
+        .max_by(|a, b| a.1.norm().total_cmp(&b.1.norm()))
+        .map_or(0.0, |(f, _)| f)
+}
+
+/// Tunes of both transverse planes from turn by turn coordinates, using the normalised
+/// coordinates given by `twiss`, which holds (beta_x, alpha_x, beta_y, alpha_y).
+pub fn naff_tunes(history: &[Particle], twiss: [f64; 4]) -> [f64; 2] {
+    let signal = |plane: usize| -> Vec<Complex64> {
+        let (beta, alpha) = (twiss[2 * plane], twiss[2 * plane + 1]);
+        history
+            .iter()
+            .map(|p| {
+                let (u, pu) = (p[2 * plane], p[2 * plane + 1]);
+                Complex64::new(u, -(alpha * u + beta * pu)) / beta.sqrt()
+            })
+            .collect()
+    };
+    [naff_tune(&signal(0)), naff_tune(&signal(1))]
+}
+
+/// Frequency map analysis.  Each initial condition of the grid, taken about the closed orbit at
+/// `settings.element`, is tracked for two windows of `settings.n_turns` turns, the tunes of each
+/// window are found with `naff_tunes` and the diffusion is log10 of the tune change between them.
+/// The closed orbit is the transverse one at fixed momentum, found with the cavities off.
+pub fn frequency_map(line: &Line, settings: &FmaSettings) -> Result<FrequencyMap, FmaError> {
+    if settings.element >= line.line.len() {
+        return Err(FmaError::InvalidElement);
+    }
+    let tracker = Tracker::new(line, settings.tracking.clone());
+    let orbit_tracker = Tracker::new(
+        line,
+        TrackingSettings {
+            cavities: false,
+            ..settings.tracking.clone()
+        },
+    );
+    let closed_orbit = find_closed_orbit_with(
+        line,
+        OrbitDimension::FourD,
+        settings.delta,
+        |ele, coords| {
+            orbit_tracker.track_element(ele, coords);
+        },
+    )?;
+    let orbit = closed_orbit.orbit[settings.element];
+
+    let twiss = match &line.normal_modes {
+        Some(modes) => {
+            let optics = &modes.optics[settings.element];
+            [
+                optics.beta[0][0],
+                optics.alpha[0][0],
+                optics.beta[1][1],
+                optics.alpha[1][1],
+            ]
+        }
+        None => [1.0, 0.0, 1.0, 0.0],
+    };
+
+    let points = grid_points(&settings.grid)
+        .par_iter()
+        .map(|&[x, y]| {
+            let mut particle = orbit;
+            particle[0] += x;
+            particle[2] += y;
+
+            let mut history = Vec::with_capacity(2 * settings.n_turns);
+            for _ in 0..(2 * settings.n_turns) {
+                let mut deviation = particle;
+                for (d, o) in deviation.iter_mut().zip(orbit.iter()) {
+                    *d -= o;
+                }
+                history.push(deviation);
+                if tracker
+                    .track_turns_from(settings.element, &mut particle, 1)
+                    .is_some()
+                {
+                    return FmaPoint { x, y, tunes: None };
+                }
+            }
+
+            let [qx1, qy1] = naff_tunes(&history[..settings.n_turns], twiss);
+            let [qx2, qy2] = naff_tunes(&history[settings.n_turns..], twiss);
+            let (dqx, dqy) = (qx2 - qx1, qy2 - qy1);
+            FmaPoint {
+                x,
+                y,
+                tunes: Some(FmaTunes {
+                    qx: qx1,
+                    qy: qy1,
+                    dqx,
+                    dqy,
+                    diffusion: (dqx * dqx + dqy * dqy).sqrt().max(1e-20).log10(),
+                }),
+            }
+        })
+        .collect();
+
+    Ok(FrequencyMap { points })
+}
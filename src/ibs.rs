@@ -0,0 +1,226 @@
+use crate::line::C;
+use crate::*;
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt;
+
+const CLASSICAL_ELECTRON_RADIUS: f64 = 2.8179403262e-15;
+const ELEMENTARY_CHARGE: f64 = 1.602176634e-19;
+const TOLERANCE: f64 = 1e-10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IbsError {
+    /// The optics table has fewer than two points or spans no length of the ring.
+    InvalidOptics,
+    /// An emittance, the energy spread or the bunch length is not positive.
+    InvalidBeam,
+    /// The ring has no stable 6D motion, or the iteration did not converge.
+    NoEquilibrium,
+}
+
+impl Error for IbsError {}
+
+impl fmt::Display for IbsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidOptics => write!(f, "The optics table must span part of the ring"),
+            Self::InvalidBeam => write!(f, "The beam parameters must be positive"),
+            Self::NoEquilibrium => write!(f, "No intrabeam scattering equilibrium found"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IbsSettings {
+    /// Charge of the bunch in coulombs.
+    pub bunch_charge: f64,
+    /// Vertical emittance at zero current.  A coupling ratio keeps the vertical emittance in
+    /// proportion to the horizontal one as it grows.
+    pub vertical: VerticalEmittance,
+    /// Coulomb logarithm, or `None` to take it from the beam around the ring.
+    pub coulomb_log: Option<f64>,
+    /// Number of points through each element at which the growth rates are evaluated.
+    pub n_slices: usize,
+    pub max_iterations: usize,
+}
+
+impl Default for IbsSettings {
+    fn default() -> Self {
+        Self {
+            bunch_charge: 1e-9,
+            vertical: VerticalEmittance::Coupling(0.01),
+            coulomb_log: None,
+            n_slices: 4,
+            max_iterations: 1000,
+        }
+    }
+}
+
+/// Growth rates of the energy spread and the square roots of the emittances, in 1/s.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct IbsGrowthRates {
+    pub longitudinal: f64,
+    pub horizontal: f64,
+    pub vertical: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BeamParameters {
+    pub emittances: [f64; 2],
+    pub e_spread: f64,
+    pub bunch_length: f64,
+}
+
+// Bane's fit to the auxiliary function of the high energy approximation, valid for
+// 0.01 < alpha < 1 and symmetric under alpha -> 1 / alpha.
+fn bane_g(alpha: f64) -> f64 {
+    let alpha = if alpha > 1.0 { 1.0 / alpha } else { alpha };
+    alpha.powf(0.021 - 0.044 * alpha.ln())
+}
+
+/// Intrabeam scattering growth rates in the high energy approximation of Bane, the limit of
+/// Bjorken-Mtingwa for beams that are cold transversely, averaged over the points of the optics
+/// table by the trapezoidal rule.  The table must span a non-zero length and the beam parameters
+/// must be positive.
+pub fn ibs_growth_rates(
+    line: &Line,
+    optics: &[OpticsPoint],
+    beam: &BeamParameters,
+    n_particles: f64,
+    coulomb_log: Option<f64>,
+) -> Result<IbsGrowthRates, IbsError> {
+    let length = match (optics.first(), optics.last()) {
+        (Some(first), Some(last)) if last.s > first.s => last.s - first.s,
+        _ => return Err(IbsError::InvalidOptics),
+    };
+    let [ex, ey] = beam.emittances;
+    if [ex, ey, beam.e_spread, beam.bunch_length]
+        .iter()
+        .any(|x| *x <= 0.0 || x.is_nan())
+    {
+        return Err(IbsError::InvalidBeam);
+    }
+    let (sigma_p, gamma) = (beam.e_spread, line.gamma0);
+    let curly_h = |o: &ModeOptics, k: usize| {
+        let (beta, alpha) = (o.beta[k][k], o.alpha[k][k]);
+        let (eta, eta_p) = (o.eta[2 * k], o.eta[2 * k + 1]);
+        ((1.0 + alpha * alpha) * eta * eta / beta)
+            + 2.0 * alpha * eta * eta_p
+            + beta * eta_p * eta_p
+    };
+
+    // Integrands of the longitudinal rate, the curly H functions and the Coulomb logarithm.
+    let integrands = |o: &ModeOptics| -> [f64; 4] {
+        let (beta_x, beta_y) = (o.beta[0][0], o.beta[1][1]);
+        let (hx, hy) = (curly_h(o, 0), curly_h(o, 1));
+        let sigma_h = 1.0 / (1.0 / (sigma_p * sigma_p) + hx / ex + hy / ey).sqrt();
+        let ratio = (beta_x * ey / (beta_y * ex)).sqrt();
+        let sigma_y = (ey * beta_y + (o.eta[2] * sigma_p).powi(2)).sqrt();
+        [
+            sigma_h * bane_g(ratio) / (beta_x * beta_y).powf(0.25),
+            hx,
+            hy,
+            (gamma * gamma * sigma_y * ex / (CLASSICAL_ELECTRON_RADIUS * beta_x)).ln(),
+        ]
+    };
+    let mut averages = [0.0; 4];
+    for pair in optics.windows(2) {
+        let ds = pair[1].s - pair[0].s;
+        let (a, b) = (integrands(&pair[0].optics), integrands(&pair[1].optics));
+        for (average, (a, b)) in averages.iter_mut().zip(a.iter().zip(b.iter())) {
+            *average += (a + b) / 2.0 * ds;
+        }
+    }
+    let [g_average, hx_average, hy_average, log_average] = averages.map(|a| a / length);
+
+    let log = coulomb_log.unwrap_or(log_average);
+    let longitudinal = CLASSICAL_ELECTRON_RADIUS.powi(2) * C * n_particles * log * g_average
+        / (16.0 * gamma.powi(3) * (ex * ey).powf(0.75) * beam.bunch_length * sigma_p.powi(3));
+    Ok(IbsGrowthRates {
+        longitudinal,
+        horizontal: sigma_p * sigma_p * hx_average / ex * longitudinal,
+        vertical: sigma_p * sigma_p * hy_average / ey * longitudinal,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct IbsEquilibrium {
+    pub zero_current: BeamParameters,
+    pub beam: BeamParameters,
+    pub growth_rates: IbsGrowthRates,
+    pub iterations: usize,
+}
+
+/// Equilibrium emittances, energy spread and bunch length of a bunch with intrabeam scattering,
+/// where e = e0 / (1 - tau / T) in each plane, with tau the radiation damping time and T the
+/// growth time.  The bunch length follows the energy spread, without potential
+/// well distortion, and the ring must have RF.
+pub fn ibs_equilibrium(line: &Line, settings: &IbsSettings) -> Result<IbsEquilibrium, IbsError> {
+    let modes = line.normal_modes.as_ref().ok_or(IbsError::NoEquilibrium)?;
+    if modes.dimension != 6 {
+        return Err(IbsError::NoEquilibrium);
+    }
+    let optics = optics_table(line, settings.n_slices).ok_or(IbsError::NoEquilibrium)?;
+    let n_particles = settings.bunch_charge / ELEMENTARY_CHARGE;
+
+    let zero_current = BeamParameters {
+        emittances: line.emittances(settings.vertical),
+        e_spread: line.e_spread,
+        bunch_length: line.mom_compact.abs() * line.total_length * line.e_spread
+            / (2.0 * PI * modes.tunes[2]),
+    };
+    if zero_current.emittances.iter().any(|e| *e <= 0.0) {
+        return Err(IbsError::InvalidBeam);
+    }
+    let tau_x = line.tau_x;
+    let tau_y = tau_x * line.j_x;
+    let tau_p = tau_y / (3.0 - line.j_x);
+
+    let mut beam = zero_current;
+    for iteration in 0..settings.max_iterations {
+        let rates = ibs_growth_rates(line, &optics, &beam, n_particles, settings.coulomb_log)?;
+        // The fixed point e = e0 + e tau / T, which is e = e0 / (1 - tau / T), stays positive
+        // while the beam is still far from equilibrium.
+        let ex = zero_current.emittances[0] + beam.emittances[0] * tau_x * rates.horizontal;
+        let ey = match settings.vertical {
+            VerticalEmittance::Coupling(kappa) => kappa * ex,
+            VerticalEmittance::Emittance(_) => {
+                zero_current.emittances[1] + beam.emittances[1] * tau_y * rates.vertical
+            }
+        };
+        let e_spread = (zero_current.e_spread.powi(2)
+            + beam.e_spread.powi(2) * tau_p * rates.longitudinal)
+            .sqrt();
+        let ratio = e_spread / zero_current.e_spread;
+
+        // Half steps towards the new values keep the iteration from oscillating at high charge.
+        let next = BeamParameters {
+            emittances: [
+                (beam.emittances[0] + ex) / 2.0,
+                (beam.emittances[1] + ey) / 2.0,
+            ],
+            e_spread: (beam.e_spread + zero_current.e_spread * ratio) / 2.0,
+            bunch_length: (beam.bunch_length + zero_current.bunch_length * ratio) / 2.0,
+        };
+        let change = (next.emittances[0] / beam.emittances[0] - 1.0)
+            .abs()
+            .max((next.emittances[1] / beam.emittances[1] - 1.0).abs())
+            .max((next.e_spread / beam.e_spread - 1.0).abs());
+        beam = next;
+        if change < TOLERANCE {
+            return Ok(IbsEquilibrium {
+                zero_current,
+                beam,
+                growth_rates: ibs_growth_rates(
+                    line,
+                    &optics,
+                    &beam,
+                    n_particles,
+                    settings.coulomb_log,
+                )?,
+                iterations: iteration + 1,
+            });
+        }
+    }
+    Err(IbsError::NoEquilibrium)
+}
@@ -0,0 +1,215 @@
+use crate::parser::{evaluate_expr, expression_variables, make_element};
+use crate::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+#[derive(Debug, Clone)]
+struct ElementDefinition {
+    typ: String,
+    params: HashMap<String, String>,
+    overrides: HashMap<String, f64>,
+}
+
+/// A lattice that keeps the variables and parameter expressions of its Tracy file, so that a
+/// variable or an element parameter can be changed after parsing.  Only the elements affected by
+/// a change are rebuilt, and the `Line` is recomputed the next time it is asked for.  Element
+/// parameters are evaluated with the final value of each variable.
+#[derive(Debug, Clone)]
+pub struct Lattice {
+    pub periodicity: usize,
+    pub energy: f64,
+    assignments: Vec<(String, String)>,
+    definitions: HashMap<String, ElementDefinition>,
+    overrides: HashMap<String, f64>,
+    variables: HashMap<String, f64>,
+    elements: Vec<Element>,
+    line: Option<Line>,
+}
+
+fn evaluate(expr: &str, variables: &HashMap<String, f64>) -> Result<f64, ParseError> {
+    let vars = variables
+        .iter()
+        .map(|(key, val)| (key.as_str(), *val))
+        .collect();
+    evaluate_expr(expr, &vars).map_err(|_| ParseError)
+}
+
+impl Lattice {
+    pub fn from_tracy_file(
+        file_path: &str,
+        periodicity: usize,
+        energy: f64,
+    ) -> Result<Self, ParseError> {
+        let file_contents = fs::read_to_string(file_path).map_err(|_| ParseError)?;
+        Self::from_tracy(&file_contents, periodicity, energy)
+    }
+
+    pub fn from_tracy(
+        file_contents: &str,
+        periodicity: usize,
+        energy: f64,
+    ) -> Result<Self, ParseError> {
+        let (elements, variables) = parse_tracy_lattice(file_contents, &HashMap::new())?;
+
+        let mut assignments = vec![];
+        let mut definitions = HashMap::new();
+        for statement in parse_tracy_file(file_contents) {
+            match statement {
+                Statement::Assignment(var, expr) => {
+                    assignments.push((var.to_string(), expr.to_string()));
+                }
+                Statement::Element(name, typ, params) => {
+                    definitions.insert(
+                        name.to_string(),
+                        ElementDefinition {
+                            typ: typ.to_string(),
+                            params: params
+                                .iter()
+                                .map(|(key, val)| (key.to_string(), val.to_string()))
+                                .collect(),
+                            overrides: HashMap::new(),
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            periodicity,
+            energy,
+            assignments,
+            definitions,
+            overrides: HashMap::new(),
+            variables,
+            elements,
+            line: None,
+        })
+    }
+
+    pub fn variables(&self) -> &HashMap<String, f64> {
+        &self.variables
+    }
+
+    pub fn variable(&self, name: &str) -> Option<f64> {
+        self.variables.get(name).copied()
+    }
+
+    pub fn elements(&self) -> &[Element] {
+        &self.elements
+    }
+
+    /// The line built from the current elements, recomputed only after a change.
+    pub fn line(&mut self) -> &Line {
+        self.line.get_or_insert_with(|| {
+            Line::from_elements(self.elements.clone(), self.periodicity, self.energy)
+        })
+    }
+
+    /// Fixes the value of a variable, re-evaluating the variables that depend on it and
+    /// rebuilding the elements that use any variable whose value changed.  The lattice is left as
+    /// it was if a variable or an element cannot be re-evaluated.
+    pub fn set_variable(&mut self, name: &str, value: f64) -> Result<(), ParseError> {
+        if !self.variables.contains_key(name) {
+            return Err(ParseError);
+        }
+        let mut overrides = self.overrides.clone();
+        overrides.insert(name.to_string(), value);
+
+        let mut variables = HashMap::new();
+        for (var, expr) in self.assignments.iter() {
+            let val = match overrides.get(var) {
+                Some(val) => *val,
+                None => evaluate(expr, &variables)?,
+            };
+            variables.insert(var.clone(), val);
+        }
+        let changed: HashSet<&String> = variables
+            .iter()
+            .filter(|(var, val)| self.variables.get(*var) != Some(val))
+            .map(|(var, _)| var)
+            .collect();
+
+        let affected: Vec<String> = self
+            .definitions
+            .iter()
+            .filter(|(_, def)| {
+                def.params.iter().any(|(key, expr)| {
+                    !def.overrides.contains_key(key)
+                        && expression_variables(expr)
+                            .iter()
+                            .any(|var| changed.contains(var))
+                })
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        let previous_variables = std::mem::replace(&mut self.variables, variables);
+        let previous_elements = self.elements.clone();
+        for name in affected.iter() {
+            if let Err(err) = self.rebuild_element(name) {
+                self.variables = previous_variables;
+                self.elements = previous_elements;
+                return Err(err);
+            }
+        }
+        self.overrides = overrides;
+        Ok(())
+    }
+
+    /// Sets a parameter of an element definition, such as `B_2` of a quadrupole, to a fixed value
+    /// in the units of the Tracy file.  The element is left as it was if the new value cannot be
+    /// used.
+    pub fn set_parameter(
+        &mut self,
+        element: &str,
+        parameter: &str,
+        value: f64,
+    ) -> Result<(), ParseError> {
+        let def = self.definitions.get_mut(element).ok_or(ParseError)?;
+        let previous = def.overrides.insert(parameter.to_string(), value);
+        let result = self.rebuild_element(element);
+        if result.is_err() {
+            let def = self.definitions.get_mut(element).ok_or(ParseError)?;
+            match previous {
+                Some(previous) => def.overrides.insert(parameter.to_string(), previous),
+                None => def.overrides.remove(parameter),
+            };
+        }
+        result
+    }
+
+    fn rebuild_element(&mut self, name: &str) -> Result<(), ParseError> {
+        let def = self.definitions.get(name).ok_or(ParseError)?;
+        let mut values = HashMap::new();
+        for (key, expr) in def.params.iter() {
+            let val = match def.overrides.get(key) {
+                Some(val) => *val,
+                None => evaluate(expr, &self.variables)?,
+            };
+            values.insert(key.as_str(), val);
+        }
+        for (key, val) in def.overrides.iter() {
+            values.insert(key.as_str(), *val);
+        }
+
+        let param = |key: &str, default: &str| {
+            values
+                .get(key)
+                .copied()
+                .unwrap_or_else(|| default.parse().unwrap())
+        };
+        let new_ele = make_element(name, &def.typ, &param)?;
+        for ele in self.elements.iter_mut().filter(|ele| ele.name == name) {
+            let (kick, misalignment, multipoles) = (ele.kick, ele.misalignment, ele.multipoles);
+            *ele = new_ele.clone();
+            ele.kick = kick;
+            if !misalignment.is_zero() || !multipoles.is_zero() {
+                ele.misalignment = misalignment;
+                ele.set_multipoles(multipoles);
+            }
+        }
+        self.line = None;
+        Ok(())
+    }
+}
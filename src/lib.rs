@@ -1,7 +1,55 @@
+mod beam_size;
+mod chromaticity;
+mod closed_orbit;
+mod detuning;
+mod dynamic_aperture;
 mod element;
+mod emittance;
+mod envelope;
+mod families;
+mod floor;
+mod frequency_map;
+mod ibs;
+mod lattice;
 mod line;
+mod linalg;
+mod loco;
+mod matching;
+mod misalignment;
+mod monte_carlo;
+mod multipoles;
+mod normal_modes;
+mod orbit_correction;
+mod orbit_response;
 mod parser;
+mod plot;
+mod rdt;
+mod tracking;
 
+pub use beam_size::*;
+pub use chromaticity::*;
+pub use closed_orbit::*;
+pub use detuning::*;
+pub use dynamic_aperture::*;
 pub use element::*;
+pub use emittance::*;
+pub use envelope::*;
+pub use families::*;
+pub use floor::*;
+pub use frequency_map::*;
+pub use ibs::*;
+pub use lattice::*;
 pub use line::*;
+pub use linalg::*;
+pub use loco::*;
+pub use matching::*;
+pub use misalignment::*;
+pub use monte_carlo::*;
+pub use multipoles::*;
+pub use normal_modes::*;
+pub use orbit_correction::*;
+pub use orbit_response::*;
 pub use parser::*;
+pub use plot::*;
+pub use rdt::*;
+pub use tracking::*;
@@ -0,0 +1,520 @@
+use ndarray::{Array1, Array2};
+use num_complex::Complex64;
+
+const MAX_QR_ITERATIONS: usize = 60;
+const MAX_JACOBI_SWEEPS: usize = 60;
+
+pub fn symplectic_form(dim: usize) -> Array2<f64> {
+    let mut retval = Array2::zeros((dim, dim));
+    for i in (0..dim).step_by(2) {
+        retval[[i, i + 1]] = 1.0;
+        retval[[i + 1, i]] = -1.0;
+    }
+    retval
+}
+
+pub fn solve(a: &Array2<f64>, b: &Array1<f64>) -> Option<Array1<f64>> {
+    let n = a.nrows();
+    let mut lu = a.clone();
+    let mut x = b.clone();
+
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&i, &j| lu[[i, col]].abs().total_cmp(&lu[[j, col]].abs()))
+            .unwrap();
+        if lu[[pivot, col]] == 0.0 {
+            return None;
+        }
+        if pivot != col {
+            for j in 0..n {
+                lu.swap([pivot, j], [col, j]);
+            }
+            x.swap(pivot, col);
+        }
+        for row in (col + 1)..n {
+            let factor = lu[[row, col]] / lu[[col, col]];
+            if factor == 0.0 {
+                continue;
+            }
+            for j in col..n {
+                lu[[row, j]] -= factor * lu[[col, j]];
+            }
+            x[row] -= factor * x[col];
+        }
+    }
+
+    for row in (0..n).rev() {
+        let mut sum = x[row];
+        for j in (row + 1)..n {
+            sum -= lu[[row, j]] * x[j];
+        }
+        x[row] = sum / lu[[row, row]];
+    }
+
+    Some(x)
+}
+
+pub fn invert(a: &Array2<f64>) -> Option<Array2<f64>> {
+    let n = a.nrows();
+    let mut retval = Array2::zeros((n, n));
+    for col in 0..n {
+        let mut unit = Array1::zeros(n);
+        unit[col] = 1.0;
+        retval.column_mut(col).assign(&solve(a, &unit)?);
+    }
+    Some(retval)
+}
+
+#[derive(Debug, Clone)]
+pub struct LeastSquares {
+    pub values: Vec<f64>,
+    /// Sum of the squared residuals at `values`.
+    pub cost: f64,
+    pub iterations: usize,
+}
+
+/// Minimises the sum of squares of `residuals` with the Levenberg-Marquardt method, keeping each
+/// parameter within its (lower, upper) bounds by projecting the steps onto them.  The residual
+/// function may return `None` for parameters where it cannot be evaluated, such as an unstable
+/// lattice, and such steps are rejected.
+pub fn levenberg_marquardt<F>(
+    residuals: F,
+    initial: &[f64],
+    bounds: &[(f64, f64)],
+    max_iterations: usize,
+) -> Option<LeastSquares>
+where
+    F: Fn(&[f64]) -> Option<Vec<f64>>,
+{
+    let clamp = |values: &mut [f64]| {
+        for (x, (lower, upper)) in values.iter_mut().zip(bounds.iter()) {
+            *x = x.clamp(*lower, *upper);
+        }
+    };
+    let cost = |r: &[f64]| r.iter().map(|x| x * x).sum::<f64>();
+
+    let n = initial.len();
+    let mut values = initial.to_vec();
+    clamp(&mut values);
+    let mut r = residuals(&values)?;
+    let mut current = cost(&r);
+    let mut lambda = 1e-3;
+
+    for iteration in 0..max_iterations {
+        if current < 1e-30 {
+            return Some(LeastSquares {
+                values,
+                cost: current,
+                iterations: iteration,
+            });
+        }
+
+        let mut jacobian = Array2::<f64>::zeros((r.len(), n));
+        for j in 0..n {
+            let step = 1e-7 * values[j].abs().max(1.0);
+            let mut shifted = values.clone();
+            let upper = bounds.get(j).map_or(f64::INFINITY, |b| b.1);
+            let sign = if values[j] + step > upper { -1.0 } else { 1.0 };
+            shifted[j] += sign * step;
+            let r_shifted = residuals(&shifted)?;
+            for i in 0..r.len() {
+                jacobian[[i, j]] = (r_shifted[i] - r[i]) / (sign * step);
+            }
+        }
+        let jtj = jacobian.t().dot(&jacobian);
+        let gradient = jacobian.t().dot(&Array1::from(r.clone()));
+
+        let mut accepted = false;
+        while lambda < 1e12 {
+            let mut system = jtj.clone();
+            for i in 0..n {
+                system[[i, i]] += lambda * jtj[[i, i]].max(1e-12);
+            }
+            if let Some(step) = solve(&system, &-&gradient) {
+                let mut trial: Vec<f64> =
+                    values.iter().zip(step.iter()).map(|(x, d)| x + d).collect();
+                clamp(&mut trial);
+                if let Some(r_trial) = residuals(&trial) {
+                    let trial_cost = cost(&r_trial);
+                    if trial_cost < current {
+                        let converged = current - trial_cost < 1e-14 * current;
+                        values = trial;
+                        r = r_trial;
+                        current = trial_cost;
+                        lambda = (lambda / 10.0).max(1e-12);
+                        accepted = !converged;
+                        break;
+                    }
+                }
+            }
+            lambda *= 10.0;
+        }
+        if !accepted {
+            return Some(LeastSquares {
+                values,
+                cost: current,
+                iterations: iteration + 1,
+            });
+        }
+    }
+
+    Some(LeastSquares {
+        values,
+        cost: current,
+        iterations: max_iterations,
+    })
+}
+
+pub fn solve_complex(a: &Array2<Complex64>, b: &Array1<Complex64>) -> Option<Array1<Complex64>> {
+    let n = a.nrows();
+    let mut lu = a.clone();
+    let mut x = b.clone();
+
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&i, &j| lu[[i, col]].norm().total_cmp(&lu[[j, col]].norm()))
+            .unwrap();
+        if lu[[pivot, col]].norm() == 0.0 {
+            return None;
+        }
+        if pivot != col {
+            for j in 0..n {
+                lu.swap([pivot, j], [col, j]);
+            }
+            x.swap(pivot, col);
+        }
+        for row in (col + 1)..n {
+            let factor = lu[[row, col]] / lu[[col, col]];
+            for j in col..n {
+                let upper = lu[[col, j]];
+                lu[[row, j]] -= factor * upper;
+            }
+            let upper = x[col];
+            x[row] -= factor * upper;
+        }
+    }
+
+    for row in (0..n).rev() {
+        let mut sum = x[row];
+        for j in (row + 1)..n {
+            sum -= lu[[row, j]] * x[j];
+        }
+        x[row] = sum / lu[[row, row]];
+    }
+
+    Some(x)
+}
+
+/// Thin singular value decomposition A = U diag(S) V^T, with the singular values in decreasing
+/// order, by one-sided Jacobi rotations.  U is m x k and V is n x k, where k = min(m, n).
+pub fn svd(a: &Array2<f64>) -> Option<(Array2<f64>, Array1<f64>, Array2<f64>)> {
+    let (m, n) = a.dim();
+    if m < n {
+        let (u, s, v) = svd(&a.t().to_owned())?;
+        return Some((v, s, u));
+    }
+
+    let mut u = a.clone();
+    let mut v: Array2<f64> = Array2::eye(n);
+    let mut converged = false;
+    for _ in 0..MAX_JACOBI_SWEEPS {
+        let mut rotated = false;
+        for p in 0..n {
+            for q in (p + 1)..n {
+                let alpha: f64 = u.column(p).iter().map(|x| x * x).sum();
+                let beta: f64 = u.column(q).iter().map(|x| x * x).sum();
+                let gamma: f64 = u.column(p).dot(&u.column(q));
+                if gamma.abs() <= 1e-15 * (alpha * beta).sqrt() || gamma == 0.0 {
+                    continue;
+                }
+                rotated = true;
+                let zeta = (beta - alpha) / (2.0 * gamma);
+                let t = zeta.signum() / (zeta.abs() + (1.0 + zeta * zeta).sqrt());
+                let c = 1.0 / (1.0 + t * t).sqrt();
+                let s = c * t;
+                for matrix in [&mut u, &mut v] {
+                    for i in 0..matrix.nrows() {
+                        let (x, y) = (matrix[[i, p]], matrix[[i, q]]);
+                        matrix[[i, p]] = c * x - s * y;
+                        matrix[[i, q]] = s * x + c * y;
+                    }
+                }
+            }
+        }
+        if !rotated {
+            converged = true;
+            break;
+        }
+    }
+    if !converged {
+        return None;
+    }
+
+    let norms: Vec<f64> = (0..n)
+        .map(|j| u.column(j).iter().map(|x| x * x).sum::<f64>().sqrt())
+        .collect();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| norms[j].total_cmp(&norms[i]));
+
+    let mut u_sorted = Array2::zeros((m, n));
+    let mut v_sorted = Array2::zeros((n, n));
+    let mut singular_values = Array1::zeros(n);
+    for (k, &j) in order.iter().enumerate() {
+        singular_values[k] = norms[j];
+        if norms[j] > 0.0 {
+            u_sorted.column_mut(k).assign(&(&u.column(j) / norms[j]));
+        }
+        v_sorted.column_mut(k).assign(&v.column(j));
+    }
+    Some((u_sorted, singular_values, v_sorted))
+}
+
+/// Eigenvalues of a general real matrix, by reduction to upper Hessenberg form followed by the
+/// shifted QR algorithm.
+pub fn eigenvalues(matrix: &Array2<f64>) -> Option<Vec<Complex64>> {
+    let mut a = matrix.clone();
+    reduce_to_hessenberg(&mut a);
+    hessenberg_qr(&mut a)
+}
+
+/// Eigenvalues and (unit-norm) eigenvectors of a general real matrix.  The eigenvectors are found
+/// by inverse iteration, so eigenvalues must be distinct.
+pub fn eigen(matrix: &Array2<f64>) -> Option<Vec<(Complex64, Array1<Complex64>)>> {
+    let n = matrix.nrows();
+    let scale = matrix.iter().fold(0.0f64, |acc, x| acc.max(x.abs())).max(1.0);
+    let complex_matrix = matrix.mapv(|x| Complex64::new(x, 0.0));
+
+    eigenvalues(matrix)?
+        .into_iter()
+        .map(|lambda| {
+            let shift = lambda + Complex64::new(scale * 1e-10, scale * 1e-10);
+            let mut shifted = complex_matrix.clone();
+            for i in 0..n {
+                shifted[[i, i]] -= shift;
+            }
+
+            let mut vec = Array1::from_elem(n, Complex64::new(1.0, 0.0));
+            for _ in 0..3 {
+                vec = solve_complex(&shifted, &vec)?;
+                let norm = vec.iter().map(|x| x.norm_sqr()).sum::<f64>().sqrt();
+                vec.mapv_inplace(|x| x / norm);
+            }
+            Some((lambda, vec))
+        })
+        .collect()
+}
+
+fn reduce_to_hessenberg(a: &mut Array2<f64>) {
+    let n = a.nrows();
+
+    for m in 1..n.saturating_sub(1) {
+        let mut x = 0.0f64;
+        let mut i = m;
+        for j in m..n {
+            if a[[j, m - 1]].abs() > x.abs() {
+                x = a[[j, m - 1]];
+                i = j;
+            }
+        }
+        if i != m {
+            for j in (m - 1)..n {
+                a.swap([i, j], [m, j]);
+            }
+            for j in 0..n {
+                a.swap([j, i], [j, m]);
+            }
+        }
+        if x != 0.0 {
+            for i in (m + 1)..n {
+                let mut y = a[[i, m - 1]];
+                if y != 0.0 {
+                    y /= x;
+                    a[[i, m - 1]] = y;
+                    for j in m..n {
+                        a[[i, j]] -= y * a[[m, j]];
+                    }
+                    for j in 0..n {
+                        a[[j, m]] += y * a[[j, i]];
+                    }
+                }
+            }
+        }
+    }
+
+    for i in 0..n {
+        for j in 0..i.saturating_sub(1) {
+            a[[i, j]] = 0.0;
+        }
+    }
+}
+
+fn hessenberg_qr(a: &mut Array2<f64>) -> Option<Vec<Complex64>> {
+    let n = a.nrows();
+    let mut wr = vec![0.0; n];
+    let mut wi = vec![0.0; n];
+
+    let mut anorm = 0.0;
+    for i in 0..n {
+        for j in i.saturating_sub(1)..n {
+            anorm += a[[i, j]].abs();
+        }
+    }
+
+    let mut nn = n as isize - 1;
+    let mut t = 0.0;
+    while nn >= 0 {
+        let mut its = 0;
+        loop {
+            let nu = nn as usize;
+            let mut l = nu;
+            while l >= 1 {
+                let mut s = a[[l - 1, l - 1]].abs() + a[[l, l]].abs();
+                if s == 0.0 {
+                    s = anorm;
+                }
+                if a[[l, l - 1]].abs() + s == s {
+                    a[[l, l - 1]] = 0.0;
+                    break;
+                }
+                l -= 1;
+            }
+
+            let mut x = a[[nu, nu]];
+            if l == nu {
+                wr[nu] = x + t;
+                wi[nu] = 0.0;
+                nn -= 1;
+                break;
+            }
+
+            let mut y = a[[nu - 1, nu - 1]];
+            let mut w = a[[nu, nu - 1]] * a[[nu - 1, nu]];
+            if l == nu - 1 {
+                let p = 0.5 * (y - x);
+                let q = p * p + w;
+                let mut z = q.abs().sqrt();
+                x += t;
+                if q >= 0.0 {
+                    z = p + z.copysign(p);
+                    wr[nu - 1] = x + z;
+                    wr[nu] = x + z;
+                    if z != 0.0 {
+                        wr[nu] = x - w / z;
+                    }
+                    wi[nu - 1] = 0.0;
+                    wi[nu] = 0.0;
+                } else {
+                    wr[nu - 1] = x + p;
+                    wr[nu] = x + p;
+                    wi[nu - 1] = -z;
+                    wi[nu] = z;
+                }
+                nn -= 2;
+                break;
+            }
+
+            if its == MAX_QR_ITERATIONS {
+                return None;
+            }
+            if its == 10 || its == 20 {
+                t += x;
+                for i in 0..=nu {
+                    a[[i, i]] -= x;
+                }
+                let s = a[[nu, nu - 1]].abs() + a[[nu - 1, nu - 2]].abs();
+                x = 0.75 * s;
+                y = x;
+                w = -0.4375 * s * s;
+            }
+            its += 1;
+
+            let mut m = nu - 2;
+            let (mut p, mut q, mut r);
+            loop {
+                let z = a[[m, m]];
+                let rr = x - z;
+                let ss = y - z;
+                p = (rr * ss - w) / a[[m + 1, m]] + a[[m, m + 1]];
+                q = a[[m + 1, m + 1]] - z - rr - ss;
+                r = a[[m + 2, m + 1]];
+                let s = p.abs() + q.abs() + r.abs();
+                p /= s;
+                q /= s;
+                r /= s;
+                if m == l {
+                    break;
+                }
+                let u = a[[m, m - 1]].abs() * (q.abs() + r.abs());
+                let v = p.abs() * (a[[m - 1, m - 1]].abs() + z.abs() + a[[m + 1, m + 1]].abs());
+                if u + v == v {
+                    break;
+                }
+                m -= 1;
+            }
+
+            for i in (m + 2)..=nu {
+                a[[i, i - 2]] = 0.0;
+                if i != m + 2 {
+                    a[[i, i - 3]] = 0.0;
+                }
+            }
+
+            for k in m..nu {
+                if k != m {
+                    p = a[[k, k - 1]];
+                    q = a[[k + 1, k - 1]];
+                    r = if k != nu - 1 { a[[k + 2, k - 1]] } else { 0.0 };
+                    x = p.abs() + q.abs() + r.abs();
+                    if x != 0.0 {
+                        p /= x;
+                        q /= x;
+                        r /= x;
+                    }
+                }
+                let s = (p * p + q * q + r * r).sqrt().copysign(p);
+                if s == 0.0 {
+                    continue;
+                }
+                if k == m {
+                    if l != m {
+                        a[[k, k - 1]] = -a[[k, k - 1]];
+                    }
+                } else {
+                    a[[k, k - 1]] = -s * x;
+                }
+                p += s;
+                x = p / s;
+                y = q / s;
+                let z = r / s;
+                q /= p;
+                r /= p;
+                for j in k..=nu {
+                    let mut p = a[[k, j]] + q * a[[k + 1, j]];
+                    if k != nu - 1 {
+                        p += r * a[[k + 2, j]];
+                        a[[k + 2, j]] -= p * z;
+                    }
+                    a[[k + 1, j]] -= p * y;
+                    a[[k, j]] -= p * x;
+                }
+                let mmin = if nu < k + 3 { nu } else { k + 3 };
+                for i in l..=mmin {
+                    let mut p = x * a[[i, k]] + y * a[[i, k + 1]];
+                    if k != nu - 1 {
+                        p += z * a[[i, k + 2]];
+                        a[[i, k + 2]] -= p * r;
+                    }
+                    a[[i, k + 1]] -= p * q;
+                    a[[i, k]] -= p;
+                }
+            }
+        }
+    }
+
+    Some(
+        wr.into_iter()
+            .zip(wi)
+            .map(|(re, im)| Complex64::new(re, im))
+            .collect(),
+    )
+}
@@ -3,8 +3,9 @@ use ndarray::{Array1, Array2, arr1};
 use std::f64::consts::PI;
 
 const ELECTRON_MASS: f64 = 510998.9499961642f64;
-const C: f64 = 299792458.0f64;
+pub(crate) const C: f64 = 299792458.0f64;
 
+#[derive(Debug, Clone)]
 pub struct Line {
     pub line: Vec<Element>,
     pub periodicity: usize,
@@ -28,22 +29,52 @@ pub struct Line {
     pub mom_compact: f64,
     pub nat_emitt_x: f64,
     pub e_spread: f64,
+    pub synch_phase: f64,
+    pub normal_modes: Option<NormalModes>,
 }
 
 impl Line {
     pub fn new(file_path: &str, periodicity: usize, energy: f64) -> Result<Self, ParseError> {
         let line = parse_lattice_from_tracy_file(file_path)?;
+        Ok(Line::from_elements(line, periodicity, energy))
+    }
+
+    pub fn from_elements(mut line: Vec<Element>, periodicity: usize, energy: f64) -> Self {
         let line_length = get_line_length(&line);
+
+        let e_loss_per_turn = e_loss_per_turn(
+            periodicity as f64 * synch_rad_integral_2(&line),
+            energy / ELECTRON_MASS,
+        );
+        let total_voltage = periodicity as f64 * line.iter().map(|ele| ele._voltage).sum::<f64>();
+        let synch_phase = if total_voltage == 0.0 {
+            0.0
+        } else {
+            (e_loss_per_turn / total_voltage).min(1.0).asin()
+        };
+
+        // The uncoupled optics and radiation integrals below are those with the momentum held
+        // constant, so they are found before the RF focusing is switched on.
+        for ele in line.iter_mut() {
+            ele.r_matrix[[5, 4]] = 0.0;
+        }
+        let rf_off_line_matrix = get_line_matrix(&line);
+        let rf_off_total_matrix = apply_matrix_n_times(&rf_off_line_matrix, periodicity);
+        for ele in line.iter_mut() {
+            ele.r_matrix[[5, 4]] =
+                rf_focusing(ele, energy, synch_phase, line_length * periodicity as f64);
+        }
+
         let line_matrix = get_line_matrix(&line);
         let total_matrix = apply_matrix_n_times(&line_matrix, periodicity);
         let line_angle = radians_to_degrees(get_bending_angle(&line));
         let total_angle = line_angle * periodicity as f64;
 
-        let phi_x = ((total_matrix[[0, 0]] + total_matrix[[1, 1]]) / 2.0).acos();
-        let phi_y = ((total_matrix[[2, 2]] + total_matrix[[3, 3]]) / 2.0).acos();
-        let beta_x = (total_matrix[[0, 1]] / phi_x.sin()).abs();
-        let beta_y = (total_matrix[[2, 3]] / phi_y.sin()).abs();
-        let eta_x = total_matrix[[0, 5]] / (1.0 - total_matrix[[0, 0]]);
+        let phi_x = ((rf_off_total_matrix[[0, 0]] + rf_off_total_matrix[[1, 1]]) / 2.0).acos();
+        let phi_y = ((rf_off_total_matrix[[2, 2]] + rf_off_total_matrix[[3, 3]]) / 2.0).acos();
+        let beta_x = (rf_off_total_matrix[[0, 1]] / phi_x.sin()).abs();
+        let beta_y = (rf_off_total_matrix[[2, 3]] / phi_y.sin()).abs();
+        let eta_x = rf_off_total_matrix[[0, 5]] / (1.0 - rf_off_total_matrix[[0, 0]]);
 
         let x_frac_tune = phi_x / (2.0 * PI);
         let y_frac_tune = phi_y / (2.0 * PI);
@@ -64,7 +95,7 @@ impl Line {
         let mut eta_x_vec: Vec<f64> = vec![];
 
         let mut synch_integrals: [f64; 5] = [
-            line_matrix[[4, 5]],
+            path_length_of_dispersion(&rf_off_line_matrix),
             synch_rad_integral_2(&line),
             synch_rad_integral_3(&line),
             0.0,
@@ -111,18 +142,11 @@ impl Line {
 
         let j_x = 1.0 - synch_integrals[3] / synch_integrals[1];
         let t_0 = (line_length * periodicity as f64) / C;
-        let tau_x = 2.0 * energy * t_0
-            / (j_x
-                * e_loss_per_turn(
-                    periodicity as f64 * synch_integrals[1],
-                    energy / ELECTRON_MASS,
-                ));
-        let e_loss_per_turn = e_loss_per_turn(
-            periodicity as f64 * synch_integrals[1],
-            energy / ELECTRON_MASS,
-        );
+        let tau_x = 2.0 * energy * t_0 / (j_x * e_loss_per_turn);
 
-        let mom_compact = total_matrix[[4, 5]] / (line_length * periodicity as f64);
+        // I_1 is the path length through the cell of the periodic dispersion orbit, so this is
+        // also the momentum compaction of the full ring.
+        let mom_compact = synch_integrals[0] / line_length;
 
         let nat_emitt_x = natural_emittance_x(
             synch_integrals[1],
@@ -138,7 +162,9 @@ impl Line {
             energy / ELECTRON_MASS,
         );
 
-        let retval = Line {
+        let normal_modes = normal_mode_analysis(&line, &line_matrix, periodicity);
+
+        Line {
             line,
             periodicity,
             energy,
@@ -161,8 +187,97 @@ impl Line {
             mom_compact,
             nat_emitt_x,
             e_spread,
-        };
+            synch_phase,
+            normal_modes,
+        }
+    }
+}
+
+/// An element of the ring, given either by its index or by its name.  Indices count elements
+/// around the full ring, so that element `i` of the second cell has index `line.line.len() + i`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElementLocation {
+    Index(usize),
+    /// The first instance of the name, which is always in the first cell.
+    Name(String),
+    /// Instance `n`, counting from zero, of the name around the full ring, so that with `m`
+    /// instances in each cell, instance `m` is the first one in the second cell.
+    Instance(String, usize),
+}
+
+impl From<usize> for ElementLocation {
+    fn from(index: usize) -> Self {
+        ElementLocation::Index(index)
+    }
+}
+
+impl From<&str> for ElementLocation {
+    fn from(name: &str) -> Self {
+        ElementLocation::Name(name.to_string())
+    }
+}
+
+impl From<String> for ElementLocation {
+    fn from(name: String) -> Self {
+        ElementLocation::Name(name)
+    }
+}
+
+impl Line {
+    fn ring_index(&self, location: &ElementLocation) -> Option<usize> {
+        match location {
+            ElementLocation::Index(index) => {
+                (*index < self.line.len() * self.periodicity).then_some(*index)
+            }
+            ElementLocation::Name(name) => self.line.iter().position(|ele| &ele.name == name),
+            ElementLocation::Instance(name, n) => (0..self.line.len() * self.periodicity)
+                .filter(|i| &self.line[i % self.line.len()].name == name)
+                .nth(*n),
+        }
+    }
+
+    /// Transfer matrix from the entrance of `from` to the entrance of `to`, passing through as
+    /// many cells as needed.  When `to` comes before `from` the matrix wraps around the end of
+    /// the ring, and equal locations give the identity.
+    pub fn transfer_matrix(
+        &self,
+        from: impl Into<ElementLocation>,
+        to: impl Into<ElementLocation>,
+    ) -> Option<Array2<f64>> {
+        let from = self.ring_index(&from.into())?;
+        let to = self.ring_index(&to.into())?;
+        let n_ring = self.line.len() * self.periodicity;
+        let n_elements = (to + n_ring - from) % n_ring;
 
-        Ok(retval)
+        let mut retval: Array2<f64> = Array2::eye(6);
+        for i in from..from + n_elements {
+            retval = self.line[i % self.line.len()].r_matrix.dot(&retval);
+        }
+        Some(retval)
     }
 }
+
+fn rf_focusing(ele: &Element, energy: f64, synch_phase: f64, circumference: f64) -> f64 {
+    if ele._voltage == 0.0 {
+        return 0.0;
+    }
+    -ele._voltage * rf_wavenumber(ele, circumference) * synch_phase.cos() / energy
+}
+
+pub(crate) fn rf_wavenumber(ele: &Element, circumference: f64) -> f64 {
+    let frequency = if ele._frequency != 0.0 {
+        ele._frequency
+    } else {
+        ele._harmonic * C / circumference
+    };
+    2.0 * PI * frequency / C
+}
+
+// Path length R56 + R51 eta + R52 eta' through `matrix` of the orbit with the periodic horizontal
+// dispersion (eta, eta') of the matrix, per unit momentum offset.
+fn path_length_of_dispersion(matrix: &Array2<f64>) -> f64 {
+    let det = (1.0 - matrix[[0, 0]]) * (1.0 - matrix[[1, 1]]) - matrix[[0, 1]] * matrix[[1, 0]];
+    let eta = ((1.0 - matrix[[1, 1]]) * matrix[[0, 5]] + matrix[[0, 1]] * matrix[[1, 5]]) / det;
+    let eta_p = (matrix[[1, 0]] * matrix[[0, 5]] + (1.0 - matrix[[0, 0]]) * matrix[[1, 5]]) / det;
+    matrix[[4, 5]] + matrix[[4, 0]] * eta + matrix[[4, 1]] * eta_p
+}
@@ -0,0 +1,262 @@
+use crate::orbit_response::linear_response;
+use crate::*;
+use ndarray::Array2;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+#[derive(Debug)]
+pub struct LocoError;
+
+impl Error for LocoError {}
+
+impl fmt::Display for LocoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LOCO fit failed")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LocoSettings {
+    /// BPMs and correctors of the measurement, and whether it was taken with the RF on.
+    pub orm: OrmSettings,
+    /// Glob pattern for the quadrupoles whose gradients are fitted, each instance around the
+    /// ring separately.
+    pub quadrupoles: String,
+    pub fit_bpm_gains: bool,
+    pub fit_bpm_rolls: bool,
+    pub fit_corrector_gains: bool,
+    pub max_iterations: usize,
+}
+
+impl Default for LocoSettings {
+    fn default() -> Self {
+        Self {
+            orm: OrmSettings::default(),
+            quadrupoles: "q*".to_string(),
+            fit_bpm_gains: true,
+            fit_bpm_rolls: true,
+            fit_corrector_gains: true,
+            max_iterations: 20,
+        }
+    }
+}
+
+/// Reads a response matrix from a text file with one row of whitespace separated values per
+/// line, laid out as by `coupled_orbit_response`.  Blank lines and anything after a `#` are
+/// ignored.
+pub fn read_response_matrix(file_path: &str) -> Result<Array2<f64>, LocoError> {
+    let file_contents = fs::read_to_string(file_path).map_err(|_| LocoError)?;
+    parse_response_matrix(&file_contents)
+}
+
+pub fn parse_response_matrix(file_contents: &str) -> Result<Array2<f64>, LocoError> {
+    let mut rows: Vec<Vec<f64>> = vec![];
+    for line in file_contents.lines() {
+        let data = line.split('#').next().unwrap_or("");
+        if data.trim().is_empty() {
+            continue;
+        }
+        let row = data
+            .split_whitespace()
+            .map(|value| value.parse::<f64>().map_err(|_| LocoError))
+            .collect::<Result<Vec<f64>, _>>()?;
+        if rows.first().is_some_and(|first| first.len() != row.len()) {
+            return Err(LocoError);
+        }
+        rows.push(row);
+    }
+    let n_cols = rows.first().map_or(0, Vec::len);
+    Array2::from_shape_vec((rows.len(), n_cols), rows.concat()).map_err(|_| LocoError)
+}
+
+/// The response matrix in the format read by `read_response_matrix`.
+pub fn format_response_matrix(matrix: &Array2<f64>) -> String {
+    matrix
+        .rows()
+        .into_iter()
+        .map(|row| {
+            let values: Vec<String> = row.iter().map(|x| format!("{x:.12e}")).collect();
+            values.join(" ") + "\n"
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct LocoResult {
+    /// Indices of the fitted quadrupoles around the ring and their fitted gradients.
+    pub quadrupoles: Vec<usize>,
+    pub k1: Vec<f64>,
+    /// Horizontal and vertical gain of each BPM, which are one when not fitted.
+    pub bpm_gains: Vec<[f64; 2]>,
+    /// Roll of each BPM in radians, positive from x towards y.
+    pub bpm_rolls: Vec<f64>,
+    /// Calibration of the horizontal and then the vertical correctors, as the ratio of the
+    /// actual kick to the nominal one.
+    pub corrector_gains: Vec<f64>,
+    /// RMS difference between the model and the measured response, in m/rad, before and after
+    /// the fit.
+    pub initial_rms: f64,
+    pub rms: f64,
+    pub iterations: usize,
+}
+
+impl LocoResult {
+    /// The ring, unrolled to a single cell, with the fitted gradients.
+    pub fn apply(&self, line: &Line) -> Line {
+        let mut elements = line.unrolled().line;
+        for (&index, &k1) in self.quadrupoles.iter().zip(self.k1.iter()) {
+            elements[index].set_parameter(ElementParameter::K1, k1);
+        }
+        Line::from_elements(elements, 1, line.energy)
+    }
+}
+
+// Position of each group of fit parameters in the vector of values.
+struct Layout {
+    n_quads: usize,
+    n_bpms: usize,
+    n_correctors: usize,
+    gains: Option<usize>,
+    rolls: Option<usize>,
+    corrector_gains: Option<usize>,
+    len: usize,
+}
+
+impl Layout {
+    fn new(n_quads: usize, n_bpms: usize, n_correctors: usize, settings: &LocoSettings) -> Self {
+        let mut len = n_quads;
+        let mut group = |fitted: bool, size: usize| {
+            fitted.then(|| {
+                len += size;
+                len - size
+            })
+        };
+        let gains = group(settings.fit_bpm_gains, 2 * n_bpms);
+        let rolls = group(settings.fit_bpm_rolls, n_bpms);
+        let corrector_gains = group(settings.fit_corrector_gains, n_correctors);
+        Self {
+            n_quads,
+            n_bpms,
+            n_correctors,
+            gains,
+            rolls,
+            corrector_gains,
+            len,
+        }
+    }
+
+    fn bpm_gains(&self, values: &[f64]) -> Vec<[f64; 2]> {
+        (0..self.n_bpms)
+            .map(|i| {
+                self.gains
+                    .map_or([1.0; 2], |g| [values[g + 2 * i], values[g + 2 * i + 1]])
+            })
+            .collect()
+    }
+
+    fn bpm_rolls(&self, values: &[f64]) -> Vec<f64> {
+        (0..self.n_bpms)
+            .map(|i| self.rolls.map_or(0.0, |r| values[r + i]))
+            .collect()
+    }
+
+    fn corrector_gains(&self, values: &[f64]) -> Vec<f64> {
+        (0..self.n_correctors)
+            .map(|j| self.corrector_gains.map_or(1.0, |c| values[c + j]))
+            .collect()
+    }
+
+    // The response of the ring as seen through the BPMs and correctors.
+    fn measured(&self, response: &Array2<f64>, values: &[f64]) -> Array2<f64> {
+        let gains = self.bpm_gains(values);
+        let rolls = self.bpm_rolls(values);
+        let corrector_gains = self.corrector_gains(values);
+        let mut retval = response.clone();
+        for (j, c) in corrector_gains.iter().enumerate() {
+            for i in 0..self.n_bpms {
+                let (x, y) = (response[[i, j]], response[[self.n_bpms + i, j]]);
+                let (s, cos) = rolls[i].sin_cos();
+                retval[[i, j]] = c * gains[i][0] * (cos * x + s * y);
+                retval[[self.n_bpms + i, j]] = c * gains[i][1] * (-s * x + cos * y);
+            }
+        }
+        retval
+    }
+}
+
+/// Fits the gradients of the quadrupoles, and optionally the gains and rolls of the BPMs and the
+/// calibration of the correctors, so that the response of the ring reproduces the measured
+/// response matrix, in the layout of `coupled_orbit_response`.
+pub fn loco_fit(
+    line: &Line,
+    measured: &Array2<f64>,
+    settings: &LocoSettings,
+) -> Result<LocoResult, LocoError> {
+    let ring = line.unrolled();
+    let bpms = ring.find(&settings.orm.bpms);
+    let correctors = [
+        ring.find(&settings.orm.h_correctors),
+        ring.find(&settings.orm.v_correctors),
+    ];
+    let quadrupoles = ring.find(&settings.quadrupoles);
+    let n_correctors = correctors[0].len() + correctors[1].len();
+    if measured.dim() != (2 * bpms.len(), n_correctors) {
+        return Err(LocoError);
+    }
+    let layout = Layout::new(quadrupoles.len(), bpms.len(), n_correctors, settings);
+
+    let mut initial = vec![1.0; layout.len];
+    for (value, &index) in initial.iter_mut().zip(quadrupoles.iter()) {
+        *value = ring.line[index].k[1];
+    }
+    if let Some(r) = layout.rolls {
+        initial[r..r + layout.n_bpms].fill(0.0);
+    }
+
+    // The lattice only changes with the gradients, so its response is kept between calls that
+    // vary the other parameters.
+    let cache: RefCell<Option<(Vec<f64>, Array2<f64>)>> = RefCell::new(None);
+    let residuals = |values: &[f64]| -> Option<Vec<f64>> {
+        let k1 = &values[..layout.n_quads];
+        let mut cache = cache.borrow_mut();
+        if cache.as_ref().is_none_or(|(cached, _)| cached != k1) {
+            let mut elements = ring.line.clone();
+            for (&index, &value) in quadrupoles.iter().zip(k1.iter()) {
+                elements[index].set_parameter(ElementParameter::K1, value);
+            }
+            let response = linear_response(
+                &elements,
+                &bpms,
+                &correctors,
+                settings.orm.fixed_path_length,
+            )?;
+            *cache = Some((k1.to_vec(), response));
+        }
+        let response = &cache.as_ref()?.1;
+        let model = layout.measured(response, values);
+        Some((model - measured).iter().copied().collect())
+    };
+
+    let rms = |cost: f64| (cost / measured.len().max(1) as f64).sqrt();
+    let initial_cost: f64 = residuals(&initial)
+        .ok_or(LocoError)?
+        .iter()
+        .map(|x| x * x)
+        .sum();
+    let bounds = vec![(f64::NEG_INFINITY, f64::INFINITY); layout.len];
+    let fit = levenberg_marquardt(residuals, &initial, &bounds, settings.max_iterations)
+        .ok_or(LocoError)?;
+
+    Ok(LocoResult {
+        k1: fit.values[..layout.n_quads].to_vec(),
+        bpm_gains: layout.bpm_gains(&fit.values),
+        bpm_rolls: layout.bpm_rolls(&fit.values),
+        corrector_gains: layout.corrector_gains(&fit.values),
+        initial_rms: rms(initial_cost),
+        rms: rms(fit.cost),
+        iterations: fit.iterations,
+        quadrupoles,
+    })
+}
@@ -1,4 +1,6 @@
 use rust_lattice_analysis::*;
+use std::fs;
+use std::path::Path;
 
 fn main() {
     let kinetic_energy: f64 = 3.0e9;
@@ -6,6 +8,77 @@ fn main() {
     let file_path = "lattices/max_4u_sp_jb_5.lat";
     let line: Line = Line::new(file_path, periodicity, kinetic_energy).unwrap();
 
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("plot") {
+        let directory = Path::new(args.get(2).map_or(".", String::as_str));
+        for (name, contents) in [
+            ("optics.svg", optics_svg(&line)),
+            ("floor_plan.svg", floor_plan_svg(&line)),
+        ] {
+            let path = directory.join(name);
+            match fs::write(&path, contents) {
+                Ok(()) => println!("Wrote {}", path.display()),
+                Err(err) => eprintln!("ERROR: Could not write {}: {err}", path.display()),
+            }
+        }
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("optics") {
+        let usage = "usage: optics <csv file> (coupling <ratio> | emittance <m.rad>)";
+        let value = args.get(4).and_then(|value| value.parse::<f64>().ok());
+        let vertical = match (args.get(3).map(String::as_str), value) {
+            (Some("coupling"), Some(ratio)) => VerticalEmittance::Coupling(ratio),
+            (Some("emittance"), Some(emittance)) => VerticalEmittance::Emittance(emittance),
+            _ => {
+                eprintln!("{usage}");
+                return;
+            }
+        };
+        let Some(path) = args.get(2).map(Path::new) else {
+            eprintln!("{usage}");
+            return;
+        };
+        let Some(contents) = optics_table_csv(&line, 10, vertical) else {
+            eprintln!("ERROR: The lattice has no stable optics");
+            return;
+        };
+        match fs::write(path, contents) {
+            Ok(()) => println!("Wrote {}", path.display()),
+            Err(err) => eprintln!("ERROR: Could not write {}: {err}", path.display()),
+        }
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("loco") {
+        let Some(measured_path) = args.get(2) else {
+            eprintln!("usage: loco <response matrix file>");
+            return;
+        };
+        let Ok(measured) = read_response_matrix(measured_path) else {
+            eprintln!("ERROR: Could not read a response matrix from {measured_path}");
+            return;
+        };
+        let result = match loco_fit(&line, &measured, &LocoSettings::default()) {
+            Ok(result) => result,
+            Err(err) => {
+                eprintln!("ERROR: {err}");
+                return;
+            }
+        };
+        println!(
+            "LOCO fit: RMS residual {:0.3e} -> {:0.3e} m/rad after {} iterations",
+            result.initial_rms, result.rms, result.iterations
+        );
+        let n_eles = line.line.len();
+        for (&index, k1) in result.quadrupoles.iter().zip(result.k1.iter()) {
+            let ele = &line.line[index % n_eles];
+            println!(
+                "{index:6} {:>12} K1 = {k1:+0.6} ({:+0.6})",
+                ele.name, ele.k[1]
+            );
+        }
+        return;
+    }
+
     println!();
     println!("Summary of the lattice defined in {file_path}");
     println!();
@@ -19,6 +92,12 @@ fn main() {
         "Total bending angle of the lattice: {:0.3} deg ({:0.3} deg for the line)",
         line.total_angle, line.line_angle
     );
+    let closure = floor_closure(&line, FloorPosition::default());
+    println!(
+        "Ring closure error: {:0.3e} m, {:0.3e} rad",
+        closure.offset.iter().map(|x| x * x).sum::<f64>().sqrt(),
+        closure.angle[0]
+    );
     println!();
     println!("Total matrix, R, for the line is:");
     print_matrix(&line.line_matrix);
@@ -57,6 +136,24 @@ fn main() {
     println!();
     println!("x fractional tune:    {:0.6}", line.x_frac_tune);
     println!("y fractional tune:    {:0.6}", line.y_frac_tune);
+    if let Some(modes) = &line.normal_modes {
+        println!(
+            "Normal mode tunes:    {:0.6}, {:0.6}, {:0.6} ({}D)",
+            modes.tunes[0], modes.tunes[1], modes.tunes[2], modes.dimension
+        );
+    }
+    if let Some(xi) = analytic_chromaticity(&line) {
+        println!("Chromaticity:         {:0.3}, {:0.3}", xi[0], xi[1]);
+    }
+    if let Some(xi2) = analytic_second_order_chromaticity(&line) {
+        println!("Second order chromaticity: {:0.3}, {:0.3}", xi2[0], xi2[1]);
+    }
+    if let Some(detuning) = analytic_detuning(&line) {
+        println!(
+            "dQx/dJx, dQx/dJy, dQy/dJy: {:0.3e}, {:0.3e}, {:0.3e}",
+            detuning.dqx_djx, detuning.dqx_djy, detuning.dqy_djy
+        );
+    }
     println!(
         "Energy loss per turn: {:0.3} keV",
         line.e_loss_per_turn / 1e3
@@ -68,5 +165,33 @@ fn main() {
         "Natural x emittance:  {:0.3} pm.rad",
         1e12 * line.nat_emitt_x
     );
+    if let Some(modes) = mode_emittances(&line) {
+        println!(
+            "Mode emittances:      {:0.3}, {:0.3} pm.rad",
+            1e12 * modes.emittances[0],
+            1e12 * modes.emittances[1]
+        );
+    }
     println!("Energy spread:        {:0.3e}", line.e_spread);
+    if let Ok(ibs) = ibs_equilibrium(&line, &IbsSettings::default()) {
+        println!(
+            "With IBS at 1 nC:     {:0.3} pm.rad, {:0.3e} energy spread, {:0.3} mm bunch length",
+            1e12 * ibs.beam.emittances[0],
+            ibs.beam.e_spread,
+            1e3 * ibs.beam.bunch_length
+        );
+    }
+    if let Some(envelope) = envelope(&line) {
+        println!(
+            "Envelope emittances:  {:0.3}, {:0.3} pm.rad, {:0.3e} m",
+            1e12 * envelope.emittances[0],
+            1e12 * envelope.emittances[1],
+            envelope.emittances[2]
+        );
+        println!(
+            "Envelope energy spread and bunch length: {:0.3e}, {:0.3} mm",
+            envelope.energy_spread(),
+            1e3 * envelope.bunch_length()
+        );
+    }
 }
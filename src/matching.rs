@@ -0,0 +1,188 @@
+use crate::*;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+const MAX_ITERATIONS: usize = 100;
+
+#[derive(Debug)]
+pub struct MatchError;
+
+impl Error for MatchError {}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Matching failed")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Knob {
+    /// `k[index]` of every element with the given name.
+    Element { name: String, index: usize },
+    /// A variable set in an `Assignment` statement of a Tracy lattice file.
+    Variable(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchVariable {
+    pub knob: Knob,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+/// Quantities that can be matched.  Optics functions are taken at the entrance of the first
+/// element with the given name, and the planes are numbered 0 for x and 1 for y.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchTarget {
+    FracTune(usize),
+    Beta { element: String, plane: usize },
+    Alpha { element: String, plane: usize },
+    Eta { element: String },
+    EtaPrime { element: String },
+    MomentumCompaction,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchConstraint {
+    pub target: MatchTarget,
+    pub value: f64,
+    pub weight: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchResult {
+    /// Final value of each variable, in the order they were given.
+    pub values: Vec<f64>,
+    pub line: Line,
+    /// Weighted sum of the squared deviations from the targets.
+    pub cost: f64,
+    pub iterations: usize,
+}
+
+/// Value of a match target for the line, if it can be evaluated.
+pub fn target_value(line: &Line, target: &MatchTarget) -> Option<f64> {
+    let modes = line.normal_modes.as_ref()?;
+    let optics_at = |name: &str| {
+        line.line
+            .iter()
+            .position(|ele| ele.name == name)
+            .map(|i| &modes.optics[i])
+    };
+    match target {
+        MatchTarget::FracTune(plane) => Some(modes.frac_tunes[*plane]),
+        MatchTarget::Beta { element, plane } => optics_at(element).map(|o| o.beta[*plane][*plane]),
+        MatchTarget::Alpha { element, plane } => {
+            optics_at(element).map(|o| o.alpha[*plane][*plane])
+        }
+        MatchTarget::Eta { element } => optics_at(element).map(|o| o.eta[0]),
+        MatchTarget::EtaPrime { element } => optics_at(element).map(|o| o.eta[1]),
+        MatchTarget::MomentumCompaction => Some(line.mom_compact),
+    }
+}
+
+pub(crate) fn set_element_knob(elements: &mut [Element], name: &str, index: usize, value: f64) {
+    for ele in elements.iter_mut().filter(|ele| ele.name == name) {
+        ele.k[index] = value;
+        ele.update_matrices();
+    }
+}
+
+fn run_match<F>(
+    build: F,
+    initial: &[f64],
+    variables: &[MatchVariable],
+    constraints: &[MatchConstraint],
+) -> Result<MatchResult, MatchError>
+where
+    F: Fn(&[f64]) -> Option<Line>,
+{
+    let residuals = |values: &[f64]| {
+        let line = build(values)?;
+        constraints
+            .iter()
+            .map(|c| target_value(&line, &c.target).map(|v| c.weight * (v - c.value)))
+            .collect::<Option<Vec<f64>>>()
+    };
+    let bounds: Vec<(f64, f64)> = variables.iter().map(|v| (v.lower, v.upper)).collect();
+
+    let fit = levenberg_marquardt(residuals, initial, &bounds, MAX_ITERATIONS).ok_or(MatchError)?;
+    Ok(MatchResult {
+        line: build(&fit.values).ok_or(MatchError)?,
+        values: fit.values,
+        cost: fit.cost,
+        iterations: fit.iterations,
+    })
+}
+
+/// Varies the strengths of named elements of the line to meet the constraints.  Only
+/// `Knob::Element` variables can be used.
+pub fn match_line(
+    line: &Line,
+    variables: &[MatchVariable],
+    constraints: &[MatchConstraint],
+) -> Result<MatchResult, MatchError> {
+    let mut initial = Vec::with_capacity(variables.len());
+    for variable in variables.iter() {
+        match &variable.knob {
+            Knob::Element { name, index } => {
+                let ele = line.line.iter().find(|ele| &ele.name == name);
+                initial.push(*ele.ok_or(MatchError)?.k.get(*index).ok_or(MatchError)?);
+            }
+            Knob::Variable(_) => return Err(MatchError),
+        }
+    }
+
+    let build = |values: &[f64]| {
+        let mut elements = line.line.clone();
+        for (variable, value) in variables.iter().zip(values.iter()) {
+            if let Knob::Element { name, index } = &variable.knob {
+                set_element_knob(&mut elements, name, *index, *value);
+            }
+        }
+        Some(Line::from_elements(elements, line.periodicity, line.energy))
+    };
+    run_match(build, &initial, variables, constraints)
+}
+
+/// Matches a lattice given as the contents of a Tracy file, where the variables may be either
+/// Tracy variables, with the lattice re-evaluated for each new value, or element strengths.
+pub fn match_tracy_lattice(
+    file_contents: &str,
+    periodicity: usize,
+    energy: f64,
+    variables: &[MatchVariable],
+    constraints: &[MatchConstraint],
+) -> Result<MatchResult, MatchError> {
+    let (elements, vars) =
+        parse_tracy_lattice(file_contents, &HashMap::new()).map_err(|_| MatchError)?;
+    let mut initial = Vec::with_capacity(variables.len());
+    for variable in variables.iter() {
+        initial.push(match &variable.knob {
+            Knob::Element { name, index } => {
+                let ele = elements.iter().find(|ele| &ele.name == name);
+                *ele.ok_or(MatchError)?.k.get(*index).ok_or(MatchError)?
+            }
+            Knob::Variable(name) => *vars.get(name).ok_or(MatchError)?,
+        });
+    }
+
+    let build = |values: &[f64]| {
+        let overrides: HashMap<String, f64> = variables
+            .iter()
+            .zip(values.iter())
+            .filter_map(|(variable, value)| match &variable.knob {
+                Knob::Variable(name) => Some((name.clone(), *value)),
+                _ => None,
+            })
+            .collect();
+        let (mut elements, _) = parse_tracy_lattice(file_contents, &overrides).ok()?;
+        for (variable, value) in variables.iter().zip(values.iter()) {
+            if let Knob::Element { name, index } = &variable.knob {
+                set_element_knob(&mut elements, name, *index, *value);
+            }
+        }
+        Some(Line::from_elements(elements, periodicity, energy))
+    };
+    run_match(build, &initial, variables, constraints)
+}
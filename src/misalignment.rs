@@ -0,0 +1,122 @@
+use crate::*;
+use ndarray::Array2;
+
+/// Placement error of an element relative to the reference orbit.  The offsets are taken at the
+/// entrance of the element, in metres, and the roll is about the longitudinal axis in radians,
+/// positive from x towards y.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Misalignment {
+    pub dx: f64,
+    pub dy: f64,
+    pub ds: f64,
+    pub roll: f64,
+}
+
+impl Misalignment {
+    pub fn is_zero(&self) -> bool {
+        *self == Misalignment::default()
+    }
+}
+
+/// Transformation from the reference frame to a frame rolled by `angle`.
+pub fn roll_matrix(angle: f64) -> Array2<f64> {
+    let (s, c) = angle.sin_cos();
+    let mut retval = Array2::eye(6);
+    for i in 0..2 {
+        retval[[i, i]] = c;
+        retval[[i, i + 2]] = s;
+        retval[[i + 2, i]] = -s;
+        retval[[i + 2, i + 2]] = c;
+    }
+    retval
+}
+
+fn linear_drift(coords: &mut [f64; 6], length: f64) {
+    coords[0] += length * coords[1];
+    coords[2] += length * coords[3];
+}
+
+fn rotate(coords: &mut [f64; 6], angle: f64) {
+    let (s, c) = angle.sin_cos();
+    for i in 0..2 {
+        let (x, y) = (coords[i], coords[i + 2]);
+        coords[i] = c * x + s * y;
+        coords[i + 2] = -s * x + c * y;
+    }
+}
+
+impl Element {
+    /// Sets the placement error of the element and rolls its transfer matrix to match.
+    pub fn set_misalignment(&mut self, misalignment: Misalignment) {
+        self.misalignment = misalignment;
+        self.update_matrices();
+    }
+
+    /// Transfer matrix of the element in its own frame rolled into the reference frame.
+    pub(crate) fn roll_into_reference(&self, matrix: Array2<f64>) -> Array2<f64> {
+        let roll = self.misalignment.roll;
+        if roll == 0.0 {
+            return matrix;
+        }
+        roll_matrix(-roll).dot(&matrix).dot(&roll_matrix(roll))
+    }
+
+    /// Moves coordinates from the reference frame to the displaced, but not rolled, frame of the
+    /// element at its entrance.
+    pub(crate) fn shift_in(&self, coords: &mut [f64; 6]) {
+        let m = &self.misalignment;
+        coords[0] -= m.dx;
+        coords[2] -= m.dy;
+        linear_drift(coords, m.ds);
+    }
+
+    /// Moves coordinates from the displaced frame of the element at its exit back to the
+    /// reference frame, allowing for the rotation of the exit face of a bend.
+    pub(crate) fn shift_out(&self, coords: &mut [f64; 6]) {
+        let m = &self.misalignment;
+        let (s, c) = self.k[0].sin_cos();
+        coords[0] += c * m.dx + s * m.ds;
+        coords[2] += m.dy;
+        linear_drift(coords, s * m.dx - c * m.ds);
+    }
+
+    /// The constant kick left over when a rolled bend deflects the beam out of the horizontal
+    /// plane, applied as a thin kick in the middle of the element.
+    pub(crate) fn roll_error_kick(&self, coords: &mut [f64; 6]) {
+        let (angle, roll) = (self.k[0], self.misalignment.roll);
+        if angle == 0.0 || roll == 0.0 {
+            return;
+        }
+        let kick = [angle * (1.0 - roll.cos()), -angle * roll.sin()];
+        coords[0] += kick[0] * self.length / 2.0;
+        coords[1] += kick[0];
+        coords[2] += kick[1] * self.length / 2.0;
+        coords[3] += kick[1];
+    }
+
+    pub(crate) fn roll_in(&self, coords: &mut [f64; 6]) {
+        rotate(coords, self.misalignment.roll);
+    }
+
+    pub(crate) fn roll_out(&self, coords: &mut [f64; 6]) {
+        rotate(coords, -self.misalignment.roll);
+    }
+}
+
+impl Line {
+    /// The full ring as a single cell with a periodicity of one, so that each instance of an
+    /// element can be given its own errors.
+    pub fn unrolled(&self) -> Line {
+        let elements = (0..self.periodicity)
+            .flat_map(|_| self.line.iter().cloned())
+            .collect();
+        Line::from_elements(elements, 1, self.energy)
+    }
+
+    /// Sets the placement error of element `index` of the line and recomputes the line.
+    pub fn set_misalignment(&mut self, index: usize, misalignment: Misalignment) {
+        let mut elements = self.line.clone();
+        elements[index].set_misalignment(misalignment);
+        *self = Line::from_elements(elements, self.periodicity, self.energy);
+    }
+}
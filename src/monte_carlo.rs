@@ -0,0 +1,222 @@
+use crate::*;
+use rayon::prelude::*;
+
+/// Seeded xoshiro256** generator, so that an error ensemble can be regenerated exactly from its
+/// seeds on any platform.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: [u64; 4],
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // The state is filled with SplitMix64, as recommended for xoshiro.
+        let mut x = seed;
+        let mut state = [0; 4];
+        for s in state.iter_mut() {
+            x = x.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            *s = z ^ (z >> 31);
+        }
+        Self { state }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let retval = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        retval
+    }
+
+    /// Uniformly distributed in [0, 1).
+    pub fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Standard normal, by the Box-Muller transform.
+    pub fn gaussian(&mut self) -> f64 {
+        let u1 = 1.0 - self.uniform();
+        let u2 = self.uniform();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+
+    /// Standard normal with values beyond `cutoff` standard deviations redrawn.  A cutoff of
+    /// zero or less gives an untruncated distribution.
+    pub fn truncated_gaussian(&mut self, cutoff: f64) -> f64 {
+        loop {
+            let value = self.gaussian();
+            if cutoff <= 0.0 || value.abs() <= cutoff {
+                return value;
+            }
+        }
+    }
+}
+
+/// Random error of one parameter on the elements matching a glob pattern.  Relative errors are
+/// given as a fraction of the design value, as is usual for gradient errors, and absolute errors
+/// are added to it, as for alignment errors.
+#[derive(Debug, Clone)]
+pub struct ErrorSpec {
+    pub pattern: String,
+    pub parameter: ElementParameter,
+    pub rms: f64,
+    pub relative: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ErrorSettings {
+    pub errors: Vec<ErrorSpec>,
+    /// Truncation of the Gaussian distributions in standard deviations.
+    pub cutoff: f64,
+}
+
+/// The ring with random errors applied for one seed.  The ring is unrolled to a single cell
+/// first, so that every instance of an element gets its own error.
+pub fn apply_errors(line: &Line, settings: &ErrorSettings, seed: u64) -> Line {
+    let mut rng = Rng::new(seed);
+    let ring = line.unrolled();
+    let mut elements = ring.line;
+    for spec in settings.errors.iter() {
+        for ele in elements.iter_mut() {
+            if !glob_match(&spec.pattern, &ele.name) {
+                continue;
+            }
+            let error = spec.rms * rng.truncated_gaussian(settings.cutoff);
+            let value = ele.parameter(spec.parameter);
+            let new_value = if spec.relative {
+                value * (1.0 + error)
+            } else {
+                value + error
+            };
+            ele.set_parameter(spec.parameter, new_value);
+        }
+    }
+    Line::from_elements(elements, 1, line.energy)
+}
+
+#[derive(Debug, Clone)]
+pub struct SeedResult {
+    pub seed: u64,
+    /// Tunes of the two transverse normal modes, if the ring is stable.
+    pub tunes: Option<[f64; 2]>,
+    /// RMS relative beta beating in each plane around the ring, if the ring is stable.
+    pub beta_beat: Option<[f64; 2]>,
+    /// Equilibrium emittances of the two transverse normal modes, so that the vertical one
+    /// includes the coupling from the errors, if the ring is stable.
+    pub emittances: Option<[f64; 2]>,
+    /// Dynamic aperture area at each momentum offset, when requested.
+    pub da_areas: Option<Vec<f64>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Statistics {
+    pub count: usize,
+    pub mean: f64,
+    pub std: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Statistics {
+    /// Statistics of the values, or `None` if there are none.
+    pub fn from_values(values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let count = values.len();
+        let mean = values.iter().sum::<f64>() / count as f64;
+        let variance = if count > 1 {
+            values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (count - 1) as f64
+        } else {
+            0.0
+        };
+        Some(Self {
+            count,
+            mean,
+            std: variance.sqrt(),
+            min: values.iter().copied().fold(f64::INFINITY, f64::min),
+            max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MonteCarloResult {
+    pub seeds: Vec<SeedResult>,
+}
+
+impl MonteCarloResult {
+    /// Statistics of a quantity over the seeds where it is available.
+    pub fn statistics<F>(&self, quantity: F) -> Option<Statistics>
+    where
+        F: Fn(&SeedResult) -> Option<f64>,
+    {
+        let values: Vec<f64> = self.seeds.iter().filter_map(quantity).collect();
+        Statistics::from_values(&values)
+    }
+
+    /// Number of seeds for which the ring is unstable.
+    pub fn unstable(&self) -> usize {
+        self.seeds
+            .iter()
+            .filter(|seed| seed.tunes.is_none())
+            .count()
+    }
+}
+
+fn beta_beat(ideal: &Line, line: &Line) -> Option<[f64; 2]> {
+    let reference = ideal.normal_modes.as_ref()?;
+    let modes = line.normal_modes.as_ref()?;
+    let n_eles = ideal.line.len();
+    let mut retval = [0.0; 2];
+    for (i, optics) in modes.optics.iter().enumerate() {
+        let design = &reference.optics[i % n_eles];
+        for (plane, beat) in retval.iter_mut().enumerate() {
+            let b0 = design.beta[plane][plane];
+            *beat += ((optics.beta[plane][plane] - b0) / b0).powi(2);
+        }
+    }
+    Some(retval.map(|sum| (sum / modes.optics.len() as f64).sqrt()))
+}
+
+/// Applies the errors for each seed in parallel and evaluates the tunes, beta beating, mode
+/// emittances and, if `da` is given, the dynamic aperture of each resulting ring.
+pub fn monte_carlo(
+    line: &Line,
+    settings: &ErrorSettings,
+    seeds: &[u64],
+    da: Option<&DaSettings>,
+) -> MonteCarloResult {
+    let seeds = seeds
+        .par_iter()
+        .map(|&seed| {
+            let ring = apply_errors(line, settings, seed);
+            let tunes = ring
+                .normal_modes
+                .as_ref()
+                .map(|modes| [modes.tunes[0], modes.tunes[1]]);
+            let da_areas = da.and_then(|da| {
+                tunes?;
+                dynamic_aperture(&ring, da)
+                    .ok()
+                    .map(|result| result.areas())
+            });
+            SeedResult {
+                seed,
+                tunes,
+                beta_beat: beta_beat(line, &ring),
+                emittances: mode_emittances(&ring).map(|modes| modes.emittances),
+                da_areas,
+            }
+        })
+        .collect();
+    MonteCarloResult { seeds }
+}
@@ -0,0 +1,148 @@
+use crate::*;
+use ndarray::Array2;
+use num_complex::Complex64;
+
+/// Highest multipole order stored, where order n is the 2n-pole, so 10 is the 20-pole.
+pub const MAX_MULTIPOLE_ORDER: usize = 10;
+
+/// Normal and skew multipole components of an element, in the convention of Tracy where
+/// B_y + i B_x = sum (b_n + i a_n) (x + i y)^(n - 1) and index `n - 1` holds order n.  They are
+/// per unit length, or integrated strengths on a zero length element, and act in addition to
+/// `Element::k`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Multipoles {
+    pub normal: [f64; MAX_MULTIPOLE_ORDER],
+    pub skew: [f64; MAX_MULTIPOLE_ORDER],
+}
+
+impl Multipoles {
+    pub fn is_zero(&self) -> bool {
+        *self == Multipoles::default()
+    }
+
+    /// Sets the components of `order` from values relative to the main field at the reference
+    /// radius, so that b_n = normal * main_strength * reference_radius^(main_order - n).
+    pub fn set_relative(
+        &mut self,
+        order: usize,
+        normal: f64,
+        skew: f64,
+        main_order: usize,
+        main_strength: f64,
+        reference_radius: f64,
+    ) {
+        let scale = main_strength * reference_radius.powi(main_order as i32 - order as i32);
+        self.normal[order - 1] = normal * scale;
+        self.skew[order - 1] = skew * scale;
+    }
+
+    /// Components of `order` relative to the main field at the reference radius.
+    pub fn relative(
+        &self,
+        order: usize,
+        main_order: usize,
+        main_strength: f64,
+        reference_radius: f64,
+    ) -> [f64; 2] {
+        let scale = main_strength * reference_radius.powi(main_order as i32 - order as i32);
+        [self.normal[order - 1] / scale, self.skew[order - 1] / scale]
+    }
+}
+
+impl Element {
+    /// Sets the multipole components of the element and recomputes its transfer matrices.
+    pub fn set_multipoles(&mut self, multipoles: Multipoles) {
+        self.multipoles = multipoles;
+        self.update_matrices();
+    }
+
+    // Coefficients b_n + i a_n of the field beyond the linear optics of `k[0]` and `k[1]`.
+    fn nonlinear_coefficients(&self) -> [Complex64; MAX_MULTIPOLE_ORDER] {
+        let mut retval = [Complex64::default(); MAX_MULTIPOLE_ORDER];
+        for (n, c) in retval.iter_mut().enumerate() {
+            *c = Complex64::new(self.multipoles.normal[n], self.multipoles.skew[n]);
+        }
+        retval[2] += self.k[2];
+        retval[3] += self.k[3];
+        retval
+    }
+
+    /// B_y + i B_x of the multipoles and the sextupole and octupole strengths in `k`, at the
+    /// position `z = x + i y` in the frame of the element.
+    pub fn nonlinear_field(&self, z: Complex64) -> Complex64 {
+        self.nonlinear_coefficients()
+            .iter()
+            .rev()
+            .fold(Complex64::default(), |acc, c| acc * z + c)
+    }
+
+    fn nonlinear_gradient(&self, z: Complex64) -> Complex64 {
+        self.nonlinear_coefficients()
+            .iter()
+            .enumerate()
+            .skip(1)
+            .rev()
+            .fold(Complex64::default(), |acc, (n, c)| acc * z + c * n as f64)
+    }
+
+    /// Position of the reference orbit in the frame of a misaligned element.
+    fn reference_position(&self) -> Complex64 {
+        let m = &self.misalignment;
+        -Complex64::new(m.dx, m.dy) * Complex64::from_polar(1.0, -m.roll)
+    }
+
+    /// Length over which the strengths act, which is one for a thin element whose strengths are
+    /// integrated.
+    pub(crate) fn effective_length(&self) -> f64 {
+        if self.length == 0.0 { 1.0 } else { self.length }
+    }
+
+    /// Thin lens matrix of the gradient fed down from the multipoles, sextupoles and octupoles
+    /// at the reference orbit, in the frame of the element and for a slice of `length`.
+    pub(crate) fn feed_down_matrix(&self, length: f64) -> Option<Array2<f64>> {
+        let gradient = length * self.nonlinear_gradient(self.reference_position());
+        if gradient == Complex64::default() {
+            return None;
+        }
+        let mut retval = Array2::eye(6);
+        retval[[1, 0]] = -gradient.re;
+        retval[[1, 2]] = gradient.im;
+        retval[[3, 2]] = gradient.re;
+        retval[[3, 0]] = gradient.im;
+        Some(retval)
+    }
+
+    /// The constant part of the multipole kick along the reference orbit, in the reference frame,
+    /// that is not in the transfer matrix.
+    pub(crate) fn multipole_orbit_kick(&self) -> Option<[f64; 2]> {
+        if self.multipoles.is_zero() && self.k[2] == 0.0 && self.k[3] == 0.0 {
+            return None;
+        }
+        let z0 = self.reference_position();
+        let residual = self.nonlinear_field(z0) - self.nonlinear_gradient(z0) * z0;
+        if residual == Complex64::default() {
+            return None;
+        }
+        let kick = self.effective_length()
+            * Complex64::new(-residual.re, residual.im)
+            * Complex64::from_polar(1.0, self.misalignment.roll);
+        Some([kick.re, kick.im])
+    }
+
+    /// Applies the multipole components, in the frame of the element, as a thin kick through
+    /// `length`.
+    pub(crate) fn multipole_kick(&self, coords: &mut [f64; 6], length: f64) {
+        let z = Complex64::new(coords[0], coords[2]);
+        let field = self
+            .multipoles
+            .normal
+            .iter()
+            .zip(self.multipoles.skew.iter())
+            .rev()
+            .fold(Complex64::default(), |acc, (b, a)| {
+                acc * z + Complex64::new(*b, *a)
+            });
+        coords[1] -= length * field.re;
+        coords[3] += length * field.im;
+    }
+}
@@ -0,0 +1,346 @@
+use crate::*;
+use itertools::Itertools;
+use ndarray::{Array1, Array2, s};
+use num_complex::Complex64;
+use std::f64::consts::PI;
+
+/// Generalised (Chao/Wolski) lattice functions at a single location.  `beta[k][p]` and
+/// `alpha[k][p]` are the beta and alpha functions of normal mode `k` projected onto the
+/// degree-of-freedom `p` (x, y, z), while `phase[k]` is the accumulated phase advance of mode `k`
+/// since the start of the line.
+#[derive(Debug, Clone)]
+pub struct ModeOptics {
+    pub beta: [[f64; 3]; 3],
+    pub alpha: [[f64; 3]; 3],
+    pub phase: [f64; 3],
+    pub eta: [f64; 4],
+}
+
+#[derive(Debug, Clone)]
+pub struct NormalModes {
+    pub dimension: usize,
+    pub tunes: [f64; 3],
+    pub frac_tunes: [f64; 3],
+    pub eigenvalues: [Complex64; 3],
+    pub eigenvectors: [Array1<Complex64>; 3],
+    pub optics: Vec<ModeOptics>,
+}
+
+/// Eigenvalues and eigenvectors of a stable, symplectic matrix of dimension 2n, with the
+/// eigenvectors normalised such that `v^† S v = i` and ordered so that mode `k` is the one which
+/// dominates the degree-of-freedom `k`.
+pub fn symplectic_eigenmodes(matrix: &Array2<f64>) -> Option<Vec<(Complex64, Array1<Complex64>)>> {
+    let dim = matrix.nrows();
+    let n_modes = dim / 2;
+    let s_mat = symplectic_form(dim).mapv(|x| Complex64::new(x, 0.0));
+
+    let mut modes = vec![];
+    for (lambda, vec) in eigen(matrix)? {
+        if lambda.im <= 0.0 {
+            continue;
+        }
+        if (lambda.norm() - 1.0).abs() > 1e-6 {
+            return None;
+        }
+        let q = vec.mapv(|x| x.conj()).dot(&s_mat.dot(&vec));
+        let (lambda, vec) = if q.im < 0.0 {
+            (lambda.conj(), vec.mapv(|x| x.conj()))
+        } else {
+            (lambda, vec)
+        };
+        let norm = q.im.abs().sqrt();
+        modes.push((lambda, vec.mapv(|x| x / norm)));
+    }
+    if modes.len() != n_modes {
+        return None;
+    }
+
+    let weight = |vec: &Array1<Complex64>, plane: usize| {
+        (vec[2 * plane].conj() * vec[2 * plane + 1]).im.abs()
+    };
+    let order = (0..n_modes)
+        .permutations(n_modes)
+        .max_by(|a, b| {
+            let score = |perm: &Vec<usize>| {
+                perm.iter()
+                    .enumerate()
+                    .map(|(plane, &mode)| weight(&modes[mode].1, plane))
+                    .sum::<f64>()
+            };
+            score(a).total_cmp(&score(b))
+        })
+        .unwrap();
+
+    Some(
+        order
+            .into_iter()
+            .enumerate()
+            .map(|(plane, mode)| {
+                let (lambda, vec) = &modes[mode];
+                let rotation = vec[2 * plane].conj() / vec[2 * plane].norm();
+                (*lambda, vec.mapv(|x| x * rotation))
+            })
+            .collect(),
+    )
+}
+
+// The longitudinal coordinate, z, is the path length excess which is conjugate to -delta.
+// Flipping its sign gives the canonical ordering used by `symplectic_form`.
+fn flip_longitudinal(matrix: &Array2<f64>) -> Array2<f64> {
+    let mut retval = matrix.clone();
+    retval.row_mut(4).mapv_inplace(|x| -x);
+    retval.column_mut(4).mapv_inplace(|x| -x);
+    retval
+}
+
+fn has_longitudinal_focusing(matrix: &Array2<f64>) -> bool {
+    (0..5).any(|j| matrix[[5, j]] != 0.0)
+}
+
+fn mode_optics(vecs: &[Array1<Complex64>; 3], phase: [f64; 3], eta: [f64; 4]) -> ModeOptics {
+    let mut beta = [[0.0; 3]; 3];
+    let mut alpha = [[0.0; 3]; 3];
+    for (k, vec) in vecs.iter().enumerate() {
+        for p in 0..3 {
+            let sign = if p == 2 { -1.0 } else { 1.0 };
+            beta[k][p] = 2.0 * vec[2 * p].norm_sqr();
+            alpha[k][p] = -2.0 * sign * (vec[2 * p] * vec[2 * p + 1].conj()).re;
+        }
+    }
+    ModeOptics {
+        beta,
+        alpha,
+        phase,
+        eta,
+    }
+}
+
+// Transports the eigenvectors and dispersion through `matrix`, accumulating the phase advance of
+// each mode, and returns the new dispersion.
+fn propagate(
+    vecs: &mut [Array1<Complex64>; 3],
+    phase: &mut [f64; 3],
+    eta: &Array1<f64>,
+    matrix: &Array2<f64>,
+    six_d: bool,
+) -> Array1<f64> {
+    let c_matrix = matrix.mapv(|x| Complex64::new(x, 0.0));
+    for (k, vec) in vecs.iter_mut().enumerate() {
+        let new_vec = c_matrix.dot(vec);
+        if new_vec[2 * k].norm() > 0.0 && vec[2 * k].norm() > 0.0 {
+            let mut dphi = new_vec[2 * k].arg() - vec[2 * k].arg();
+            if dphi > PI {
+                dphi -= 2.0 * PI;
+            } else if dphi < -PI {
+                dphi += 2.0 * PI;
+            }
+            phase[k] += dphi;
+        }
+        *vec = new_vec;
+    }
+
+    if six_d {
+        Array1::from(eta_from_longitudinal_mode(&vecs[2]).to_vec())
+    } else {
+        matrix.slice(s![0..4, 0..4]).dot(eta) + matrix.slice(s![0..4, 5])
+    }
+}
+
+fn eta_from_longitudinal_mode(vec: &Array1<Complex64>) -> [f64; 4] {
+    let denom = vec[5].norm_sqr();
+    let mut eta = [0.0; 4];
+    for (i, val) in eta.iter_mut().enumerate() {
+        *val = (vec[i] * vec[5].conj()).re / denom;
+    }
+    eta
+}
+
+/// Normal mode analysis of a periodic line.  If the one-turn matrix contains longitudinal
+/// focusing a full 6D analysis is done, otherwise the transverse modes are found from the 4D
+/// matrix and the dispersion from its fixed point.
+pub fn normal_mode_analysis(
+    line: &[Element],
+    line_matrix: &Array2<f64>,
+    periodicity: usize,
+) -> Option<NormalModes> {
+    let zero = Complex64::new(0.0, 0.0);
+    let six_d = has_longitudinal_focusing(line_matrix);
+
+    let (eigenvalues, mut vecs, mut eta) = if six_d {
+        let modes = symplectic_eigenmodes(&flip_longitudinal(line_matrix))?;
+        let mut vecs: [Array1<Complex64>; 3] = std::array::from_fn(|k| modes[k].1.clone());
+        for vec in vecs.iter_mut() {
+            vec[4] = -vec[4];
+        }
+        let eta = eta_from_longitudinal_mode(&vecs[2]);
+        (
+            [modes[0].0, modes[1].0, modes[2].0],
+            vecs,
+            Array1::from(eta.to_vec()),
+        )
+    } else {
+        let m4 = line_matrix.slice(s![0..4, 0..4]).to_owned();
+        let modes = symplectic_eigenmodes(&m4)?;
+        let vecs: [Array1<Complex64>; 3] = std::array::from_fn(|k| {
+            let mut vec = Array1::from_elem(6, zero);
+            if k < 2 {
+                vec.slice_mut(s![0..4]).assign(&modes[k].1);
+            }
+            vec
+        });
+        let eta = solve(
+            &(Array2::eye(4) - &m4),
+            &line_matrix.slice(s![0..4, 5]).to_owned(),
+        )?;
+        (
+            [modes[0].0, modes[1].0, Complex64::new(1.0, 0.0)],
+            vecs,
+            eta,
+        )
+    };
+
+    let initial_vecs = vecs.clone();
+    let mut phase = [0.0; 3];
+    let mut optics = Vec::with_capacity(line.len() + 1);
+    let to_array = |eta: &Array1<f64>| [eta[0], eta[1], eta[2], eta[3]];
+
+    for ele in line.iter() {
+        optics.push(mode_optics(&vecs, phase, to_array(&eta)));
+        eta = propagate(&mut vecs, &mut phase, &eta, &ele.r_matrix, six_d);
+    }
+    optics.push(mode_optics(&vecs, phase, to_array(&eta)));
+
+    for (k, total) in phase.iter().enumerate() {
+        if *total < 0.0 {
+            for entry in optics.iter_mut() {
+                entry.phase[k] = -entry.phase[k];
+            }
+        }
+    }
+
+    let tunes: [f64; 3] =
+        std::array::from_fn(|k| periodicity as f64 * phase[k].abs() / (2.0 * PI));
+
+    Some(NormalModes {
+        dimension: if six_d { 6 } else { 4 },
+        tunes,
+        frac_tunes: tunes.map(|q| q.fract()),
+        eigenvalues,
+        eigenvectors: initial_vecs,
+        optics,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct OpticsPoint {
+    pub s: f64,
+    /// Index of the element containing the point.
+    pub element: usize,
+    pub optics: ModeOptics,
+}
+
+// Optics at the given (element, distance into the element) locations of one cell, which must be
+// in order along the line.
+pub(crate) fn optics_inside_elements(
+    line: &Line,
+    locations: &[(usize, f64)],
+) -> Option<Vec<ModeOptics>> {
+    let modes = line.normal_modes.as_ref()?;
+    let six_d = modes.dimension == 6;
+    let mut vecs = modes.eigenvectors.clone();
+    let mut phase = [0.0; 3];
+    let mut eta = Array1::from(modes.optics[0].eta.to_vec());
+    let to_array = |eta: &Array1<f64>| [eta[0], eta[1], eta[2], eta[3]];
+
+    let mut retval = Vec::with_capacity(locations.len());
+    let mut locations = locations.iter().peekable();
+    for (i, ele) in line.line.iter().enumerate() {
+        let (mut inner_vecs, mut inner_phase, mut inner_eta) = (vecs.clone(), phase, eta.clone());
+        let mut position = 0.0;
+        while let Some(&(_, offset)) = locations.next_if(|(element, _)| *element == i) {
+            inner_eta = propagate(
+                &mut inner_vecs,
+                &mut inner_phase,
+                &inner_eta,
+                &ele.partial_matrix(offset - position),
+                six_d,
+            );
+            position = offset;
+            retval.push(mode_optics(&inner_vecs, inner_phase, to_array(&inner_eta)));
+        }
+        eta = propagate(&mut vecs, &mut phase, &eta, &ele.r_matrix, six_d);
+    }
+
+    for (k, total) in phase.iter().enumerate() {
+        if *total < 0.0 {
+            for entry in retval.iter_mut() {
+                entry.phase[k] = -entry.phase[k];
+            }
+        }
+    }
+    Some(retval)
+}
+
+/// Optics at `n_slices` evenly spaced points through each element of one cell, starting at the
+/// entrance of each element, followed by the end of the cell.  Zero length elements give a
+/// single point.
+pub fn optics_table(line: &Line, n_slices: usize) -> Option<Vec<OpticsPoint>> {
+    let n_slices = n_slices.max(1);
+    let mut locations = vec![];
+    let mut positions = vec![];
+    let mut s = 0.0;
+    for (i, ele) in line.line.iter().enumerate() {
+        let n = if ele.length == 0.0 { 1 } else { n_slices };
+        for slice in 0..n {
+            let offset = ele.length * slice as f64 / n as f64;
+            locations.push((i, offset));
+            positions.push(s + offset);
+        }
+        s += ele.length;
+    }
+    let n_eles = line.line.len();
+    if n_eles > 0 {
+        locations.push((n_eles - 1, line.line[n_eles - 1].length));
+        positions.push(s);
+    }
+
+    let optics = optics_inside_elements(line, &locations)?;
+    Some(
+        optics
+            .into_iter()
+            .zip(locations.iter().zip(positions.iter()))
+            .map(|(optics, (&(element, _), &s))| OpticsPoint { s, element, optics })
+            .collect(),
+    )
+}
+
+/// Optics at any position `s` around the ring, including inside elements.
+pub fn optics_at(line: &Line, s: f64) -> Option<OpticsPoint> {
+    let modes = line.normal_modes.as_ref()?;
+    if !(0.0..=line.total_length).contains(&s) || line.line.is_empty() {
+        return None;
+    }
+    let cell = ((s / line.line_length).floor() as usize).min(line.periodicity - 1);
+    let s_cell = s - cell as f64 * line.line_length;
+
+    let mut start = 0.0;
+    let mut location = (line.line.len() - 1, line.line[line.line.len() - 1].length);
+    for (i, ele) in line.line.iter().enumerate() {
+        if s_cell < start + ele.length {
+            location = (i, s_cell - start);
+            break;
+        }
+        start += ele.length;
+    }
+
+    let mut optics = optics_inside_elements(line, &[location])?.pop()?;
+    let cell_phase = modes.optics[line.line.len()].phase;
+    for (phase, total) in optics.phase.iter_mut().zip(cell_phase.iter()) {
+        *phase += cell as f64 * total;
+    }
+    Some(OpticsPoint {
+        s,
+        element: location.0,
+        optics,
+    })
+}
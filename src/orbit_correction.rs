@@ -0,0 +1,166 @@
+use crate::*;
+use ndarray::{Array1, Array2};
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CorrectionError {
+    /// The number of readings differs from the number of rows of the response matrix.
+    DimensionMismatch,
+    /// The singular value decomposition of the response matrix did not converge.
+    SingularValueDecomposition,
+    /// The closed orbit of the ring, before or after the correction, was not found.
+    ClosedOrbit,
+}
+
+impl Error for CorrectionError {}
+
+impl fmt::Display for CorrectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DimensionMismatch => write!(f, "Readings do not match the response matrix"),
+            Self::SingularValueDecomposition => {
+                write!(f, "Singular value decomposition did not converge")
+            }
+            Self::ClosedOrbit => write!(f, "Closed orbit finder did not converge"),
+        }
+    }
+}
+
+impl From<ClosedOrbitError> for CorrectionError {
+    fn from(_: ClosedOrbitError) -> Self {
+        Self::ClosedOrbit
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CorrectionSettings {
+    /// Singular values below this fraction of the largest are dropped.
+    pub cutoff: f64,
+    /// Tikhonov parameter, in the units of the singular values, that damps the weakest modes
+    /// instead of dropping them.
+    pub regularisation: f64,
+}
+
+impl Default for CorrectionSettings {
+    fn default() -> Self {
+        Self {
+            cutoff: 1e-3,
+            regularisation: 0.0,
+        }
+    }
+}
+
+/// Corrector kicks that minimise the orbit at the BPMs, with singular values s_i that survive
+/// the cutoff weighted by s_i / (s_i^2 + lambda^2) in the pseudo-inverse.  Also returns the
+/// number of singular values used.
+pub fn correction_kicks(
+    response: &Array2<f64>,
+    readings: &[f64],
+    settings: &CorrectionSettings,
+) -> Result<(Vec<f64>, usize), CorrectionError> {
+    if response.nrows() != readings.len() {
+        return Err(CorrectionError::DimensionMismatch);
+    }
+    let (u, s, v) = svd(response).ok_or(CorrectionError::SingularValueDecomposition)?;
+    let readings = Array1::from(readings.to_vec());
+    let s_max = s.first().copied().unwrap_or(0.0);
+    let lambda2 = settings.regularisation.powi(2);
+    let mut kicks = Array1::zeros(response.ncols());
+    let mut used = 0;
+    for (i, &s_i) in s.iter().enumerate() {
+        if s_i <= 0.0 || s_i < settings.cutoff * s_max {
+            continue;
+        }
+        used += 1;
+        let weight = s_i / (s_i * s_i + lambda2) * u.column(i).dot(&readings);
+        kicks.scaled_add(-weight, &v.column(i));
+    }
+    Ok((kicks.to_vec(), used))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OrbitStatistics {
+    pub rms: f64,
+    pub max: f64,
+}
+
+impl OrbitStatistics {
+    pub fn from_readings(readings: &[f64]) -> Self {
+        if readings.is_empty() {
+            return Self::default();
+        }
+        let sum2: f64 = readings.iter().map(|x| x * x).sum();
+        Self {
+            rms: (sum2 / readings.len() as f64).sqrt(),
+            max: readings.iter().fold(0.0, |acc, x| acc.max(x.abs())),
+        }
+    }
+}
+
+/// Horizontal and vertical closed orbit at the BPMs of the response, from the linear on-momentum
+/// orbit of the ring.  As the momentum is fixed, they match a response matrix computed without
+/// `OrmSettings::fixed_path_length`.
+pub fn bpm_readings(
+    line: &Line,
+    response: &OrbitResponse,
+) -> Result<[Vec<f64>; 2], CorrectionError> {
+    let orbit = find_closed_orbit(&line.unrolled(), OrbitDimension::FourD, 0.0)?.orbit;
+    Ok([0, 1].map(|plane| {
+        response
+            .bpms
+            .iter()
+            .map(|&bpm| orbit[bpm][2 * plane])
+            .collect()
+    }))
+}
+
+#[derive(Debug, Clone)]
+pub struct CorrectionResult {
+    /// Kicks added to the horizontal and vertical correctors of the response, in rad.
+    pub h_kicks: Vec<f64>,
+    pub v_kicks: Vec<f64>,
+    /// The ring, unrolled to a single cell, with the kicks applied.
+    pub line: Line,
+    /// Horizontal and vertical orbit at the BPMs before and after the correction.
+    pub before: [OrbitStatistics; 2],
+    pub after: [OrbitStatistics; 2],
+    /// Number of singular values used in each plane.
+    pub singular_values: [usize; 2],
+}
+
+/// Corrects the closed orbit at the BPMs of the response matrix and applies the kicks to its
+/// correctors.  The indices of the response are taken around the full ring, as given by
+/// `orbit_response` or `analytic_orbit_response` on the same ring.
+pub fn correct_orbit(
+    line: &Line,
+    response: &OrbitResponse,
+    settings: &CorrectionSettings,
+) -> Result<CorrectionResult, CorrectionError> {
+    let readings = bpm_readings(line, response)?;
+    let (h_kicks, h_used) = correction_kicks(&response.horizontal, &readings[0], settings)?;
+    let (v_kicks, v_used) = correction_kicks(&response.vertical, &readings[1], settings)?;
+
+    let mut elements = line.unrolled().line;
+    for (&corrector, kick) in response.h_correctors.iter().zip(h_kicks.iter()) {
+        elements[corrector].kick[0] += kick;
+    }
+    for (&corrector, kick) in response.v_correctors.iter().zip(v_kicks.iter()) {
+        elements[corrector].kick[1] += kick;
+    }
+    let corrected = Line::from_elements(elements, 1, line.energy);
+    let residual = bpm_readings(&corrected, response)?;
+
+    Ok(CorrectionResult {
+        h_kicks,
+        v_kicks,
+        line: corrected,
+        before: readings
+            .each_ref()
+            .map(|r| OrbitStatistics::from_readings(r)),
+        after: residual
+            .each_ref()
+            .map(|r| OrbitStatistics::from_readings(r)),
+        singular_values: [h_used, v_used],
+    })
+}
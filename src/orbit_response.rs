@@ -0,0 +1,230 @@
+use crate::*;
+use ndarray::{Array1, Array2, s};
+use std::f64::consts::PI;
+
+const DELTA_STEP: f64 = 1e-6;
+
+#[derive(Debug, Clone)]
+pub struct OrmSettings {
+    /// Glob patterns for the names of the BPMs and the horizontal and vertical correctors.
+    pub bpms: String,
+    pub h_correctors: String,
+    pub v_correctors: String,
+    /// Whether the path length is held fixed, as it is by the RF, so that each kick also shifts
+    /// the momentum and the orbit picks up a dispersive term.
+    pub fixed_path_length: bool,
+}
+
+impl Default for OrmSettings {
+    fn default() -> Self {
+        Self {
+            bpms: "bpm*".to_string(),
+            h_correctors: "ch*".to_string(),
+            v_correctors: "cv*".to_string(),
+            fixed_path_length: true,
+        }
+    }
+}
+
+/// Orbit response of each BPM to each corrector, in m/rad, with one row per BPM.  Elements are
+/// given by their indices around the full ring.
+#[derive(Debug, Clone)]
+pub struct OrbitResponse {
+    pub bpms: Vec<usize>,
+    pub h_correctors: Vec<usize>,
+    pub v_correctors: Vec<usize>,
+    pub horizontal: Array2<f64>,
+    pub vertical: Array2<f64>,
+}
+
+fn ring_find(line: &Line, pattern: &str) -> Vec<usize> {
+    let n_eles = line.line.len();
+    let cell = line.find(pattern);
+    (0..line.periodicity)
+        .flat_map(|c| cell.iter().map(move |i| c * n_eles + i))
+        .collect()
+}
+
+// Optics where a kick acts or an orbit is read: the middle of the element.
+fn optics_at_element(line: &Line, s: &[f64], index: usize) -> Option<OpticsPoint> {
+    let n_eles = line.line.len();
+    let cell = (index / n_eles) as f64 * line.line_length;
+    let i = index % n_eles;
+    optics_at(line, cell + (s[i] + s[i + 1]) / 2.0)
+}
+
+/// Orbit response matrix from the uncoupled optics, with
+/// R_ij = sqrt(beta_i beta_j) cos(pi Q - |phi_i - phi_j|) / (2 sin(pi Q)), less
+/// eta_i eta_j / (alpha_c C) in the horizontal plane when the path length is fixed.
+pub fn analytic_orbit_response(line: &Line, settings: &OrmSettings) -> Option<OrbitResponse> {
+    let modes = line.normal_modes.as_ref()?;
+    let s = line.s_positions();
+    let bpms = ring_find(line, &settings.bpms);
+    let h_correctors = ring_find(line, &settings.h_correctors);
+    let v_correctors = ring_find(line, &settings.v_correctors);
+    let optics = |indices: &[usize]| -> Option<Vec<OpticsPoint>> {
+        indices
+            .iter()
+            .map(|&i| optics_at_element(line, &s, i))
+            .collect()
+    };
+    let bpm_optics = optics(&bpms)?;
+    let circumference = line.total_length;
+
+    let response = |correctors: &[usize], plane: usize| -> Option<Array2<f64>> {
+        let corrector_optics = optics(correctors)?;
+        let tune = modes.tunes[plane];
+        let mut retval = Array2::zeros((bpms.len(), correctors.len()));
+        for (i, b) in bpm_optics.iter().enumerate() {
+            for (j, c) in corrector_optics.iter().enumerate() {
+                let (bi, bj) = (b.optics.beta[plane][plane], c.optics.beta[plane][plane]);
+                let dphi = (b.optics.phase[plane] - c.optics.phase[plane]).abs();
+                retval[[i, j]] =
+                    (bi * bj).sqrt() * (PI * tune - dphi).cos() / (2.0 * (PI * tune).sin());
+                if settings.fixed_path_length && plane == 0 {
+                    retval[[i, j]] -=
+                        b.optics.eta[0] * c.optics.eta[0] / (line.mom_compact * circumference);
+                }
+            }
+        }
+        Some(retval)
+    };
+
+    Some(OrbitResponse {
+        horizontal: response(&h_correctors, 0)?,
+        vertical: response(&v_correctors, 1)?,
+        bpms,
+        h_correctors,
+        v_correctors,
+    })
+}
+
+/// Orbit response matrix from the change of the linear closed orbit with each corrector kick.
+/// The ring is unrolled so that each corrector is kicked on its own.
+pub fn orbit_response(
+    line: &Line,
+    settings: &OrmSettings,
+    kick: f64,
+) -> Result<OrbitResponse, ClosedOrbitError> {
+    let bpms = ring_find(line, &settings.bpms);
+    let h_correctors = ring_find(line, &settings.h_correctors);
+    let v_correctors = ring_find(line, &settings.v_correctors);
+    let ring = line.unrolled();
+
+    let orbit = |ring: &Line, delta: f64| {
+        find_closed_orbit(ring, OrbitDimension::FourD, delta).map(|orbit| orbit.orbit)
+    };
+    let path_length = |orbit: &[[f64; 6]]| orbit[orbit.len() - 1][4] - orbit[0][4];
+    let reference = orbit(&ring, 0.0)?;
+    let off_momentum = orbit(&ring, DELTA_STEP)?;
+    let dz_ddelta = (path_length(&off_momentum) - path_length(&reference)) / DELTA_STEP;
+
+    let response = |correctors: &[usize], plane: usize| {
+        let mut retval = Array2::zeros((bpms.len(), correctors.len()));
+        for (j, &corrector) in correctors.iter().enumerate() {
+            let mut kicked = ring.clone();
+            kicked.line[corrector].kick[plane] += kick;
+            let kicked_orbit = orbit(&kicked, 0.0)?;
+            let delta = if settings.fixed_path_length {
+                -(path_length(&kicked_orbit) - path_length(&reference)) / dz_ddelta
+            } else {
+                0.0
+            };
+            for (i, &bpm) in bpms.iter().enumerate() {
+                let dispersive =
+                    (off_momentum[bpm][2 * plane] - reference[bpm][2 * plane]) / DELTA_STEP * delta;
+                retval[[i, j]] =
+                    (kicked_orbit[bpm][2 * plane] - reference[bpm][2 * plane] + dispersive) / kick;
+            }
+        }
+        Ok(retval)
+    };
+
+    Ok(OrbitResponse {
+        horizontal: response(&h_correctors, 0)?,
+        vertical: response(&v_correctors, 1)?,
+        bpms,
+        h_correctors,
+        v_correctors,
+    })
+}
+
+/// Full orbit response matrix of the linear ring, including the coupling between the planes, as
+/// used to fit the lattice with LOCO.  The rows are the horizontal and then the vertical readings
+/// at the BPMs and the columns are the horizontal and then the vertical correctors, each in
+/// order around the ring.
+pub fn coupled_orbit_response(line: &Line, settings: &OrmSettings) -> Option<Array2<f64>> {
+    let correctors = [
+        ring_find(line, &settings.h_correctors),
+        ring_find(line, &settings.v_correctors),
+    ];
+    linear_response(
+        &line.unrolled().line,
+        &ring_find(line, &settings.bpms),
+        &correctors,
+        settings.fixed_path_length,
+    )
+}
+
+// Response of a ring of elements from its transfer matrices.  As in `Element::transport`, each
+// corrector kicks in its middle and the second half is a drift, so that a kick θ changes the
+// orbit at its exit by θL/2 in position and θ in angle.
+pub(crate) fn linear_response(
+    elements: &[Element],
+    bpms: &[usize],
+    correctors: &[Vec<usize>; 2],
+    fixed_path_length: bool,
+) -> Option<Array2<f64>> {
+    let mut matrices: Vec<Array2<f64>> = vec![Array2::eye(6)];
+    for ele in elements.iter() {
+        let next = ele.r_matrix.dot(&matrices[matrices.len() - 1]);
+        matrices.push(next);
+    }
+    let one_turn = &matrices[elements.len()];
+    let transverse = s![0..4, 0..4];
+    let closure = |m: &Array2<f64>| invert(&(Array2::eye(4) - m.slice(transverse)));
+
+    // Dispersion at the BPMs and the change of path length with momentum.
+    let eta0 = closure(one_turn)?.dot(&one_turn.slice(s![0..4, 5]));
+    let mut off_momentum = Array1::zeros(6);
+    off_momentum.slice_mut(s![0..4]).assign(&eta0);
+    off_momentum[5] = 1.0;
+    let dz_ddelta = one_turn.row(4).dot(&off_momentum);
+    let eta: Vec<Array1<f64>> = bpms
+        .iter()
+        .map(|&b| matrices[b].dot(&off_momentum))
+        .collect();
+
+    let n_correctors = correctors[0].len() + correctors[1].len();
+    let mut retval = Array2::zeros((2 * bpms.len(), n_correctors));
+    let columns = correctors
+        .iter()
+        .enumerate()
+        .flat_map(|(plane, indices)| indices.iter().map(move |&c| (plane, c)));
+    for (j, (plane, corrector)) in columns.enumerate() {
+        let p = corrector + 1;
+        let inverse = invert(&matrices[p])?;
+        let to_end = one_turn.dot(&inverse);
+        let one_turn_p = matrices[p].dot(&to_end);
+        let mut kick = Array1::zeros(4);
+        kick[2 * plane] = elements[corrector].length / 2.0;
+        kick[2 * plane + 1] = 1.0;
+        let mut orbit = Array1::zeros(6);
+        orbit
+            .slice_mut(s![0..4])
+            .assign(&closure(&one_turn_p)?.dot(&kick));
+        let delta = if fixed_path_length {
+            -one_turn_p.row(4).dot(&orbit) / dz_ddelta
+        } else {
+            0.0
+        };
+        // The orbit at the start of the turn before and after the kick.
+        let start = [inverse.dot(&orbit), to_end.dot(&orbit)];
+        for (i, &bpm) in bpms.iter().enumerate() {
+            let reading = matrices[bpm].dot(&start[usize::from(bpm < p)]) + delta * &eta[i];
+            retval[[i, j]] = reading[0];
+            retval[[bpms.len() + i, j]] = reading[2];
+        }
+    }
+    Some(retval)
+}
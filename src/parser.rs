@@ -1,10 +1,10 @@
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::error::Error;
 use std::f64::consts::PI;
 use std::fmt;
 use std::fs::File;
 use std::io::{BufReader, Read};
-use std::process::exit;
 
 use evalexpr::*;
 use winnow::combinator::{alt, delimited, opt, separated};
@@ -40,7 +40,7 @@ pub fn radians_to_degrees(rads: f64) -> f64 {
     rads * 180.0 / PI
 }
 
-fn evaluate_expr(expr: &str, vars: &HashMap<&str, f64>) -> Result<f64, EvalexprError> {
+pub(crate) fn evaluate_expr(expr: &str, vars: &HashMap<&str, f64>) -> Result<f64, EvalexprError> {
     let mut context = HashMapContext::new();
 
     for (&key, &val) in vars {
@@ -54,91 +54,136 @@ fn evaluate_expr(expr: &str, vars: &HashMap<&str, f64>) -> Result<f64, EvalexprE
     }
 }
 
-pub fn parse_lattice_from_tracy_file(file_path: &str) -> Result<Vec<crate::Element>, ParseError> {
-    use Statement::*;
+fn evaluate_n_slices(param: &dyn Fn(&str, &str) -> f64) -> usize {
+    (param("N", "1").round() as usize).max(1)
+}
 
-    let f = File::open(file_path).unwrap_or_else(|err| {
+/// Builds an element of the Tracy type `typ`, with `param(key, default)` giving the value of each
+/// of its parameters.  Element types that are not supported give a `ParseError`.
+pub(crate) fn make_element(
+    name: &str,
+    typ: &str,
+    param: &dyn Fn(&str, &str) -> f64,
+) -> Result<crate::Element, ParseError> {
+    let ele = match typ {
+        "Drift" => make_drift(name.to_string(), param("L", "0.0")),
+        "Cavity" => make_cavity(
+            name.to_string(),
+            param("L", "0.0"),
+            param("Frequency", "0.0"),
+            param("Voltage", "0.0"),
+            param("Phi", "0.0"),
+            param("HarNum", "0.0"),
+        ),
+        "Quadrupole" => {
+            if param("Phi", "0.0") != 0.0 {
+                eprintln!("ERROR: Cannot yet deal with skew quads");
+                return Err(ParseError);
+            }
+            let mut ele = make_quad(name.to_string(), param("L", "0.0"), param("B_2", "0.0"));
+            ele.n_slices = evaluate_n_slices(param);
+            ele
+        }
+        "Bending" => {
+            let angle = degrees_to_radians(param("Phi", "0.0"));
+            let mut ele = make_sbend(
+                name.to_string(),
+                param("L", "0.0"),
+                angle,
+                param("B_2", "0.0"),
+            );
+            ele.n_slices = evaluate_n_slices(param);
+            ele
+        }
+        "Sextupole" => {
+            let mut ele = make_sext(name.to_string(), param("L", "0.0"), param("B_3", "0.0"));
+            ele.n_slices = evaluate_n_slices(param);
+            ele
+        }
+        "Octupole" => {
+            let mut ele = make_oct(name.to_string(), param("L", "0.0"), param("B_4", "0.0"));
+            ele.n_slices = evaluate_n_slices(param);
+            ele
+        }
+        "Marker" => make_marker(name.to_string()),
+        &_ => {
+            eprintln!("ERROR: Cannot yet deal with elements of type {typ}");
+            return Err(ParseError);
+        }
+    };
+    Ok(ele)
+}
+
+/// Names of the variables used in an expression.
+pub(crate) fn expression_variables(expr: &str) -> Vec<String> {
+    build_operator_tree::<DefaultNumericTypes>(expr)
+        .map(|tree| {
+            tree.iter_variable_identifiers()
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn parse_lattice_from_tracy_file(file_path: &str) -> Result<Vec<crate::Element>, ParseError> {
+    let f = File::open(file_path).map_err(|err| {
         eprintln!("ERROR: Could not open {file_path}: {err}");
-        exit(1);
-    });
+        ParseError
+    })?;
     let mut reader = BufReader::new(f);
 
     let mut file_contents: String = String::new();
-    reader
-        .read_to_string(&mut file_contents)
-        .unwrap_or_else(|err| {
-            eprintln!("ERROR: Could not open {file_path}: {err}");
-            exit(1);
-        });
-
-    let parsed_data = parse_tracy_file(&file_contents);
-    let mut vars = HashMap::new();
+    reader.read_to_string(&mut file_contents).map_err(|err| {
+        eprintln!("ERROR: Could not open {file_path}: {err}");
+        ParseError
+    })?;
+
+    parse_tracy_lattice(&file_contents, &HashMap::new()).map(|(line, _)| line)
+}
+
+/// Parses the contents of a Tracy lattice file, returning the line and the table of variables.
+/// Variables named in `overrides` take the given value in place of their assigned expression.
+/// An expression that cannot be evaluated or a line that names an unknown element gives a
+/// `ParseError`.
+pub fn parse_tracy_lattice(
+    file_contents: &str,
+    overrides: &HashMap<String, f64>,
+) -> Result<(Vec<crate::Element>, HashMap<String, f64>), ParseError> {
+    use Statement::*;
+
+    let parsed_data = parse_tracy_file(file_contents);
+    let mut vars: HashMap<&str, f64> = HashMap::new();
     let mut element_dictionary: HashMap<&str, crate::Element> = HashMap::new();
     let mut line_dictionary: HashMap<&str, Vec<crate::Element>> = HashMap::new();
     for line in parsed_data.iter() {
         match line {
+            Assignment(var, _) if overrides.contains_key(*var) => {
+                vars.insert(*var, overrides[*var]);
+            }
             Assignment(var, expr) => {
                 match evaluate_expr(expr, &vars) {
-                    Ok(result) => vars.insert(var, result),
+                    Ok(result) => vars.insert(*var, result),
                     Err(e) => {
                         eprintln!("ERROR: {e}");
-                        exit(1);
+                        return Err(ParseError);
                     }
                 };
             }
-            Element(name, typ, params) => match *typ {
-                "Drift" => {
-                    let length = evaluate_expr(params.get("L").unwrap_or(&"0.0"), &vars).unwrap();
-                    element_dictionary.insert(name, make_drift((*name).to_string(), length));
-                }
-                "Cavity" => {
-                    let length = evaluate_expr(params.get("L").unwrap_or(&"0.0"), &vars).unwrap();
-                    let harnum =
-                        evaluate_expr(params.get("HarNum").unwrap_or(&"0.0"), &vars).unwrap();
-                    let voltage =
-                        evaluate_expr(params.get("Voltage").unwrap_or(&"0.0"), &vars).unwrap();
-                    let freq =
-                        evaluate_expr(params.get("Frequency").unwrap_or(&"0.0"), &vars).unwrap();
-                    let phi = evaluate_expr(params.get("Phi").unwrap_or(&"0.0"), &vars).unwrap();
-                    element_dictionary.insert(
-                        name,
-                        make_cavity((*name).to_string(), length, freq, voltage, phi, harnum),
-                    );
-                }
-                "Quadrupole" => {
-                    let length = evaluate_expr(params.get("L").unwrap_or(&"0.0"), &vars).unwrap();
-                    let phi = evaluate_expr(params.get("Phi").unwrap_or(&"0.0"), &vars).unwrap();
-                    if phi != 0.0 {
-                        eprintln!("ERROR: Cannot yet deal with skew quads");
-                        exit(1);
-                    }
-                    let b_2 = evaluate_expr(params.get("B_2").unwrap_or(&"0.0"), &vars).unwrap();
-                    element_dictionary.insert(name, make_quad((*name).to_string(), length, b_2));
-                }
-                "Bending" => {
-                    let length = evaluate_expr(params.get("L").unwrap_or(&"0.0"), &vars).unwrap();
-                    let b_2 = evaluate_expr(params.get("B_2").unwrap_or(&"0.0"), &vars).unwrap();
-                    let angle = evaluate_expr(params.get("Phi").unwrap_or(&"0.0"), &vars).unwrap();
-                    element_dictionary.insert(
-                        name,
-                        make_sbend((*name).to_string(), length, degrees_to_radians(angle), b_2),
-                    );
-                }
-                "Sextupole" => {
-                    let length = evaluate_expr(params.get("L").unwrap_or(&"0.0"), &vars).unwrap();
-                    let b_3 = evaluate_expr(params.get("B_3").unwrap_or(&"0.0"), &vars).unwrap();
-                    element_dictionary.insert(name, make_sext((*name).to_string(), length, b_3));
-                }
-                "Octupole" => {
-                    let length = evaluate_expr(params.get("L").unwrap_or(&"0.0"), &vars).unwrap();
-                    let b_4 = evaluate_expr(params.get("B_4").unwrap_or(&"0.0"), &vars).unwrap();
-                    element_dictionary.insert(name, make_oct((*name).to_string(), length, b_4));
-                }
-                "Marker" => {
-                    element_dictionary.insert(name, make_marker((*name).to_string()));
+            Element(name, typ, params) => {
+                let failed = Cell::new(false);
+                let param = |key: &str, default: &str| {
+                    evaluate_expr(params.get(key).unwrap_or(&default), &vars).unwrap_or_else(|e| {
+                        eprintln!("ERROR: {key} of {name}: {e}");
+                        failed.set(true);
+                        0.0
+                    })
+                };
+                let ele = make_element(name, typ, &param)?;
+                if failed.get() {
+                    return Err(ParseError);
                 }
-                &_ => todo!(),
-            },
+                element_dictionary.insert(name, ele);
+            }
             Line(name, eles_in_line) => {
                 let mut new_line: Vec<crate::Element> = Vec::new();
                 let mut rev_line: bool;
@@ -164,7 +209,7 @@ pub fn parse_lattice_from_tracy_file(file_path: &str) -> Result<Vec<crate::Eleme
                         }
                     } else {
                         eprintln!("ERROR: Could not find the element {search_str}");
-                        exit(1);
+                        return Err(ParseError);
                     }
                 }
                 line_dictionary.insert(name, new_line);
@@ -175,7 +220,11 @@ pub fn parse_lattice_from_tracy_file(file_path: &str) -> Result<Vec<crate::Eleme
                     return Err(ParseError);
                 } else {
                     let retval = line_dictionary.remove(name).unwrap(); // line_dictionary[name];
-                    return Ok(retval);
+                    let vars = vars
+                        .iter()
+                        .map(|(&key, &val)| (key.to_string(), val))
+                        .collect();
+                    return Ok((retval, vars));
                 }
             }
         }
@@ -285,7 +334,7 @@ pub fn parse_statement<'a>(input: &mut &'a str) -> Result<Statement<'a>> {
     .parse_next(input)
 }
 
-pub fn parse_tracy_file(input: &str) -> Vec<Statement> {
+pub fn parse_tracy_file(input: &str) -> Vec<Statement<'_>> {
     let mut remaining = input;
     let mut statements = Vec::new();
 
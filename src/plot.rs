@@ -0,0 +1,261 @@
+use crate::*;
+use std::fmt::Write;
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 500.0;
+const MARGIN: [f64; 4] = [70.0, 70.0, 30.0, 50.0]; // left, right, top, bottom
+const LAYOUT_HEIGHT: f64 = 40.0;
+
+// Minimal SVG document builder.
+struct Svg {
+    body: String,
+}
+
+impl Svg {
+    fn new() -> Self {
+        Svg {
+            body: String::new(),
+        }
+    }
+
+    fn line(&mut self, from: [f64; 2], to: [f64; 2], colour: &str, width: f64) {
+        let _ = writeln!(
+            self.body,
+            r#"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}" stroke="{colour}" stroke-width="{width}"/>"#,
+            from[0], from[1], to[0], to[1]
+        );
+    }
+
+    fn polyline(&mut self, points: &[[f64; 2]], colour: &str) {
+        let points: Vec<String> = points
+            .iter()
+            .map(|p| format!("{:.2},{:.2}", p[0], p[1]))
+            .collect();
+        let _ = writeln!(
+            self.body,
+            r#"<polyline points="{}" fill="none" stroke="{colour}" stroke-width="1.5"/>"#,
+            points.join(" ")
+        );
+    }
+
+    fn rect(&mut self, corner: [f64; 2], size: [f64; 2], colour: &str) {
+        let _ = writeln!(
+            self.body,
+            r#"<rect x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" fill="{colour}"/>"#,
+            corner[0], corner[1], size[0], size[1]
+        );
+    }
+
+    fn text(&mut self, position: [f64; 2], anchor: &str, colour: &str, text: &str) {
+        let _ = writeln!(
+            self.body,
+            r#"<text x="{:.2}" y="{:.2}" text-anchor="{anchor}" fill="{colour}" font-family="sans-serif" font-size="12">{text}</text>"#,
+            position[0], position[1]
+        );
+    }
+
+    fn finish(self) -> String {
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{WIDTH}\" height=\"{HEIGHT}\" viewBox=\"0 0 {WIDTH} {HEIGHT}\">\n\
+             <rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n{}</svg>\n",
+            self.body
+        )
+    }
+}
+
+/// Colour used for an element type in layout plots, or `None` for elements that are not drawn.
+pub fn element_colour(typ: &EleType) -> Option<&'static str> {
+    match typ {
+        EleType::EleTypeMarker | EleType::EleTypeDrift => None,
+        EleType::EleTypeBend => Some("royalblue"),
+        EleType::EleTypeQuad => Some("crimson"),
+        EleType::EleTypeSext => Some("forestgreen"),
+        EleType::EleTypeOct => Some("darkorange"),
+        EleType::EleTypeMult => Some("purple"),
+        EleType::EleTypeCav => Some("dimgray"),
+    }
+}
+
+// Round tick spacing giving about five ticks over the range.
+fn ticks(min: f64, max: f64) -> Vec<f64> {
+    let range = max - min;
+    if range <= 0.0 {
+        return vec![min];
+    }
+    let raw = range / 5.0;
+    let magnitude = 10.0_f64.powf(raw.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|m| m * magnitude)
+        .find(|step| range / step <= 6.0)
+        .unwrap_or(10.0 * magnitude);
+    let first = (min / step).ceil() as i64;
+    let last = (max / step).floor() as i64;
+    (first..=last).map(|i| i as f64 * step).collect()
+}
+
+fn label(value: f64) -> String {
+    let rounded = (value * 1e9).round() / 1e9;
+    format!("{}", rounded + 0.0)
+}
+
+fn padded_range(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| {
+        (lo.min(v), hi.max(v))
+    });
+    if !min.is_finite() || !max.is_finite() {
+        (0.0, 1.0)
+    } else if max - min < 1e-12 {
+        (min - 0.5, max + 0.5)
+    } else {
+        let pad = 0.05 * (max - min);
+        (min - pad, max + pad)
+    }
+}
+
+/// SVG figure of the uncoupled beta functions and horizontal dispersion along one cell, with the
+/// magnet layout drawn above.  Focusing quadrupoles are drawn above the axis of the layout bar
+/// and defocusing ones below.
+pub fn optics_svg(line: &Line) -> String {
+    let mut svg = Svg::new();
+    let s = line.s_positions();
+    let s_max = line.line_length.max(1e-12);
+    let [left, right, top, bottom] = MARGIN;
+    let plot_top = top + LAYOUT_HEIGHT + 10.0;
+    let plot_bottom = HEIGHT - bottom;
+    let x_of = |s: f64| left + (WIDTH - left - right) * s / s_max;
+
+    // Layout bar.
+    let axis = top + LAYOUT_HEIGHT / 2.0;
+    svg.line([left, axis], [WIDTH - right, axis], "black", 1.0);
+    for (i, ele) in line.line.iter().enumerate() {
+        let typ = element_type(ele);
+        let Some(colour) = element_colour(&typ) else {
+            continue;
+        };
+        let half = LAYOUT_HEIGHT / 2.0;
+        let (y, height) = match typ {
+            EleType::EleTypeQuad if ele.k[1] > 0.0 => (axis - half, half),
+            EleType::EleTypeQuad => (axis, half),
+            EleType::EleTypeBend => (axis - half / 2.0, half),
+            _ => (axis - half * 0.75, 1.5 * half),
+        };
+        let width = (x_of(s[i + 1]) - x_of(s[i])).max(1.0);
+        svg.rect([x_of(s[i]), y], [width, height], colour);
+    }
+
+    // Optics.
+    let n = s.len().min(line.beta_x_vec.len());
+    let (beta_min, beta_max) = padded_range(
+        line.beta_x_vec[..n]
+            .iter()
+            .chain(line.beta_y_vec[..n].iter())
+            .copied()
+            .chain(std::iter::once(0.0)),
+    );
+    let (eta_min, eta_max) = padded_range(line.eta_x_vec[..n].iter().copied());
+    let y_of = |value: f64, min: f64, max: f64| {
+        plot_bottom - (plot_bottom - plot_top) * (value - min) / (max - min)
+    };
+
+    svg.line(
+        [left, plot_bottom],
+        [WIDTH - right, plot_bottom],
+        "black",
+        1.0,
+    );
+    svg.line([left, plot_top], [left, plot_bottom], "black", 1.0);
+    svg.line(
+        [WIDTH - right, plot_top],
+        [WIDTH - right, plot_bottom],
+        "black",
+        1.0,
+    );
+    for tick in ticks(0.0, s_max) {
+        let x = x_of(tick);
+        svg.line([x, plot_bottom], [x, plot_bottom + 5.0], "black", 1.0);
+        svg.text([x, plot_bottom + 18.0], "middle", "black", &label(tick));
+    }
+    for tick in ticks(beta_min, beta_max) {
+        let y = y_of(tick, beta_min, beta_max);
+        svg.line([left - 5.0, y], [left, y], "black", 1.0);
+        svg.text([left - 8.0, y + 4.0], "end", "black", &label(tick));
+    }
+    for tick in ticks(eta_min, eta_max) {
+        let y = y_of(tick, eta_min, eta_max);
+        svg.line([WIDTH - right, y], [WIDTH - right + 5.0, y], "black", 1.0);
+        svg.text(
+            [WIDTH - right + 8.0, y + 4.0],
+            "start",
+            "green",
+            &label(tick),
+        );
+    }
+    svg.text([WIDTH / 2.0, HEIGHT - 10.0], "middle", "black", "s [m]");
+    svg.text([15.0, plot_top - 5.0], "start", "black", "β [m]");
+    svg.text([WIDTH - 15.0, plot_top - 5.0], "end", "green", "η [m]");
+
+    let curve = |values: &[f64], min: f64, max: f64| -> Vec<[f64; 2]> {
+        (0..n)
+            .map(|i| [x_of(s[i]), y_of(values[i], min, max)])
+            .collect()
+    };
+    svg.polyline(&curve(&line.beta_x_vec, beta_min, beta_max), "blue");
+    svg.polyline(&curve(&line.beta_y_vec, beta_min, beta_max), "red");
+    svg.polyline(&curve(&line.eta_x_vec, eta_min, eta_max), "green");
+    svg.text([left + 10.0, plot_top + 15.0], "start", "blue", "βx");
+    svg.text([left + 40.0, plot_top + 15.0], "start", "red", "βy");
+    svg.text([left + 70.0, plot_top + 15.0], "start", "green", "ηx");
+
+    svg.finish()
+}
+
+/// SVG floor plan of the full ring in the horizontal plane, with Z across the page and X up it,
+/// and each magnet drawn in the colour of its type.
+pub fn floor_plan_svg(line: &Line) -> String {
+    let mut svg = Svg::new();
+    let points = floor_coordinates(line, FloorPosition::default());
+    let [left, right, top, bottom] = MARGIN;
+
+    let (z_min, z_max) = padded_range(points.iter().map(|p| p.position.z));
+    let (x_min, x_max) = padded_range(points.iter().map(|p| p.position.x));
+    let scale =
+        ((WIDTH - left - right) / (z_max - z_min)).min((HEIGHT - top - bottom) / (x_max - x_min));
+    let z_centre = (z_min + z_max) / 2.0;
+    let x_centre = (x_min + x_max) / 2.0;
+    let to_page = |p: &FloorPosition| {
+        [
+            (left + WIDTH - right) / 2.0 + scale * (p.z - z_centre),
+            (top + HEIGHT - bottom) / 2.0 - scale * (p.x - x_centre),
+        ]
+    };
+
+    let path: Vec<[f64; 2]> = points.iter().map(|p| to_page(&p.position)).collect();
+    svg.polyline(&path, "black");
+    let n = line.line.len();
+    for (i, pair) in points.windows(2).enumerate() {
+        if let Some(colour) = element_colour(&element_type(&line.line[i % n])) {
+            svg.line(
+                to_page(&pair[0].position),
+                to_page(&pair[1].position),
+                colour,
+                5.0,
+            );
+        }
+    }
+
+    let bar = ticks(0.0, (WIDTH - left - right) / scale / 4.0)
+        .last()
+        .copied()
+        .unwrap_or(1.0);
+    let y = HEIGHT - bottom / 2.0;
+    svg.line([left, y], [left + scale * bar, y], "black", 2.0);
+    svg.text(
+        [left + scale * bar + 8.0, y + 4.0],
+        "start",
+        "black",
+        &format!("{} m", label(bar)),
+    );
+
+    svg.finish()
+}
@@ -0,0 +1,219 @@
+use crate::detuning::{Kick, field_slices, ring_kicks};
+use crate::*;
+use num_complex::Complex64;
+use std::collections::HashMap;
+
+/// Resonance driving terms h_jklmp, in the convention of Bengtsson where the Hamiltonian term is
+/// h_jklmp (2Jx)^((j+k)/2) (2Jy)^((l+m)/2) delta^p.  The octupole-like terms include the first
+/// order octupole contribution and the second order sextupole contribution.  The chromatic terms
+/// include the weak focusing of the bends.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DrivingTerms {
+    pub h21000: Complex64,
+    pub h30000: Complex64,
+    pub h10110: Complex64,
+    pub h10020: Complex64,
+    pub h10200: Complex64,
+    pub h20001: Complex64,
+    pub h00201: Complex64,
+    pub h10002: Complex64,
+    pub h22000: Complex64,
+    pub h11110: Complex64,
+    pub h00220: Complex64,
+    pub h31000: Complex64,
+    pub h40000: Complex64,
+    pub h20110: Complex64,
+    pub h11200: Complex64,
+    pub h20020: Complex64,
+    pub h20200: Complex64,
+    pub h00310: Complex64,
+    pub h00400: Complex64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DrivingTermPoint {
+    pub s: f64,
+    pub terms: DrivingTerms,
+}
+
+// Polynomial in the resonance basis (hx+, hx-, hy+, hy-), keyed by the exponents.
+type Polynomial = HashMap<[i32; 4], Complex64>;
+
+fn binomial(n: i32, k: i32) -> f64 {
+    (0..k).fold(1.0, |acc, i| acc * (n - i) as f64 / (i + 1) as f64)
+}
+
+// Adds the driving terms of a Hamiltonian term coeff x^p y^q at the kick.
+fn add_monomial(poly: &mut Polynomial, coeff: f64, p: i32, q: i32, kick: &Kick) {
+    let scale = -coeff * (kick.beta[0].powi(p) * kick.beta[1].powi(q)).sqrt() / 2.0_f64.powi(p + q);
+    for j in 0..=p {
+        for l in 0..=q {
+            let phase = (2 * j - p) as f64 * kick.phase[0] + (2 * l - q) as f64 * kick.phase[1];
+            *poly.entry([j, p - j, l, q - l]).or_default() +=
+                scale * binomial(p, j) * binomial(q, l) * Complex64::from_polar(1.0, phase);
+        }
+    }
+}
+
+// Poisson bracket in the resonance basis, where {hx+, hx-} = {hy+, hy-} = 2i.
+fn bracket(a: &Polynomial, b: &Polynomial) -> Polynomial {
+    let mut retval = Polynomial::new();
+    for (ea, ca) in a.iter() {
+        for (eb, cb) in b.iter() {
+            for plane in 0..2 {
+                let (plus, minus) = (2 * plane, 2 * plane + 1);
+                for (first, second, sign) in [(plus, minus, 1.0), (minus, plus, -1.0)] {
+                    if ea[first] == 0 || eb[second] == 0 {
+                        continue;
+                    }
+                    let mut exponents = [0; 4];
+                    for i in 0..4 {
+                        exponents[i] = ea[i] + eb[i];
+                    }
+                    exponents[first] -= 1;
+                    exponents[second] -= 1;
+                    let factor = (ea[first] * eb[second]) as f64;
+                    *retval.entry(exponents).or_default() +=
+                        Complex64::new(0.0, 2.0 * sign * factor) * ca * cb;
+                }
+            }
+        }
+    }
+    retval
+}
+
+fn add_assign(poly: &mut Polynomial, other: &Polynomial, scale: f64) {
+    for (exponents, coeff) in other.iter() {
+        *poly.entry(*exponents).or_default() += scale * coeff;
+    }
+}
+
+fn make_terms(first: &Polynomial, second: &Polynomial, chromatic: [Complex64; 3]) -> DrivingTerms {
+    let get =
+        |poly: &Polynomial, exponents: [i32; 4]| poly.get(&exponents).copied().unwrap_or_default();
+    DrivingTerms {
+        h21000: get(first, [2, 1, 0, 0]),
+        h30000: get(first, [3, 0, 0, 0]),
+        h10110: get(first, [1, 0, 1, 1]),
+        h10020: get(first, [1, 0, 0, 2]),
+        h10200: get(first, [1, 0, 2, 0]),
+        h20001: chromatic[0],
+        h00201: chromatic[1],
+        h10002: chromatic[2],
+        h22000: get(second, [2, 2, 0, 0]),
+        h11110: get(second, [1, 1, 1, 1]),
+        h00220: get(second, [0, 0, 2, 2]),
+        h31000: get(second, [3, 1, 0, 0]),
+        h40000: get(second, [4, 0, 0, 0]),
+        h20110: get(second, [2, 0, 1, 1]),
+        h11200: get(second, [1, 1, 2, 0]),
+        h20020: get(second, [2, 0, 0, 2]),
+        h20200: get(second, [2, 0, 2, 0]),
+        h00310: get(second, [0, 0, 3, 1]),
+        h00400: get(second, [0, 0, 4, 0]),
+    }
+}
+
+/// Build-up of the driving terms along the full ring, with one point at the start and one at the
+/// end of every element.  The last point holds the one-turn values.
+pub fn driving_term_build_up(line: &Line) -> Option<Vec<DrivingTermPoint>> {
+    let modes = line.normal_modes.as_ref()?;
+    let n_eles = line.line.len();
+    let n_ring = n_eles * line.periodicity;
+
+    let mut kicks: Vec<(usize, Kick)> = (2..4)
+        .flat_map(|index| {
+            ring_kicks(line, modes, index)
+                .into_iter()
+                .map(move |k| (index, k))
+        })
+        .collect();
+    kicks.sort_by(|a, b| {
+        a.1.element
+            .cmp(&b.1.element)
+            .then(a.1.phase[0].total_cmp(&b.1.phase[0]))
+    });
+
+    // The chromatic terms are linear in the fields, so they are summed over slices through each
+    // element of one cell and carried to the other cells by the phase advance.
+    let mut cell_chromatic = vec![[Complex64::default(); 3]; n_eles];
+    for slice in field_slices(line)? {
+        let optics = &slice.optics;
+        let (beta_x, beta_y) = (optics.beta[0][0], optics.beta[1][1]);
+        let [phase_x, phase_y, _] = optics.phase;
+        let eta = optics.eta[0];
+        let focusing = slice.h * slice.h + slice.k1;
+        let terms = &mut cell_chromatic[slice.element];
+        terms[0] += slice.length
+            * (focusing - 2.0 * slice.b3 * eta)
+            * beta_x
+            * Complex64::from_polar(0.125, 2.0 * phase_x);
+        terms[1] -= slice.length
+            * (slice.k1 - 2.0 * slice.b3 * eta)
+            * beta_y
+            * Complex64::from_polar(0.125, 2.0 * phase_y);
+        terms[2] += slice.length
+            * (focusing * eta - slice.b3 * eta * eta)
+            * beta_x.sqrt()
+            * Complex64::from_polar(0.5, phase_x);
+    }
+    let cell_phase = modes.optics[n_eles].phase;
+
+    let mut first = Polynomial::new();
+    let mut second = Polynomial::new();
+    let mut chromatic = [Complex64::default(); 3];
+    let mut kicks = kicks.into_iter().peekable();
+    let mut s = 0.0;
+    let mut retval = Vec::with_capacity(n_ring + 1);
+    retval.push(DrivingTermPoint {
+        s,
+        terms: DrivingTerms::default(),
+    });
+
+    for element in 0..n_ring {
+        let cell = (element / n_eles) as f64;
+        let rotations = [
+            Complex64::from_polar(1.0, 2.0 * cell * cell_phase[0]),
+            Complex64::from_polar(1.0, 2.0 * cell * cell_phase[1]),
+            Complex64::from_polar(1.0, cell * cell_phase[0]),
+        ];
+        for (term, (cell_term, rotation)) in chromatic.iter_mut().zip(
+            cell_chromatic[element % n_eles]
+                .iter()
+                .zip(rotations.iter()),
+        ) {
+            *term += cell_term * rotation;
+        }
+        while let Some((index, kick)) = kicks.next_if(|(_, k)| k.element == element) {
+            match index {
+                2 => {
+                    let b3 = kick.strength;
+                    let mut generator = Polynomial::new();
+                    add_monomial(&mut generator, b3 / 3.0, 3, 0, &kick);
+                    add_monomial(&mut generator, -b3, 1, 2, &kick);
+                    add_assign(&mut second, &bracket(&first, &generator), 0.5);
+                    add_assign(&mut first, &generator, 1.0);
+                }
+                _ => {
+                    let b4 = kick.strength;
+                    add_monomial(&mut second, b4 / 4.0, 4, 0, &kick);
+                    add_monomial(&mut second, -1.5 * b4, 2, 2, &kick);
+                    add_monomial(&mut second, b4 / 4.0, 0, 4, &kick);
+                }
+            }
+        }
+
+        s += line.line[element % n_eles].length;
+        retval.push(DrivingTermPoint {
+            s,
+            terms: make_terms(&first, &second, chromatic),
+        });
+    }
+
+    Some(retval)
+}
+
+/// One-turn resonance driving terms of the full ring.
+pub fn driving_terms(line: &Line) -> Option<DrivingTerms> {
+    driving_term_build_up(line)?.pop().map(|point| point.terms)
+}
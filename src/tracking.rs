@@ -0,0 +1,206 @@
+use crate::line::rf_wavenumber;
+use crate::*;
+use num_complex::Complex64;
+
+/// Phase space coordinates (x, px, y, py, z, delta), with z the path length excess.
+pub type Particle = [f64; 6];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntegratorOrder {
+    Second,
+    Fourth,
+}
+
+#[derive(Debug, Clone)]
+pub struct TrackingSettings {
+    pub order: IntegratorOrder,
+    /// Number of integration steps per magnet.  When `None` the Tracy `N` of each element is used.
+    pub slices: Option<usize>,
+    /// Particles with |x| or |y| beyond this are considered lost.
+    pub aperture: f64,
+    pub cavities: bool,
+}
+
+impl Default for TrackingSettings {
+    fn default() -> Self {
+        Self {
+            order: IntegratorOrder::Fourth,
+            slices: None,
+            aperture: 1.0,
+            cavities: true,
+        }
+    }
+}
+
+// Forest-Ruth coefficients for the fourth order drift-kick integrator.
+const FR_THETA: f64 = 1.3512071919596578;
+const FR_DRIFTS: [f64; 4] = [
+    FR_THETA / 2.0,
+    (1.0 - FR_THETA) / 2.0,
+    (1.0 - FR_THETA) / 2.0,
+    FR_THETA / 2.0,
+];
+const FR_KICKS: [f64; 3] = [FR_THETA, 1.0 - 2.0 * FR_THETA, FR_THETA];
+
+/// Symplectic tracking through a line, repeated `periodicity` times per turn.  Magnets are
+/// integrated with drift-kick splitting of the expanded Hamiltonian, where `k[0]` is the bending
+/// angle, `k[1]` the quadrupole gradient and `k[2]`, `k[3]` the Tracy sextupole and octupole
+/// strengths `b_3` and `b_4`.
+pub struct Tracker<'a> {
+    pub line: &'a Line,
+    pub settings: TrackingSettings,
+}
+
+impl<'a> Tracker<'a> {
+    pub fn new(line: &'a Line, settings: TrackingSettings) -> Self {
+        Self { line, settings }
+    }
+
+    /// Tracks a particle through one element, returning `false` if it was lost.
+    pub fn track_element(&self, ele: &Element, particle: &mut Particle) -> bool {
+        match element_type(ele) {
+            EleType::EleTypeMarker => {
+                particle[1] += ele.kick[0];
+                particle[3] += ele.kick[1];
+            }
+            EleType::EleTypeDrift => {
+                drift(particle, ele.length / 2.0);
+                particle[1] += ele.kick[0];
+                particle[3] += ele.kick[1];
+                drift(particle, ele.length / 2.0);
+            }
+            EleType::EleTypeCav => {
+                drift(particle, ele.length / 2.0);
+                if self.settings.cavities && ele._voltage != 0.0 {
+                    let wavenumber = rf_wavenumber(ele, self.line.total_length);
+                    let phi_s = self.line.synch_phase;
+                    particle[5] += ele._voltage / self.line.energy
+                        * ((phi_s - wavenumber * particle[4]).sin() - phi_s.sin());
+                }
+                drift(particle, ele.length / 2.0);
+            }
+            _ if ele.misalignment.is_zero() => self.integrate_magnet(ele, particle),
+            _ => {
+                ele.shift_in(particle);
+                ele.roll_in(particle);
+                self.integrate_magnet(ele, particle);
+                ele.roll_out(particle);
+                ele.roll_error_kick(particle);
+                ele.shift_out(particle);
+            }
+        }
+        self.is_alive(particle)
+    }
+
+    /// Tracks a particle once through the line, returning the index of the element where it was
+    /// lost, if it was.
+    pub fn track_line(&self, particle: &mut Particle) -> Option<usize> {
+        for (i, ele) in self.line.line.iter().enumerate() {
+            if !self.track_element(ele, particle) {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    /// Tracks a particle for `n_turns` turns of the full ring, returning the turn on which it was
+    /// lost, if it was.
+    pub fn track_turns(&self, particle: &mut Particle, n_turns: usize) -> Option<usize> {
+        self.track_turns_from(0, particle, n_turns)
+    }
+
+    /// As `track_turns`, but with each turn starting and ending at the entrance of element
+    /// `start` of the line.
+    pub fn track_turns_from(
+        &self,
+        start: usize,
+        particle: &mut Particle,
+        n_turns: usize,
+    ) -> Option<usize> {
+        let n_eles = self.line.line.len();
+        for turn in 0..n_turns {
+            for i in start..(start + n_eles * self.line.periodicity) {
+                if !self.track_element(&self.line.line[i % n_eles], particle) {
+                    return Some(turn);
+                }
+            }
+        }
+        None
+    }
+
+    /// As `track_turns`, but also returns the coordinates at the start of the line on every turn.
+    pub fn track_turns_recorded(
+        &self,
+        particle: &mut Particle,
+        n_turns: usize,
+    ) -> (Vec<Particle>, Option<usize>) {
+        let mut history = Vec::with_capacity(n_turns);
+        for turn in 0..n_turns {
+            history.push(*particle);
+            if self.track_turns(particle, 1).is_some() {
+                return (history, Some(turn));
+            }
+        }
+        (history, None)
+    }
+
+    fn is_alive(&self, particle: &Particle) -> bool {
+        particle.iter().all(|x| x.is_finite())
+            && particle[0].abs() < self.settings.aperture
+            && particle[2].abs() < self.settings.aperture
+    }
+
+    fn integrate_magnet(&self, ele: &Element, particle: &mut Particle) {
+        if ele.length == 0.0 {
+            // The strengths of a thin element are integrated.
+            let field = ele.nonlinear_field(Complex64::new(particle[0], particle[2]));
+            particle[1] += ele.kick[0] - field.re;
+            particle[3] += ele.kick[1] + field.im;
+            return;
+        }
+        let n_slices = self.settings.slices.unwrap_or(ele.n_slices).max(1);
+        let step = ele.length / n_slices as f64;
+
+        for _ in 0..n_slices {
+            match self.settings.order {
+                IntegratorOrder::Second => {
+                    drift(particle, step / 2.0);
+                    kick(particle, ele, step);
+                    drift(particle, step / 2.0);
+                }
+                IntegratorOrder::Fourth => {
+                    for (d, k) in FR_DRIFTS.iter().zip(FR_KICKS.iter()) {
+                        drift(particle, d * step);
+                        kick(particle, ele, k * step);
+                    }
+                    drift(particle, FR_DRIFTS[3] * step);
+                }
+            }
+        }
+    }
+}
+
+fn drift(particle: &mut Particle, length: f64) {
+    let p = 1.0 + particle[5];
+    particle[0] += length * particle[1] / p;
+    particle[2] += length * particle[3] / p;
+    particle[4] += length * (particle[1].powi(2) + particle[3].powi(2)) / (2.0 * p * p);
+}
+
+fn kick(particle: &mut Particle, ele: &Element, length: f64) {
+    let (x, y) = (particle[0], particle[2]);
+    let h = ele.k[0] / ele.length;
+    let (k1, b3, b4) = (ele.k[1], ele.k[2], ele.k[3]);
+
+    let fx = (h * h + k1) * x - h * particle[5]
+        + b3 * (x * x - y * y)
+        + b4 * (x.powi(3) - 3.0 * x * y * y);
+    let fy = -k1 * y - 2.0 * b3 * x * y - b4 * (3.0 * x * x * y - y.powi(3));
+
+    particle[1] -= length * (fx - ele.kick[0] / ele.length);
+    particle[3] -= length * (fy - ele.kick[1] / ele.length);
+    particle[4] += length * h * x;
+    if !ele.multipoles.is_zero() {
+        ele.multipole_kick(particle, length);
+    }
+}
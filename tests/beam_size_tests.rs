@@ -0,0 +1,63 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+
+#[test]
+fn test_emittances() {
+    let line = fodo_ring(false);
+    let [ex, ey] = line.emittances(VerticalEmittance::Coupling(0.1));
+    assert!((ex + ey - line.nat_emitt_x).abs() < 1e-12 * line.nat_emitt_x);
+    assert!((ey / ex - 0.1).abs() < 1e-12);
+    assert_eq!(
+        line.emittances(VerticalEmittance::Emittance(8e-12)),
+        [line.nat_emitt_x, 8e-12]
+    );
+}
+
+#[test]
+fn test_beam_sizes() {
+    let line = fodo_ring(false);
+    let vertical = VerticalEmittance::Emittance(1e-11);
+    let sizes = line.beam_sizes(vertical).unwrap();
+    assert_eq!(sizes.len(), line.line.len() + 1);
+    for (i, size) in sizes.iter().enumerate() {
+        let sigma_x2 =
+            line.nat_emitt_x * line.beta_x_vec[i] + (line.eta_x_vec[i] * line.e_spread).powi(2);
+        assert!((size.sigma_x.powi(2) - sigma_x2).abs() < 1e-6 * sigma_x2);
+        let sigma_y2 = 1e-11 * line.beta_y_vec[i];
+        assert!((size.sigma_y.powi(2) - sigma_y2).abs() < 1e-6 * sigma_y2);
+        assert!(size.sigma_px > 0.0 && size.sigma_py > 0.0);
+    }
+
+    // At a symmetry point of the FODO cell alpha vanishes, so the divergence is sqrt(e / beta)
+    // plus the dispersive term.
+    let optics = &line.normal_modes.as_ref().unwrap().optics[0];
+    let expected = (1e-11 / optics.beta[1][1]).sqrt();
+    assert!((sizes[0].sigma_py - expected).abs() < 1e-6 * expected);
+}
+
+#[test]
+fn test_optics_table_csv() {
+    let line = fodo_ring(false);
+    let vertical = VerticalEmittance::Coupling(0.01);
+    let csv = optics_table_csv(&line, 4, vertical).unwrap();
+    let table = optics_table(&line, 4).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), table.len() + 1);
+    assert!(lines[0].starts_with("s,element,name,beta_x"));
+    assert!(lines.iter().all(|l| l.split(',').count() == 15));
+
+    let columns: Vec<&str> = lines[1].split(',').collect();
+    assert_eq!(columns[2], "\"begin\"");
+    let size = table[0].beam_size(line.emittances(vertical), line.e_spread);
+    let sigma_x: f64 = columns[11].parse().unwrap();
+    assert!((sigma_x - size.sigma_x).abs() < 1e-9 * size.sigma_x);
+
+    let mut cell = fodo_cell(false);
+    cell[0].name = "begin, \"cell\"".to_string();
+    let line = Line::from_elements(cell, FODO_PERIODICITY, FODO_ENERGY);
+    let csv = optics_table_csv(&line, 4, vertical).unwrap();
+    let row = csv.lines().nth(1).unwrap();
+    assert!(row.contains(",\"begin, \"\"cell\"\"\","));
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+
+fn four_family_ring() -> Line {
+    let mut cell = fodo_cell(false);
+    cell.insert(2, make_sext("sf1".to_string(), 0.1, 10.0));
+    cell.insert(4, make_sext("sd1".to_string(), 0.1, -10.0));
+    cell.insert(7, make_sext("sd2".to_string(), 0.1, -10.0));
+    cell.insert(9, make_sext("sf2".to_string(), 0.1, 10.0));
+    Line::from_elements(cell, FODO_PERIODICITY, FODO_ENERGY)
+}
+
+fn tracking() -> TrackingSettings {
+    TrackingSettings {
+        slices: Some(10),
+        cavities: false,
+        ..Default::default()
+    }
+}
+
+fn settings(families: &[&str], driving_terms: Vec<(DrivingTerm, f64)>) -> ChromaticitySettings {
+    ChromaticitySettings {
+        families: families.iter().map(|f| f.to_string()).collect(),
+        target: [1.0, 0.5],
+        driving_terms,
+        tracking: tracking(),
+    }
+}
+
+// Linear chromaticity of the tracked tunes over a wider range of momentum offsets than the
+// correction uses.
+fn tracked_chromaticity(line: &Line) -> [f64; 2] {
+    let deltas = [-1e-3, -5e-4, 0.0, 5e-4, 1e-3];
+    let chromatic = chromatic_tunes(line, &deltas, &tracking()).unwrap();
+    [chromatic.coefficients[0][1], chromatic.coefficients[1][1]]
+}
+
+#[test]
+fn test_two_family_correction() {
+    let line = four_family_ring();
+    let result = correct_chromaticity(&line, &settings(&["sf1", "sd1"], vec![])).unwrap();
+
+    let xi = tracked_chromaticity(&result.line);
+    assert!((xi[0] - 1.0).abs() < 1e-3);
+    assert!((xi[1] - 0.5).abs() < 1e-3);
+    assert!(result.values[0] > 0.0 && result.values[1] < 0.0);
+
+    let sf2 = result.line.line.iter().find(|e| e.name == "sf2").unwrap();
+    assert_eq!(sf2.k[2], 10.0);
+
+    // The perturbative chromaticity from the sliced optics agrees with the tracked one.
+    let analytic = analytic_chromaticity(&result.line).unwrap();
+    assert!((analytic[0] - xi[0]).abs() < 1e-3);
+    assert!((analytic[1] - xi[1]).abs() < 1e-3);
+}
+
+#[test]
+fn test_correction_with_driving_terms() {
+    let line = four_family_ring();
+    let families = ["sf1", "sf2", "sd1", "sd2"];
+
+    let minimal = correct_chromaticity(&line, &settings(&families, vec![])).unwrap();
+    let constrained = correct_chromaticity(
+        &line,
+        &settings(&families, vec![(DrivingTerm::H21000, 1.0)]),
+    )
+    .unwrap();
+
+    for result in [&minimal, &constrained] {
+        let xi = tracked_chromaticity(&result.line);
+        assert!((xi[0] - 1.0).abs() < 1e-3);
+        assert!((xi[1] - 0.5).abs() < 1e-3);
+    }
+    let h21000 = |line: &Line| driving_terms(line).unwrap().h21000.norm();
+    assert!(h21000(&constrained.line) < 1e-3 * h21000(&minimal.line));
+    assert!((constrained.cost - h21000(&constrained.line).powi(2)).abs() < 1e-9);
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+
+#[test]
+fn test_off_momentum_orbit_follows_dispersion() {
+    let line = fodo_ring(false);
+    let delta = 1e-3;
+    let closed_orbit = find_closed_orbit(&line, OrbitDimension::FourD, delta).unwrap();
+
+    assert_eq!(closed_orbit.orbit.len(), line.eta_x_vec.len());
+    for (coords, eta) in closed_orbit.orbit.iter().zip(line.eta_x_vec.iter()) {
+        assert!((coords[0] - eta * delta).abs() < 1e-12);
+        assert!(coords[2].abs() < 1e-12);
+        assert_eq!(coords[5], delta);
+    }
+    assert!((&closed_orbit.total_matrix - &line.total_matrix)
+        .iter()
+        .all(|x| x.abs() < 1e-6));
+}
+
+#[test]
+fn test_corrector_orbit_is_closed() {
+    let mut cell = fodo_cell(true);
+    cell[0].kick = [1e-4, -2e-4];
+    let line = Line::from_elements(cell, FODO_PERIODICITY, FODO_ENERGY);
+
+    for dimension in [OrbitDimension::FourD, OrbitDimension::SixD] {
+        let closed_orbit = find_closed_orbit(&line, dimension, 0.0).unwrap();
+        let first = closed_orbit.orbit.first().unwrap();
+        let last = closed_orbit.orbit.last().unwrap();
+        let n_vars = if dimension == OrbitDimension::FourD { 4 } else { 6 };
+        for i in 0..n_vars {
+            assert!((first[i] - last[i]).abs() < 1e-12);
+        }
+        assert!(first[0].abs() > 1e-6);
+        assert!(first[2].abs() > 1e-6);
+    }
+
+    let six_d = find_closed_orbit(&line, OrbitDimension::SixD, 0.0).unwrap();
+    assert!(six_d.orbit[0][5] != 0.0);
+}
+
+#[test]
+fn test_six_d_orbit_needs_rf() {
+    let mut cell = fodo_cell(false);
+    cell[0].kick = [1e-4, 0.0];
+    let line = Line::from_elements(cell, FODO_PERIODICITY, FODO_ENERGY);
+    assert!(find_closed_orbit(&line, OrbitDimension::SixD, 0.0).is_err());
+}
+
+#[test]
+fn test_four_d_orbit_ignores_cavities() {
+    let delta = 1e-3;
+    let mut cell = fodo_cell(false);
+    let plain = find_closed_orbit(
+        &Line::from_elements(cell.clone(), FODO_PERIODICITY, FODO_ENERGY),
+        OrbitDimension::FourD,
+        delta,
+    )
+    .unwrap();
+    insert_after(
+        &mut cell,
+        "qd",
+        0,
+        make_cavity("cav".to_string(), 0.0, 500e6, 1.0e6, 0.0, 0.0),
+    );
+    let cavity = cell.iter().position(|ele| ele.name == "cav").unwrap();
+    let line = Line::from_elements(cell, FODO_PERIODICITY, FODO_ENERGY);
+    let closed_orbit = find_closed_orbit(&line, OrbitDimension::FourD, delta).unwrap();
+
+    // The cavity has no length, so without it the orbit matches that of the plain cell.
+    let mut orbit = closed_orbit.orbit.clone();
+    orbit.remove(cavity + 1);
+    assert_eq!(orbit.len(), plain.orbit.len());
+    for (a, b) in orbit.iter().zip(plain.orbit.iter()) {
+        for i in 0..6 {
+            assert!((a[i] - b[i]).abs() < 1e-12, "{a:?} != {b:?}");
+        }
+    }
+    for i in 0..4 {
+        assert!((orbit[0][i] - orbit.last().unwrap()[i]).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,79 @@
+#![allow(dead_code)]
+
+use rust_lattice_analysis::*;
+use std::f64::consts::PI;
+
+pub const FODO_PERIODICITY: usize = 16;
+pub const FODO_ENERGY: f64 = 3.0e9;
+
+pub fn fodo_cell(with_cavity: bool) -> Vec<Element> {
+    let angle = 2.0 * PI / (2 * FODO_PERIODICITY) as f64;
+    let mut line = vec![
+        make_marker("begin".to_string()),
+        make_quad("qf".to_string(), 0.15, 1.2),
+        make_drift("d1".to_string(), 0.5),
+        make_sbend("b".to_string(), 2.0, angle, 0.0),
+        make_drift("d1".to_string(), 0.5),
+        make_quad("qd".to_string(), 0.3, -1.2),
+        make_drift("d1".to_string(), 0.5),
+        make_sbend("b".to_string(), 2.0, angle, 0.0),
+        make_drift("d1".to_string(), 0.5),
+        make_quad("qf".to_string(), 0.15, 1.2),
+    ];
+    if with_cavity {
+        line.push(make_cavity("cav".to_string(), 0.0, 500e6, 1.0e6, 0.0, 0.0));
+    }
+    line
+}
+
+pub fn fodo_ring(with_cavity: bool) -> Line {
+    Line::from_elements(fodo_cell(with_cavity), FODO_PERIODICITY, FODO_ENERGY)
+}
+
+/// Inserts `element` after instance `instance`, counting from zero, of the element `name`.
+pub fn insert_after(cell: &mut Vec<Element>, name: &str, instance: usize, element: Element) {
+    let index = cell
+        .iter()
+        .enumerate()
+        .filter(|(_, ele)| ele.name == name)
+        .nth(instance)
+        .map(|(i, _)| i)
+        .unwrap();
+    cell.insert(index + 1, element);
+}
+
+/// A ring of FODO cells with markers for the BPMs `bpm1` and `bpm2`, the horizontal correctors
+/// `ch1` and `ch2` and either one or two vertical correctors `cv1` and `cv2` in each cell.
+pub fn instrumented_ring(periodicity: usize, n_v_correctors: usize) -> Line {
+    let mut cell = fodo_cell(false);
+    let mut markers = vec![
+        ("ch1", "qf", 0),
+        ("bpm1", "d1", 0),
+        ("cv1", "qd", 0),
+        ("ch2", "b", 1),
+        ("bpm2", "qf", 1),
+    ];
+    if n_v_correctors > 1 {
+        markers.push(("cv2", "d1", 2));
+    }
+    for (marker, name, instance) in markers {
+        insert_after(&mut cell, name, instance, make_marker(marker.to_string()));
+    }
+    Line::from_elements(cell, periodicity, FODO_ENERGY)
+}
+
+/// A ring of FODO cells with the sextupoles `sf` and `sd`, of strengths `b3` and `-1.5 b3`,
+/// after the focusing and defocusing quadrupoles and the octupole `o` after `sf`, all of the
+/// given length.
+pub fn nonlinear_ring(length: f64, b3: f64, b4: f64) -> Line {
+    let mut cell = fodo_cell(false);
+    insert_after(
+        &mut cell,
+        "qd",
+        0,
+        make_sext("sd".to_string(), length, -1.5 * b3),
+    );
+    insert_after(&mut cell, "qf", 0, make_oct("o".to_string(), length, b4));
+    insert_after(&mut cell, "qf", 0, make_sext("sf".to_string(), length, b3));
+    Line::from_elements(cell, FODO_PERIODICITY, FODO_ENERGY)
+}
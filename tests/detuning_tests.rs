@@ -0,0 +1,133 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+
+fn tracking() -> TrackingSettings {
+    TrackingSettings {
+        slices: Some(10),
+        cavities: false,
+        ..Default::default()
+    }
+}
+
+fn settings() -> DetuningSettings {
+    DetuningSettings {
+        x_max: 5e-4,
+        y_max: 5e-4,
+        n_amplitudes: 3,
+        n_turns: 256,
+        tracking: tracking(),
+    }
+}
+
+fn assert_close(a: f64, b: f64, tolerance: f64) {
+    assert!(((a - b) / b).abs() < tolerance, "{a} != {b}");
+}
+
+#[test]
+fn test_octupole_detuning() {
+    let line = nonlinear_ring(0.2, 0.0, 2000.0);
+    let analytic = analytic_detuning(&line).unwrap();
+    let tracked = tracked_detuning(&line, &settings()).unwrap();
+
+    assert!(analytic.dqx_djx > 0.0);
+    assert!(analytic.dqx_djy < 0.0);
+    assert_close(tracked.dqx_djx, analytic.dqx_djx, 0.02);
+    assert_close(tracked.dqx_djy, analytic.dqx_djy, 0.02);
+    assert_close(tracked.dqy_djy, analytic.dqy_djy, 0.02);
+}
+
+#[test]
+fn test_sextupole_detuning() {
+    let line = nonlinear_ring(0.2, 40.0, 0.0);
+    let analytic = analytic_detuning(&line).unwrap();
+    let tracked = tracked_detuning(&line, &settings()).unwrap();
+
+    assert_close(tracked.dqx_djx, analytic.dqx_djx, 0.02);
+    assert_close(tracked.dqx_djy, analytic.dqx_djy, 0.02);
+    assert_close(tracked.dqy_djy, analytic.dqy_djy, 0.02);
+}
+
+#[test]
+fn test_chromaticity() {
+    let deltas = [-2e-3, -1e-3, -5e-4, 0.0, 5e-4, 1e-3, 2e-3];
+    for b3 in [0.0, 40.0] {
+        let line = nonlinear_ring(0.2, b3, 0.0);
+        let modes = line.normal_modes.as_ref().unwrap();
+        let analytic = analytic_chromaticity(&line).unwrap();
+        let chromatic = chromatic_tunes(&line, &deltas, &tracking()).unwrap();
+
+        assert_eq!(chromatic.tunes.len(), deltas.len());
+        for (plane, xi) in analytic.iter().enumerate() {
+            assert!((chromatic.tunes[3][plane] - modes.tunes[plane]).abs() < 1e-6);
+            assert!((chromatic.coefficients[plane][0] - modes.tunes[plane]).abs() < 1e-3);
+            assert_close(chromatic.coefficients[plane][1], *xi, 1e-3);
+        }
+    }
+}
+
+#[test]
+fn test_second_order_chromaticity() {
+    // Small offsets keep the higher orders out of the quadratic term of the fit.
+    let deltas = [-2e-4, -1e-4, -5e-5, 0.0, 5e-5, 1e-4, 2e-4];
+    for (b3, b4) in [(0.0, 0.0), (40.0, 0.0), (-30.0, 0.0), (40.0, 2000.0)] {
+        let line = nonlinear_ring(0.2, b3, b4);
+        let analytic = analytic_second_order_chromaticity(&line).unwrap();
+        let chromatic = chromatic_tunes(&line, &deltas, &tracking()).unwrap();
+        for (plane, xi2) in analytic.iter().enumerate() {
+            assert_close(chromatic.coefficients[plane][2], *xi2, 0.01);
+        }
+    }
+}
+
+#[test]
+fn test_detuning_errors() {
+    let line = nonlinear_ring(0.2, 40.0, 0.0);
+    assert_eq!(
+        chromatic_tunes(&line, &[-1e-4, 0.0, 1e-4, 1e-4], &tracking()).unwrap_err(),
+        DetuningError::SingularFit
+    );
+    let lost = DetuningSettings {
+        x_max: 1.0,
+        ..settings()
+    };
+    assert_eq!(
+        tracked_detuning(&line, &lost).unwrap_err(),
+        DetuningError::ParticleLost
+    );
+}
+
+#[test]
+fn test_tracking_ignores_cavities() {
+    let deltas = [-2e-4, -1e-4, 0.0, 1e-4, 2e-4];
+    let with_rf = TrackingSettings {
+        cavities: true,
+        ..tracking()
+    };
+    let reference = chromatic_tunes(&fodo_ring(false), &deltas, &tracking()).unwrap();
+    let chromatic = chromatic_tunes(&fodo_ring(true), &deltas, &with_rf).unwrap();
+    for plane in 0..2 {
+        let (a, b) = (chromatic.coefficients[plane], reference.coefficients[plane]);
+        assert!((a[1] - b[1]).abs() < 1e-6, "{a:?} != {b:?}");
+    }
+
+    let ring = |with_cavity: bool| {
+        let mut cell = fodo_cell(with_cavity);
+        insert_after(&mut cell, "qf", 0, make_oct("o".to_string(), 0.2, 2000.0));
+        Line::from_elements(cell, FODO_PERIODICITY, FODO_ENERGY)
+    };
+    let reference = tracked_detuning(&ring(false), &settings()).unwrap();
+    let detuning = tracked_detuning(
+        &ring(true),
+        &DetuningSettings {
+            tracking: with_rf,
+            ..settings()
+        },
+    )
+    .unwrap();
+    // The horizontal actions differ slightly as they are taken with the 6D optics of the ring.
+    assert_close(detuning.dqx_djx, reference.dqx_djx, 1e-2);
+    assert_close(detuning.dqx_djy, reference.dqx_djy, 1e-6);
+    assert_close(detuning.dqy_djy, reference.dqy_djy, 1e-6);
+}
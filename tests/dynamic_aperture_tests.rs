@@ -0,0 +1,90 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+
+fn sextupole_ring() -> Line {
+    let mut cell = fodo_cell(false);
+    cell.insert(2, make_sext("sf".to_string(), 0.2, 40.0));
+    cell.insert(7, make_sext("sd".to_string(), 0.2, -60.0));
+    Line::from_elements(cell, FODO_PERIODICITY, FODO_ENERGY)
+}
+
+fn settings(grid: DaGrid) -> DaSettings {
+    DaSettings {
+        grid,
+        n_turns: 100,
+        deltas: vec![0.0, -0.005],
+        element: 3,
+        tracking: TrackingSettings::default(),
+    }
+}
+
+#[test]
+fn test_linear_lattice_survives() {
+    let line = fodo_ring(false);
+    let grid = DaGrid::Cartesian {
+        x_max: 0.01,
+        y_max: 0.005,
+        n_x: 5,
+        n_y: 4,
+    };
+    let da = dynamic_aperture(&line, &settings(grid)).unwrap();
+
+    assert_eq!(da.particles.len(), 2 * 5 * 4);
+    assert!(da.particles.iter().all(|p| p.loss_turn.is_none()));
+    for boundary in da.boundaries.iter() {
+        assert_eq!(boundary.points.len(), 5);
+        assert!(boundary.points.iter().all(|p| p[1] == 0.005));
+    }
+    for area in da.areas() {
+        assert!((area - 0.02 * 0.005).abs() < 1e-12);
+    }
+}
+
+#[test]
+fn test_sextupoles_limit_aperture() {
+    let line = sextupole_ring();
+    let grid = DaGrid::Polar {
+        x_max: 0.05,
+        y_max: 0.03,
+        n_angles: 7,
+        n_radii: 20,
+    };
+    let da = dynamic_aperture(&line, &settings(grid)).unwrap();
+
+    assert_eq!(da.particles.len(), 2 * 7 * 20);
+    assert!(da.particles.iter().any(|p| p.loss_turn.is_some()));
+
+    let areas = da.areas();
+    assert!(areas[0] > 0.0);
+    assert!(areas[0] < 0.5 * std::f64::consts::PI * 0.05 * 0.03);
+
+    assert!(da.boundaries.iter().all(|b| b.points.len() == 7));
+    assert!(
+        da.particles
+            .iter()
+            .filter_map(|p| p.loss_turn)
+            .all(|turn| turn < 100)
+    );
+}
+
+#[test]
+fn test_invalid_element_and_cavities() {
+    let line = fodo_ring(true);
+    let grid = DaGrid::Cartesian {
+        x_max: 0.01,
+        y_max: 0.005,
+        n_x: 3,
+        n_y: 2,
+    };
+    let mut settings = settings(grid);
+    let da = dynamic_aperture(&line, &settings).unwrap();
+    assert!(da.particles.iter().all(|p| p.loss_turn.is_none()));
+
+    settings.element = line.line.len();
+    assert_eq!(
+        dynamic_aperture(&line, &settings).err(),
+        Some(DaError::InvalidElement)
+    );
+}
@@ -1,3 +1,4 @@
+use ndarray::Array2;
 use rust_lattice_analysis::*;
 
 #[test]
@@ -18,18 +19,24 @@ fn test_synch_rad_integrals() {
     let filename = "lattices/max_4u_sp_jb_5.lat";
     let line = parse_lattice_from_tracy_file(filename).unwrap();
 
-    let line_matrix = get_line_matrix(&line);
-    let r56 = line_matrix[[4, 5]];
+    // I1 is the path length of the periodic dispersion orbit through the cell.  The cell is a
+    // mirror symmetric achromat, with no dispersion at its ends, so this is the R56 of the cell,
+    // whichever order the matrices are multiplied in.  Its bends have defocusing gradients, where
+    // the sign of R56 was already that of a longer path, so the value pinned before the sign of
+    // the focusing bends was fixed still holds.
+    let i_1 = Line::from_elements(line.clone(), 1, 3.0e9).synch_integrals[0];
+    let r56 = get_line_matrix(&line)[[4, 5]];
 
     let i_1_expected = 1.563960764e-03;
     let i_2_expected = 2.092111245e-02;
     let i_3_expected = 1.061231140e-03;
 
-    let i_1_calculated = r56;
+    let i_1_calculated = i_1;
     let i_2_calculated = synch_rad_integral_2(&line);
     let i_3_calculated = synch_rad_integral_3(&line);
 
     assert!((i_1_expected - i_1_calculated).abs() / i_1_expected.abs() < 1e-9);
+    assert!((i_1_expected - r56).abs() / i_1_expected.abs() < 1e-9);
     assert!((i_2_expected - i_2_calculated).abs() / i_2_expected.abs() < 1e-9);
     assert!((i_3_expected - i_3_calculated).abs() / i_3_expected.abs() < 1e-9);
 }
@@ -52,3 +59,46 @@ fn test_synch_rad_integrals() {
 //
 //     println!("{line_matrix:?}");
 // }
+
+// The transfer matrix with z flipped in sign, which makes it symplectic.
+fn is_symplectic(matrix: &Array2<f64>) -> bool {
+    let flipped = Array2::from_shape_fn((6, 6), |(i, j)| {
+        let sign = if (i == 4) != (j == 4) { -1.0 } else { 1.0 };
+        sign * matrix[[i, j]]
+    });
+    let s_mat = symplectic_form(6);
+    let error = flipped.t().dot(&s_mat).dot(&flipped) - &s_mat;
+    error.iter().all(|x| x.abs() < 1e-12)
+}
+
+#[test]
+fn test_sbend_matrix() {
+    let (length, angle) = (1.5, 0.1);
+    let h = angle / length;
+    // Focusing, pure, no net horizontal focusing and defocusing gradients.
+    for k1 in [0.5, 0.0, -h * h, -0.5] {
+        let r = make_sbend("b".to_string(), length, angle, k1).r_matrix;
+        assert!(is_symplectic(&r), "{k1}");
+        assert!(r[[4, 5]] > 0.0);
+    }
+    assert_eq!(
+        make_sbend("b".to_string(), length, angle, 0.0).r_matrix[[2, 3]],
+        length
+    );
+
+    // R56 is continuous across the branches at no net horizontal focusing.
+    let r56 = |k1: f64| make_sbend("b".to_string(), length, angle, k1).r_matrix[[4, 5]];
+    let limit = h * h * length.powi(3) / 6.0;
+    for k1 in [-h * h + 1e-6, -h * h, -h * h - 1e-6] {
+        assert!((r56(k1) - limit).abs() < 1e-6 * limit);
+    }
+}
+
+#[test]
+fn test_line_matrix_is_in_beam_order() {
+    let drift = make_drift("d".to_string(), 2.0);
+    let quad = make_quad("q".to_string(), 0.3, 1.2);
+    let line_matrix = get_line_matrix(&[drift.clone(), quad.clone()]);
+    let expected = quad.r_matrix.dot(&drift.r_matrix);
+    assert!((line_matrix - expected).iter().all(|x| x.abs() < 1e-15));
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+
+fn coupled_ring(skew: f64) -> Line {
+    let mut cell = fodo_cell(false);
+    let mut sq = make_marker("sq".to_string());
+    sq.set_parameter(ElementParameter::Skew(2), skew);
+    cell.insert(6, sq);
+    Line::from_elements(cell, FODO_PERIODICITY, FODO_ENERGY)
+}
+
+#[test]
+fn test_uncoupled_emittances() {
+    let line = fodo_ring(false);
+    let modes = mode_emittances(&line).unwrap();
+    assert!((modes.emittances[0] - line.nat_emitt_x).abs() < 1e-6 * line.nat_emitt_x);
+    assert!(modes.emittances[1].abs() < 1e-12 * line.nat_emitt_x);
+    assert!((modes.damping_partitions[0] - line.j_x).abs() < 1e-6);
+    assert!((modes.damping_partitions[1] - 1.0).abs() < 1e-12);
+}
+
+#[test]
+fn test_skew_quadrupole_shares_emittance() {
+    let line = fodo_ring(false);
+    let mut previous = 0.0;
+    for skew in [0.002, 0.005, 0.01] {
+        let modes = mode_emittances(&coupled_ring(skew)).unwrap();
+        assert!(modes.ratio() > previous);
+        previous = modes.ratio();
+
+        // The skew quadrupole shares the emittance and damping between the modes, keeping
+        // their sums close to the uncoupled values.
+        let total = modes.emittances[0] + modes.emittances[1];
+        assert!((total - line.nat_emitt_x).abs() < 0.05 * line.nat_emitt_x);
+        let partitions = modes.damping_partitions[0] + modes.damping_partitions[1];
+        assert!((partitions - line.j_x - 1.0).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn test_tune_emittance_ratio() {
+    let line = coupled_ring(0.0);
+    let tuning = tune_emittance_ratio(&line, &["sq"], 0.01, 0.1).unwrap();
+    assert!((tuning.emittances.ratio() - 0.01).abs() < 1e-6);
+    assert_eq!(tuning.strengths.len(), 1);
+    assert!(tuning.strengths[0].abs() > 0.0);
+    let sq = tuning.line.find("sq")[0];
+    assert_eq!(
+        tuning.line.line[sq].parameter(ElementParameter::Skew(2)),
+        tuning.strengths[0]
+    );
+
+    assert!(tune_emittance_ratio(&line, &["no_such_family"], 0.01, 0.1).is_err());
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+
+fn relative_difference(a: f64, b: f64) -> f64 {
+    ((a - b) / b).abs()
+}
+
+#[test]
+fn test_uncoupled_envelope() {
+    let line = fodo_ring(true);
+    let envelope = envelope(&line).unwrap();
+
+    assert!(relative_difference(envelope.emittances[0], line.nat_emitt_x) < 1e-2);
+    assert!(envelope.emittances[1].abs() < 1e-12 * line.nat_emitt_x);
+    assert!(relative_difference(envelope.energy_spread(), line.e_spread) < 1e-2);
+    assert!(relative_difference(envelope.damping_times[0], line.tau_x) < 1e-2);
+
+    // The damping partition numbers sum to four.
+    let rates: f64 = envelope.damping_times.iter().map(|tau| 1.0 / tau).sum();
+    assert!(relative_difference(rates, 4.0 / (line.tau_x * line.j_x)) < 1e-2);
+
+    let sizes = envelope.beam_sizes();
+    assert_eq!(sizes.len(), line.line.len() + 1);
+    for (i, size) in sizes.iter().enumerate() {
+        let betatron = envelope.emittances[0] * line.beta_x_vec[i];
+        let dispersive = (line.eta_x_vec[i] * envelope.energy_spread()).powi(2);
+        assert!(relative_difference(size[0].powi(2), betatron + dispersive) < 2e-2);
+    }
+    assert!(envelope.bunch_length() > 0.0);
+}
+
+#[test]
+fn test_coupled_envelope() {
+    let mut cell = fodo_cell(true);
+    let mut sq = make_marker("sq".to_string());
+    sq.set_parameter(ElementParameter::Skew(2), 0.005);
+    cell.insert(6, sq);
+    let line = Line::from_elements(cell, FODO_PERIODICITY, FODO_ENERGY);
+
+    let envelope = envelope(&line).unwrap();
+    let modes = mode_emittances(&line).unwrap();
+    for k in 0..2 {
+        assert!(relative_difference(envelope.emittances[k], modes.emittances[k]) < 2e-2);
+    }
+    assert!(envelope.beam_sizes().iter().all(|size| size[1] > 0.0));
+}
+
+#[test]
+fn test_envelope_needs_rf() {
+    assert!(envelope(&fodo_ring(false)).is_none());
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+
+#[test]
+fn test_glob_match() {
+    assert!(glob_match("q*", "qf"));
+    assert!(glob_match("q?", "qd"));
+    assert!(!glob_match("q?", "qf1"));
+    assert!(glob_match("s[1-3]", "s2"));
+    assert!(!glob_match("s[1-3]", "s4"));
+    assert!(glob_match("*bpm*", "m_bpm_1"));
+    assert!(!glob_match("d1", "d12"));
+}
+
+#[test]
+fn test_find_and_positions() {
+    let line = fodo_ring(false);
+    assert_eq!(line.find("qf"), vec![1, 9]);
+    assert_eq!(line.find("q*"), vec![1, 5, 9]);
+    assert_eq!(line.find("d1").len(), 4);
+    assert!(line.find("nothing").is_empty());
+
+    let families = line.families();
+    assert_eq!(families["b"], vec![3, 7]);
+    assert_eq!(families.len(), 5);
+
+    let s = line.s_positions();
+    assert_eq!(s.len(), line.line.len() + 1);
+    assert!((s.last().unwrap() - line.line_length).abs() < 1e-12);
+    assert_eq!(line.find_s("qd"), vec![0.15 + 0.5 + 2.0 + 0.5]);
+}
+
+#[test]
+fn test_family_modification() {
+    let mut line = fodo_ring(false);
+    let tune = line.normal_modes.as_ref().unwrap().tunes[0];
+
+    assert_eq!(
+        line.scale_family_parameter("qf", ElementParameter::K1, 1.05),
+        2
+    );
+    for &i in line.find("qf").iter() {
+        assert!((line.line[i].k[1] - 1.26).abs() < 1e-12);
+    }
+
+    let mut expected = fodo_cell(false);
+    expected[1] = make_quad("qf".to_string(), 0.15, 1.2 * 1.05);
+    expected[9] = make_quad("qf".to_string(), 0.15, 1.2 * 1.05);
+    let expected = Line::from_elements(expected, FODO_PERIODICITY, FODO_ENERGY);
+    assert_eq!(line.line[1].r_matrix, expected.line[1].r_matrix);
+    assert!(
+        (line.line_matrix.clone() - &expected.line_matrix)
+            .iter()
+            .all(|x| x.abs() < 1e-14)
+    );
+    assert!(line.normal_modes.as_ref().unwrap().tunes[0] > tune);
+
+    assert_eq!(
+        line.set_family_parameter("d*", ElementParameter::Length, 0.6),
+        4
+    );
+    assert!((line.line_length - (expected.line_length + 0.4)).abs() < 1e-12);
+    assert_eq!(
+        line.set_family_parameter("nothing", ElementParameter::K1, 1.0),
+        0
+    );
+}
+
+#[test]
+fn test_invalid_multipole_order() {
+    let mut line = fodo_ring(false);
+    for parameter in [
+        ElementParameter::Normal(0),
+        ElementParameter::Skew(MAX_MULTIPOLE_ORDER + 1),
+    ] {
+        assert!(!parameter.is_valid());
+        assert_eq!(line.set_family_parameter("q*", parameter, 1.0), 0);
+        assert_eq!(line.line[1].parameter(parameter), 0.0);
+        line.line[1].set_parameter(parameter, 1.0);
+    }
+    assert!(line.line[1].multipoles.is_zero());
+
+    let sextupole = ElementParameter::Normal(3);
+    assert!(sextupole.is_valid());
+    assert_eq!(line.set_family_parameter("qf", sextupole, 10.0), 2);
+    assert_eq!(line.line[1].parameter(sextupole), 10.0);
+}
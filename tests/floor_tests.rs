@@ -0,0 +1,50 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+use std::f64::consts::PI;
+
+#[test]
+fn test_ring_closes() {
+    let line = fodo_ring(false);
+    let start = FloorPosition {
+        x: 10.0,
+        y: 1.0,
+        z: -5.0,
+        theta: 0.3,
+        ..Default::default()
+    };
+    let points = floor_coordinates(&line, start);
+    assert_eq!(points.len(), line.line.len() * FODO_PERIODICITY + 1);
+    assert_eq!(points[0].position, start);
+    assert!((points.last().unwrap().s - line.total_length).abs() < 1e-9);
+    assert!((points.last().unwrap().position.theta - (0.3 - 2.0 * PI)).abs() < 1e-9);
+
+    let closure = floor_closure(&line, start);
+    assert!(closure.offset.iter().all(|x| x.abs() < 1e-9));
+    assert!(closure.angle.iter().all(|x| x.abs() < 1e-9));
+    assert!(points.iter().all(|p| (p.position.y - 1.0).abs() < 1e-12));
+
+    // Half way round the ring the beam travels backwards.
+    let half = &points[line.line.len() * FODO_PERIODICITY / 2].position;
+    assert!((half.theta - (0.3 - PI)).abs() < 1e-9);
+}
+
+#[test]
+fn test_single_bend_geometry() {
+    let angle = 0.1;
+    let line = Line::from_elements(
+        vec![make_sbend("b".to_string(), 2.0, angle, 0.0)],
+        1,
+        FODO_ENERGY,
+    );
+    let end = floor_coordinates(&line, FloorPosition::default())[1].position;
+    let rho = 2.0 / angle;
+    assert!((end.x - rho * (angle.cos() - 1.0)).abs() < 1e-12);
+    assert!((end.z - rho * angle.sin()).abs() < 1e-12);
+    assert!((end.theta + angle).abs() < 1e-12);
+
+    let closure = floor_closure(&line, FloorPosition::default());
+    assert!(closure.offset[0] < 0.0);
+    assert!((closure.angle[0] + angle).abs() < 1e-12);
+}
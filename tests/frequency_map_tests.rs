@@ -0,0 +1,103 @@
+mod common;
+
+use common::*;
+use num_complex::Complex64;
+use rust_lattice_analysis::*;
+use std::f64::consts::PI;
+
+fn settings(x_max: f64, n_x: usize) -> FmaSettings {
+    FmaSettings {
+        grid: DaGrid::Cartesian {
+            x_max,
+            y_max: 1e-4,
+            n_x,
+            n_y: 1,
+        },
+        n_turns: 256,
+        delta: 0.0,
+        element: 0,
+        tracking: TrackingSettings {
+            slices: Some(10),
+            cavities: false,
+            ..Default::default()
+        },
+    }
+}
+
+#[test]
+fn test_naff_tune() {
+    let tune = 0.2371234;
+    let signal: Vec<Complex64> = (0..512)
+        .map(|n| {
+            let phase = 2.0 * PI * tune * n as f64;
+            let harmonic = 2.0 * phase + 0.3;
+            Complex64::new(phase.cos(), phase.sin())
+                + 0.05 * Complex64::new(harmonic.cos(), harmonic.sin())
+        })
+        .collect();
+    assert!((naff_tune(&signal) - tune).abs() < 1e-7);
+}
+
+#[test]
+fn test_naff_close_lines() {
+    // A second line two bins from the tune pulls the peak of the spectrum by about 2e-4.
+    let (tune, other) = (0.2371234, 0.245);
+    let signal: Vec<Complex64> = (0..256)
+        .map(|n| {
+            Complex64::from_polar(1.0, 2.0 * PI * tune * n as f64)
+                + Complex64::from_polar(0.5, 2.0 * PI * other * n as f64 + 0.7)
+        })
+        .collect();
+    assert!((naff_tune(&signal) - tune).abs() < 1e-8);
+
+    let lines = naff(&signal, 2);
+    assert_eq!(lines.len(), 2);
+    assert!((lines[1].0 - other).abs() < 1e-8);
+    assert!((lines[1].1.norm() - 0.5).abs() < 1e-3);
+}
+
+#[test]
+fn test_small_amplitude_tunes() {
+    let line = fodo_ring(false);
+    let fma = frequency_map(&line, &settings(1e-5, 2)).unwrap();
+
+    assert_eq!(fma.points.len(), 2);
+    let tunes = fma.points[1].tunes.as_ref().unwrap();
+    assert!((tunes.qx - line.x_frac_tune).abs() < 1e-6);
+    assert!((tunes.qy - line.y_frac_tune).abs() < 1e-6);
+    assert!(tunes.diffusion < -6.0);
+}
+
+#[test]
+fn test_amplitude_dependent_tunes() {
+    let mut cell = fodo_cell(false);
+    cell.insert(3, make_oct("o".to_string(), 0.2, 2000.0));
+    let line = Line::from_elements(cell, FODO_PERIODICITY, FODO_ENERGY);
+    let fma = frequency_map(&line, &settings(2e-3, 5)).unwrap();
+
+    let small = fma.points[3].tunes.as_ref().unwrap();
+    let large = fma.points[4].tunes.as_ref().unwrap();
+    assert!((large.qx - small.qx).abs() > 1e-4);
+
+    let csv = fma.to_csv();
+    assert_eq!(csv.lines().count(), 6);
+}
+
+#[test]
+fn test_invalid_element_and_cavities() {
+    let line = fodo_ring(true);
+    let mut settings = settings(1e-5, 2);
+    settings.tracking.cavities = true;
+    let fma = frequency_map(&line, &settings).unwrap();
+    let tunes = fma.points[1].tunes.as_ref().unwrap();
+    // With the cavities on the tunes are those of the 6D normal modes.
+    let modes = line.normal_modes.as_ref().unwrap();
+    assert!((tunes.qx - modes.frac_tunes[0]).abs() < 1e-5);
+    assert!((tunes.qy - modes.frac_tunes[1]).abs() < 1e-5);
+
+    settings.element = line.line.len();
+    assert_eq!(
+        frequency_map(&line, &settings).err(),
+        Some(FmaError::InvalidElement)
+    );
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+
+const ENERGY: f64 = 1e9;
+
+fn ring() -> Line {
+    Line::from_elements(fodo_cell(true), FODO_PERIODICITY, ENERGY)
+}
+
+#[test]
+fn test_growth_rates_scale_with_bunch_population() {
+    let line = ring();
+    let optics = optics_table(&line, 4).unwrap();
+    let beam = BeamParameters {
+        emittances: line.emittances(VerticalEmittance::Coupling(0.01)),
+        e_spread: line.e_spread,
+        bunch_length: 5e-3,
+    };
+    let rates = ibs_growth_rates(&line, &optics, &beam, 1e10, Some(15.0)).unwrap();
+    let doubled = ibs_growth_rates(&line, &optics, &beam, 2e10, Some(15.0)).unwrap();
+    assert!(rates.longitudinal > 0.0 && rates.horizontal > 0.0);
+    assert!((doubled.longitudinal / rates.longitudinal - 2.0).abs() < 1e-12);
+    assert!((doubled.horizontal / rates.horizontal - 2.0).abs() < 1e-12);
+    // Without vertical dispersion there is no vertical growth.
+    assert!(rates.vertical.abs() < 1e-12 * rates.horizontal);
+}
+
+#[test]
+fn test_growth_rates_match_reference() {
+    // A uniform ring with beta_x = 10 m, beta_y = 5 m and eta_x = 0.1 m, where the reference
+    // rate of 84.784 1/s comes from Bane's formula with the auxiliary function g evaluated by
+    // numerical integration rather than the fit, which is good to about 1% here.
+    let line = ring();
+    let point = |s: f64| OpticsPoint {
+        s,
+        element: 0,
+        optics: ModeOptics {
+            beta: [[10.0, 0.0, 0.0], [0.0, 5.0, 0.0], [0.0; 3]],
+            alpha: [[0.0; 3]; 3],
+            phase: [0.0; 3],
+            eta: [0.1, 0.0, 0.0, 0.0],
+        },
+    };
+    let beam = BeamParameters {
+        emittances: [1e-9, 1e-11],
+        e_spread: 1e-3,
+        bunch_length: 5e-3,
+    };
+    let optics = [point(0.0), point(1.0)];
+    let rates = ibs_growth_rates(&line, &optics, &beam, 1e10, Some(10.0)).unwrap();
+    assert!((rates.longitudinal / 84.784 - 1.0).abs() < 0.015);
+    // sigma_p^2 H_x / epsilon_x is one for this beam.
+    assert!((rates.horizontal / rates.longitudinal - 1.0).abs() < 1e-12);
+    assert_eq!(rates.vertical, 0.0);
+
+    assert_eq!(
+        ibs_growth_rates(&line, &[], &beam, 1e10, None),
+        Err(IbsError::InvalidOptics)
+    );
+    assert_eq!(
+        ibs_growth_rates(&line, &[point(0.0), point(0.0)], &beam, 1e10, None),
+        Err(IbsError::InvalidOptics)
+    );
+    let cold = BeamParameters {
+        e_spread: 0.0,
+        ..beam
+    };
+    assert_eq!(
+        ibs_growth_rates(&line, &optics, &cold, 1e10, None),
+        Err(IbsError::InvalidBeam)
+    );
+}
+
+#[test]
+fn test_equilibrium() {
+    let line = ring();
+    let zero = ibs_equilibrium(
+        &line,
+        &IbsSettings {
+            bunch_charge: 0.0,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(zero.beam.emittances[0], zero.zero_current.emittances[0]);
+    assert_eq!(zero.beam.e_spread, line.e_spread);
+    assert!((zero.beam.emittances[0] * 1.01 / line.nat_emitt_x - 1.0).abs() < 1e-12);
+
+    let mut previous = zero.beam;
+    for bunch_charge in [1e-10, 1e-9, 5e-9] {
+        let result = ibs_equilibrium(
+            &line,
+            &IbsSettings {
+                bunch_charge,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let (beam, initial, rates) = (result.beam, result.zero_current, result.growth_rates);
+        assert!(beam.emittances[0] > previous.emittances[0]);
+        assert!(beam.e_spread > previous.e_spread);
+        previous = beam;
+
+        // The equilibrium balances the growth rates against the radiation damping.
+        let tau_x = line.tau_x;
+        let tau_p = tau_x * line.j_x / (3.0 - line.j_x);
+        let ex = initial.emittances[0] / (1.0 - tau_x * rates.horizontal);
+        assert!((beam.emittances[0] / ex - 1.0).abs() < 1e-8);
+        let e_spread = initial.e_spread / (1.0 - tau_p * rates.longitudinal).sqrt();
+        assert!((beam.e_spread / e_spread - 1.0).abs() < 1e-8);
+        assert!((beam.emittances[1] / beam.emittances[0] - 0.01).abs() < 1e-12);
+        let lengthening = beam.bunch_length / initial.bunch_length;
+        assert!((lengthening - beam.e_spread / initial.e_spread).abs() < 1e-8);
+    }
+}
+
+#[test]
+fn test_equilibrium_needs_rf() {
+    let line = Line::from_elements(fodo_cell(false), FODO_PERIODICITY, ENERGY);
+    assert!(ibs_equilibrium(&line, &IbsSettings::default()).is_err());
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+use std::collections::HashMap;
+
+const CONTENTS: &str = "kq = 1.2;
+    kd = -kq;
+    n_bends = 32;
+    d1: Drift, L = 0.5;
+    qf: Quadrupole, L = 0.15, B_2 = kq;
+    qd: Quadrupole, L = 0.3, B_2 = kd;
+    b: Bending, L = 2.0, Phi = 360.0 / n_bends;
+    cell: LINE = (qf, d1, b, d1, qd, d1, b, d1, qf);
+    USE: cell;";
+
+#[test]
+fn test_set_variable() {
+    let mut lattice = Lattice::from_tracy(CONTENTS, FODO_PERIODICITY, FODO_ENERGY).unwrap();
+    assert_eq!(lattice.variable("kd"), Some(-1.2));
+    let tune = lattice.line().normal_modes.as_ref().unwrap().tunes[0];
+
+    lattice.set_variable("kq", 1.3).unwrap();
+    assert_eq!(lattice.variable("kd"), Some(-1.3));
+    assert_eq!(lattice.elements()[0].k[1], 1.3);
+    assert_eq!(lattice.elements()[4].k[1], -1.3);
+    assert_eq!(lattice.elements()[8].k[1], 1.3);
+
+    let overrides = HashMap::from([("kq".to_string(), 1.3)]);
+    let (elements, _) = parse_tracy_lattice(CONTENTS, &overrides).unwrap();
+    let expected = Line::from_elements(elements, FODO_PERIODICITY, FODO_ENERGY);
+    let new_tune = lattice.line().normal_modes.as_ref().unwrap().tunes[0];
+    assert!(new_tune > tune);
+    assert_eq!(new_tune, expected.normal_modes.as_ref().unwrap().tunes[0]);
+
+    assert!(lattice.set_variable("not_a_variable", 1.0).is_err());
+}
+
+#[test]
+fn test_set_parameter() {
+    let mut lattice = Lattice::from_tracy(CONTENTS, FODO_PERIODICITY, FODO_ENERGY).unwrap();
+    lattice.set_parameter("d1", "L", 0.6).unwrap();
+    lattice.set_parameter("b", "Phi", 360.0 / 64.0).unwrap();
+
+    let line = lattice.line();
+    assert_eq!(line.line[1].length, 0.6);
+    assert_eq!(line.line[1].r_matrix[[0, 1]], 0.6);
+    assert!((line.total_angle - 180.0).abs() < 1e-9);
+
+    // Later changes to the variables leave the fixed parameters alone.
+    lattice.set_variable("n_bends", 16.0).unwrap();
+    assert!((lattice.line().total_angle - 180.0).abs() < 1e-9);
+    assert!(lattice.set_parameter("not_an_element", "L", 1.0).is_err());
+}
+
+#[test]
+fn test_unsupported_element_is_an_error() {
+    let mut lattice = Lattice::from_tracy(CONTENTS, FODO_PERIODICITY, FODO_ENERGY).unwrap();
+    assert!(lattice.set_parameter("qf", "Phi", 45.0).is_err());
+    lattice.set_variable("kq", 1.3).unwrap();
+    assert_eq!(lattice.elements()[0].k[1], 1.3);
+
+    let contents = CONTENTS.replace("d1: Drift", "d1: Solenoid");
+    assert!(parse_tracy_lattice(&contents, &HashMap::new()).is_err());
+}
+
+#[test]
+fn test_failed_change_leaves_the_lattice() {
+    // qd becomes a skew quadrupole, which is not supported, as soon as kq changes.
+    let contents = CONTENTS.replace("B_2 = kd;", "B_2 = kd, Phi = kq - 1.2;");
+    let mut lattice = Lattice::from_tracy(&contents, FODO_PERIODICITY, FODO_ENERGY).unwrap();
+    assert!(lattice.set_variable("kq", 1.3).is_err());
+    assert_eq!(lattice.variable("kq"), Some(1.2));
+    assert_eq!(lattice.variable("kd"), Some(-1.2));
+    assert_eq!(lattice.elements()[0].k[1], 1.2);
+
+    // The rejected value of kq is not kept for later changes.
+    lattice.set_variable("n_bends", 16.0).unwrap();
+    assert_eq!(lattice.variable("kq"), Some(1.2));
+    assert!((lattice.line().total_angle - 720.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_invalid_input_is_an_error() {
+    let undefined = CONTENTS.replace("B_2 = kd;", "B_2 = k_undefined;");
+    assert!(parse_tracy_lattice(&undefined, &HashMap::new()).is_err());
+
+    let assignment = CONTENTS.replace("kd = -kq;", "kd = -k_undefined;");
+    assert!(parse_tracy_lattice(&assignment, &HashMap::new()).is_err());
+
+    let missing = CONTENTS.replace("(qf, d1,", "(qf, d2,");
+    assert!(parse_tracy_lattice(&missing, &HashMap::new()).is_err());
+
+    assert!(parse_lattice_from_tracy_file("lattices/not_a_file.lat").is_err());
+}
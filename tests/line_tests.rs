@@ -1,3 +1,7 @@
+mod common;
+
+use common::*;
+use ndarray::Array2;
 use rust_lattice_analysis::*;
 
 #[test]
@@ -7,6 +11,8 @@ fn max_lattice_test() {
     let file_path = "lattices/max_4u_sp_jb_5.lat";
     let line: Line = Line::new(file_path, periodicity, kinetic_energy).unwrap();
 
+    // I1 is unchanged by the dispersion orbit definition for this achromat, see
+    // test_synch_rad_integrals.
     let si_exp = [
         1.563960764e-3,
         2.092111245e-2,
@@ -26,3 +32,85 @@ fn max_lattice_test() {
     assert!(((emit_exp - line.nat_emitt_x) / emit_exp).abs() < 1e-9);
     assert!(((espread_exp - line.e_spread) / espread_exp).abs() < 1e-9);
 }
+
+#[test]
+fn test_transfer_matrix() {
+    let line = fodo_ring(true);
+    let n = line.line.len();
+    let close = |a: &Array2<f64>, b: &Array2<f64>| (a - b).iter().all(|x| x.abs() < 1e-9);
+
+    assert!(close(&line.transfer_matrix(3, 3).unwrap(), &Array2::eye(6)));
+    assert!(close(
+        &line.transfer_matrix(0, n).unwrap(),
+        &line.line_matrix
+    ));
+    assert!(close(
+        &line.transfer_matrix(2 * n + 4, 3 * n + 4).unwrap(),
+        &line.transfer_matrix(4, n + 4).unwrap()
+    ));
+
+    let forward = line.transfer_matrix(0, 5 * n + 5).unwrap();
+    let back = line.transfer_matrix(5 * n + 5, 0).unwrap();
+    assert!(close(&back.dot(&forward), &line.total_matrix));
+
+    let by_name = line.transfer_matrix("qf", "qd").unwrap();
+    assert!(close(&by_name, &line.transfer_matrix(1, 5).unwrap()));
+    assert!(line.transfer_matrix("nothing", 0).is_none());
+
+    let second_qf = ElementLocation::Instance("qf".to_string(), 1);
+    let qd_in_third_cell = ElementLocation::Instance("qd".to_string(), 2);
+    assert!(close(
+        &line.transfer_matrix(second_qf, qd_in_third_cell).unwrap(),
+        &line.transfer_matrix(9, 2 * n + 5).unwrap()
+    ));
+    let last = ElementLocation::Instance("qf".to_string(), 2 * FODO_PERIODICITY - 1);
+    assert!(close(
+        &line.transfer_matrix(last, 0).unwrap(),
+        &line.transfer_matrix(FODO_PERIODICITY * n - 2, 0).unwrap()
+    ));
+    let beyond = ElementLocation::Instance("qf".to_string(), 2 * FODO_PERIODICITY);
+    assert!(line.transfer_matrix(beyond, 0).is_none());
+    assert!(line.transfer_matrix(0, n * FODO_PERIODICITY).is_none());
+}
+
+#[test]
+fn test_momentum_compaction() {
+    // The same ring with its cells starting part way through the first bend, where eta' is not
+    // zero.
+    let mut cell = fodo_cell(false);
+    let angle = cell[3].k[0];
+    cell[3] = make_sbend("b".to_string(), 0.5, angle / 4.0, 0.0);
+    let mut rotated = vec![make_sbend("b".to_string(), 1.5, 0.75 * angle, 0.0)];
+    rotated.extend(cell[4..].iter().cloned());
+    rotated.extend(cell[..4].iter().cloned());
+
+    let reference = fodo_ring(false).mom_compact;
+    // The RF focusing written into the cavities does not enter the momentum compaction.
+    assert!((fodo_ring(true).mom_compact / reference - 1.0).abs() < 1e-12);
+    for elements in [fodo_cell(false), rotated] {
+        let line = Line::from_elements(elements, FODO_PERIODICITY, FODO_ENERGY);
+        assert!((line.mom_compact / reference - 1.0).abs() < 1e-12);
+
+        // The path length of the dispersion orbit around the ring, from the one-turn matrix.
+        // R56 alone misses the terms in the dispersion at the start of the ring.
+        let m = &line.total_matrix;
+        let det = (1.0 - m[[0, 0]]) * (1.0 - m[[1, 1]]) - m[[0, 1]] * m[[1, 0]];
+        let eta = ((1.0 - m[[1, 1]]) * m[[0, 5]] + m[[0, 1]] * m[[1, 5]]) / det;
+        let eta_p = (m[[1, 0]] * m[[0, 5]] + (1.0 - m[[0, 0]]) * m[[1, 5]]) / det;
+        let path_length = m[[4, 5]] + m[[4, 0]] * eta + m[[4, 1]] * eta_p;
+        assert!((line.mom_compact * line.total_length / path_length - 1.0).abs() < 1e-12);
+        assert!((line.mom_compact * line.total_length / m[[4, 5]] - 1.0).abs() > 1e-2);
+
+        // The integral of eta h through the bends.
+        let optics = optics_table(&line, 200).unwrap();
+        let mut i1 = 0.0;
+        for pair in optics.windows(2) {
+            let ele = &line.line[pair[0].element];
+            if ele.k[0] != 0.0 {
+                let eta = (pair[0].optics.eta[0] + pair[1].optics.eta[0]) / 2.0;
+                i1 += ele.k[0] / ele.length * eta * (pair[1].s - pair[0].s);
+            }
+        }
+        assert!((line.synch_integrals[0] / i1 - 1.0).abs() < 1e-5);
+    }
+}
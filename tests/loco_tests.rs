@@ -0,0 +1,95 @@
+mod common;
+
+use common::*;
+use ndarray::{Array2, array};
+use rust_lattice_analysis::*;
+use std::fs;
+
+const PERIODICITY: usize = 4;
+
+// Response seen through BPMs with the given gains and rolls.
+fn with_bpm_errors(response: &Array2<f64>, gains: &[[f64; 2]], rolls: &[f64]) -> Array2<f64> {
+    let n_bpms = gains.len();
+    let mut retval = response.clone();
+    for j in 0..response.ncols() {
+        for i in 0..n_bpms {
+            let (x, y) = (response[[i, j]], response[[n_bpms + i, j]]);
+            let (s, c) = rolls[i].sin_cos();
+            retval[[i, j]] = gains[i][0] * (c * x + s * y);
+            retval[[n_bpms + i, j]] = gains[i][1] * (-s * x + c * y);
+        }
+    }
+    retval
+}
+
+#[test]
+fn test_response_matrix_file() {
+    let matrix = array![[1.5, -2.0e-3, 0.0], [3.25, 4.0, -1.0e2]];
+    let file_path = std::env::temp_dir().join("rust_lattice_analysis_loco.txt");
+    fs::write(
+        &file_path,
+        format!("# measured\n\n{}", format_response_matrix(&matrix)),
+    )
+    .unwrap();
+    let read = read_response_matrix(file_path.to_str().unwrap()).unwrap();
+    assert_eq!(read, matrix);
+
+    assert!(parse_response_matrix("1 2\n3\n").is_err());
+    assert!(parse_response_matrix("1 x\n").is_err());
+    assert!(read_response_matrix("no/such/file.txt").is_err());
+}
+
+#[test]
+fn test_fit_recovers_lattice_and_bpm_errors() {
+    let line = instrumented_ring(PERIODICITY, 1);
+    let settings = LocoSettings {
+        fit_corrector_gains: false,
+        ..Default::default()
+    };
+    let errors = ErrorSettings {
+        errors: vec![ErrorSpec {
+            pattern: "q*".to_string(),
+            parameter: ElementParameter::K1,
+            rms: 5e-3,
+            relative: true,
+        }],
+        cutoff: 2.0,
+    };
+    let truth = apply_errors(&line, &errors, 3);
+    let n_bpms = 2 * PERIODICITY;
+    let gains: Vec<[f64; 2]> = (0..n_bpms)
+        .map(|i| [1.0 + 0.01 * i as f64, 1.0 - 0.005 * i as f64])
+        .collect();
+    let rolls: Vec<f64> = (0..n_bpms).map(|i| 1e-3 * (i as f64 - 3.0)).collect();
+    let measured = with_bpm_errors(
+        &coupled_orbit_response(&truth, &settings.orm).unwrap(),
+        &gains,
+        &rolls,
+    );
+
+    let result = loco_fit(&line, &measured, &settings).unwrap();
+    assert_eq!(result.quadrupoles.len(), 3 * PERIODICITY);
+    assert!(result.initial_rms > 1e-3);
+    assert!(result.rms < 1e-6 * result.initial_rms);
+    for i in 0..n_bpms {
+        assert!((result.bpm_rolls[i] - rolls[i]).abs() < 1e-6);
+        for (fitted, gain) in result.bpm_gains[i].iter().zip(gains[i].iter()) {
+            assert!((fitted - gain).abs() < 1e-6);
+        }
+    }
+    assert!(result.corrector_gains.iter().all(|&c| c == 1.0));
+
+    // The fitted gradients give back the optics of the ring with errors.
+    let fitted = result.apply(&line);
+    let tunes = |line: &Line| line.normal_modes.as_ref().unwrap().tunes;
+    for plane in 0..2 {
+        assert!((tunes(&fitted)[plane] - tunes(&truth)[plane]).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn test_fit_rejects_mismatched_measurement() {
+    let line = instrumented_ring(PERIODICITY, 1);
+    let measured = Array2::zeros((3, 3));
+    assert!(loco_fit(&line, &measured, &LocoSettings::default()).is_err());
+}
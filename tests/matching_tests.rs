@@ -0,0 +1,128 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+
+fn quad_variables() -> Vec<MatchVariable> {
+    ["qf", "qd"]
+        .iter()
+        .map(|name| MatchVariable {
+            knob: Knob::Element {
+                name: name.to_string(),
+                index: 1,
+            },
+            lower: -5.0,
+            upper: 5.0,
+        })
+        .collect()
+}
+
+fn tune_constraints(qx: f64, qy: f64) -> Vec<MatchConstraint> {
+    vec![
+        MatchConstraint {
+            target: MatchTarget::FracTune(0),
+            value: qx,
+            weight: 1.0,
+        },
+        MatchConstraint {
+            target: MatchTarget::FracTune(1),
+            value: qy,
+            weight: 1.0,
+        },
+    ]
+}
+
+#[test]
+fn test_match_tunes() {
+    let line = fodo_ring(false);
+    let result = match_line(&line, &quad_variables(), &tune_constraints(0.45, 0.2)).unwrap();
+
+    let modes = result.line.normal_modes.as_ref().unwrap();
+    assert!((modes.frac_tunes[0] - 0.45).abs() < 1e-8);
+    assert!((modes.frac_tunes[1] - 0.2).abs() < 1e-8);
+    assert!(result.cost < 1e-16);
+    let qd = result
+        .line
+        .line
+        .iter()
+        .find(|ele| ele.name == "qd")
+        .unwrap();
+    assert_eq!(qd.k[1], result.values[1]);
+}
+
+#[test]
+fn test_match_beta_with_bounds() {
+    let line = fodo_ring(false);
+    let beta = MatchConstraint {
+        target: MatchTarget::Beta {
+            element: "begin".to_string(),
+            plane: 0,
+        },
+        value: 12.0,
+        weight: 1.0,
+    };
+
+    let result = match_line(&line, &quad_variables(), std::slice::from_ref(&beta)).unwrap();
+    assert!((target_value(&result.line, &beta.target).unwrap() - 12.0).abs() < 1e-6);
+
+    let mut bounded = quad_variables();
+    bounded[0].upper = 1.2;
+    bounded[0].lower = 1.2;
+    bounded[1].upper = -1.25;
+    let result = match_line(&line, &bounded, &[beta]).unwrap();
+    assert_eq!(result.values[0], 1.2);
+    assert!(result.values[1] <= -1.25);
+}
+
+#[test]
+fn test_match_tracy_variable() {
+    let contents = "kq = 1.2;
+        d1: Drift, L = 0.5;
+        qf: Quadrupole, L = 0.15, B_2 = kq;
+        qd: Quadrupole, L = 0.3, B_2 = -kq;
+        b: Bending, L = 2.0, Phi = 360.0 / 32.0;
+        cell: LINE = (qf, d1, b, d1, qd, d1, b, d1, qf);
+        USE: cell;";
+
+    let variables = [MatchVariable {
+        knob: Knob::Variable("kq".to_string()),
+        lower: 0.5,
+        upper: 2.0,
+    }];
+    let constraints = [MatchConstraint {
+        target: MatchTarget::FracTune(0),
+        value: 0.5,
+        weight: 1.0,
+    }];
+    let result = match_tracy_lattice(
+        contents,
+        FODO_PERIODICITY,
+        FODO_ENERGY,
+        &variables,
+        &constraints,
+    )
+    .unwrap();
+
+    let modes = result.line.normal_modes.as_ref().unwrap();
+    assert!((modes.frac_tunes[0] - 0.5).abs() < 1e-8);
+    assert_eq!(result.line.line[0].k[1], result.values[0]);
+    assert_eq!(result.line.line[4].k[1], -result.values[0]);
+}
+
+#[test]
+fn test_invalid_knob_index() {
+    let mut variables = quad_variables();
+    if let Knob::Element { index, .. } = &mut variables[1].knob {
+        *index = 99;
+    }
+    let constraints = tune_constraints(0.45, 0.2);
+    assert!(match_line(&fodo_ring(false), &variables, &constraints).is_err());
+
+    let contents = "d1: Drift, L = 0.5;
+        qf: Quadrupole, L = 0.15, B_2 = 1.2;
+        qd: Quadrupole, L = 0.3, B_2 = -1.2;
+        cell: LINE = (qf, d1, qd, d1);
+        USE: cell;";
+    let result = match_tracy_lattice(contents, 1, FODO_ENERGY, &variables, &constraints);
+    assert!(result.is_err());
+}
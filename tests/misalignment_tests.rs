@@ -0,0 +1,128 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+use std::f64::consts::PI;
+
+#[test]
+fn test_rolled_quad_matrix() {
+    let mut quad = make_quad("q".to_string(), 0.3, 1.2);
+    quad.set_misalignment(Misalignment {
+        roll: PI / 2.0,
+        ..Default::default()
+    });
+    let opposite = make_quad("q".to_string(), 0.3, -1.2);
+    assert!(
+        (&quad.r_matrix - &opposite.r_matrix)
+            .iter()
+            .all(|x| x.abs() < 1e-12)
+    );
+
+    // A small roll couples the planes.
+    let mut line = fodo_ring(false);
+    line.set_misalignment(
+        5,
+        Misalignment {
+            roll: 0.01,
+            ..Default::default()
+        },
+    );
+    assert!(line.line_matrix[[0, 2]].abs() > 1e-4);
+    assert!(line.normal_modes.is_some());
+}
+
+#[test]
+fn test_displaced_straight_lattice_orbit() {
+    let elements: Vec<Element> = fodo_cell(false)
+        .into_iter()
+        .filter(|ele| ele.k[0] == 0.0)
+        .map(|mut ele| {
+            ele.set_misalignment(Misalignment {
+                dx: 1e-3,
+                dy: -2e-3,
+                ds: 0.01,
+                roll: 0.0,
+            });
+            ele
+        })
+        .collect();
+    let line = Line::from_elements(elements, FODO_PERIODICITY, FODO_ENERGY);
+    let orbit = find_closed_orbit(&line, OrbitDimension::FourD, 0.0).unwrap();
+    for coords in orbit.orbit.iter() {
+        assert!((coords[0] - 1e-3).abs() < 1e-12);
+        assert!((coords[2] + 2e-3).abs() < 1e-12);
+        assert!(coords[1].abs() < 1e-12 && coords[3].abs() < 1e-12);
+    }
+}
+
+#[test]
+fn test_rolled_bend_vertical_orbit_and_dispersion() {
+    let mut line = fodo_ring(false).unrolled();
+    assert_eq!(line.periodicity, 1);
+    assert_eq!(line.line.len(), fodo_cell(false).len() * FODO_PERIODICITY);
+
+    line.set_misalignment(
+        3,
+        Misalignment {
+            roll: 1e-3,
+            ..Default::default()
+        },
+    );
+    let orbit = find_closed_orbit(&line, OrbitDimension::FourD, 0.0).unwrap();
+    let max_y = orbit.orbit.iter().map(|c| c[2].abs()).fold(0.0, f64::max);
+    assert!(max_y > 1e-5);
+
+    let modes = line.normal_modes.as_ref().unwrap();
+    let max_eta_y = modes
+        .optics
+        .iter()
+        .map(|o| o.eta[2].abs())
+        .fold(0.0, f64::max);
+    assert!(max_eta_y > 1e-3);
+
+    // Tracking agrees with the linear transport for small amplitudes.
+    let tracker = Tracker::new(
+        &line,
+        TrackingSettings {
+            slices: Some(20),
+            ..Default::default()
+        },
+    );
+    for index in [3, 5] {
+        let mut ele = line.line[index].clone();
+        ele.set_misalignment(Misalignment {
+            dx: 1e-4,
+            dy: 2e-4,
+            ds: 1e-3,
+            roll: 2e-3,
+        });
+        let mut tracked = [1e-6, 0.0, 0.0, 0.0, 0.0, 0.0];
+        let mut linear = tracked;
+        tracker.track_element(&ele, &mut tracked);
+        ele.transport(&mut linear);
+        for i in 0..4 {
+            assert!((tracked[i] - linear[i]).abs() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn test_update_matrices_keeps_dispersion_and_rf_consistent() {
+    let line = fodo_ring(true);
+    let mut bend = line.line[3].clone();
+    bend.set_misalignment(Misalignment {
+        roll: 0.01,
+        ..Default::default()
+    });
+    for (i, j) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+        assert_eq!(bend.eta_prop_matrix[[i, j]], bend.r_matrix[[i, j]]);
+    }
+    assert_eq!(bend.eta_prop_matrix[[0, 2]], bend.r_matrix[[0, 5]]);
+    assert_eq!(bend.eta_prop_matrix[[1, 2]], bend.r_matrix[[1, 5]]);
+
+    let mut cavity = line.line[10].clone();
+    assert!(cavity.r_matrix[[5, 4]] != 0.0);
+    let r_matrix = cavity.r_matrix.clone();
+    cavity.set_misalignment(Misalignment::default());
+    assert_eq!(cavity.r_matrix, r_matrix);
+}
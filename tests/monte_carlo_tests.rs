@@ -0,0 +1,97 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+
+#[test]
+fn test_rng() {
+    let mut a = Rng::new(42);
+    let mut b = Rng::new(42);
+    let mut c = Rng::new(43);
+    let first: Vec<u64> = (0..10).map(|_| a.next_u64()).collect();
+    assert_eq!(first, (0..10).map(|_| b.next_u64()).collect::<Vec<_>>());
+    assert_ne!(first, (0..10).map(|_| c.next_u64()).collect::<Vec<_>>());
+
+    let values: Vec<f64> = (0..100_000).map(|_| a.truncated_gaussian(2.0)).collect();
+    assert!(values.iter().all(|v| v.abs() <= 2.0));
+    let values: Vec<f64> = (0..100_000).map(|_| a.gaussian()).collect();
+    let stats = Statistics::from_values(&values).unwrap();
+    assert!(stats.mean.abs() < 0.01);
+    assert!((stats.std - 1.0).abs() < 0.01);
+}
+
+fn settings(rms: f64) -> ErrorSettings {
+    ErrorSettings {
+        errors: vec![
+            ErrorSpec {
+                pattern: "q*".to_string(),
+                parameter: ElementParameter::K1,
+                rms,
+                relative: true,
+            },
+            ErrorSpec {
+                pattern: "q*".to_string(),
+                parameter: ElementParameter::Roll,
+                rms: 1e-4,
+                relative: false,
+            },
+        ],
+        cutoff: 2.5,
+    }
+}
+
+#[test]
+fn test_apply_errors() {
+    let line = fodo_ring(false);
+    let first = apply_errors(&line, &settings(1e-3), 7);
+    let again = apply_errors(&line, &settings(1e-3), 7);
+    let other = apply_errors(&line, &settings(1e-3), 8);
+
+    assert_eq!(first.periodicity, 1);
+    assert_eq!(first.line.len(), line.line.len() * FODO_PERIODICITY);
+    let k1 = |l: &Line| l.line.iter().map(|e| e.k[1]).collect::<Vec<f64>>();
+    assert_eq!(k1(&first), k1(&again));
+    assert_ne!(k1(&first), k1(&other));
+
+    // Every instance of a family gets its own error, within the truncation.
+    assert_ne!(first.line[1].k[1], first.line[line.line.len() + 1].k[1]);
+    for (ele, ideal) in first.line.iter().zip(line.line.iter().cycle()) {
+        assert!((ele.k[1] - ideal.k[1]).abs() <= 2.5e-3 * ideal.k[1].abs() + 1e-15);
+    }
+}
+
+#[test]
+fn test_monte_carlo() {
+    let line = fodo_ring(false);
+    let seeds: Vec<u64> = (0..20).collect();
+    let result = monte_carlo(&line, &settings(1e-3), &seeds, None);
+    assert_eq!(result.seeds.len(), 20);
+    assert_eq!(result.unstable(), 0);
+    assert_eq!(result.seeds[3].seed, 3);
+
+    let beat = result.statistics(|s| s.beta_beat.map(|b| b[0])).unwrap();
+    assert_eq!(beat.count, 20);
+    assert!(beat.mean > 1e-4 && beat.max < 0.1);
+    let tune = result.statistics(|s| s.tunes.map(|t| t[0])).unwrap();
+    let ideal = line.normal_modes.as_ref().unwrap().tunes[0];
+    assert!((tune.mean - ideal).abs() < 0.01);
+    assert!(tune.std > 0.0);
+
+    // The rolled quadrupoles couple some of the horizontal emittance into the vertical mode.
+    let horizontal = result.statistics(|s| s.emittances.map(|e| e[0])).unwrap();
+    assert!((horizontal.mean - line.nat_emitt_x).abs() < 0.01 * line.nat_emitt_x);
+    let vertical = result.statistics(|s| s.emittances.map(|e| e[1])).unwrap();
+    assert!(vertical.min > 0.0 && vertical.max < 0.1 * line.nat_emitt_x);
+
+    let rerun = monte_carlo(&line, &settings(1e-3), &seeds, None);
+    assert_eq!(
+        result.seeds[5].beta_beat.unwrap(),
+        rerun.seeds[5].beta_beat.unwrap()
+    );
+
+    let mut no_errors = settings(0.0);
+    no_errors.errors[1].rms = 0.0;
+    let perfect = monte_carlo(&line, &no_errors, &seeds[..2], None);
+    assert!(perfect.seeds[0].beta_beat.unwrap()[0] < 1e-6);
+    assert!(perfect.seeds[0].emittances.unwrap()[1] < 1e-6 * vertical.mean);
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+
+#[test]
+fn test_relative_components() {
+    let mut multipoles = Multipoles::default();
+    // A 1e-4 normal 12-pole error in a quadrupole at a reference radius of 10 mm.
+    multipoles.set_relative(6, 1e-4, 2e-4, 2, 1.2, 0.01);
+    assert!((multipoles.normal[5] - 1e-4 * 1.2 * 0.01_f64.powi(-4)).abs() < 1e-9);
+    let [normal, skew] = multipoles.relative(6, 2, 1.2, 0.01);
+    assert!((normal - 1e-4).abs() < 1e-15);
+    assert!((skew - 2e-4).abs() < 1e-15);
+}
+
+// Jacobian of tracking through the element about `coords`.
+fn tracked_matrix(line: &Line, ele: &Element, coords: [f64; 6]) -> [[f64; 4]; 4] {
+    let tracker = Tracker::new(
+        line,
+        TrackingSettings {
+            slices: Some(40),
+            ..Default::default()
+        },
+    );
+    let step = 1e-7;
+    let mut retval = [[0.0; 4]; 4];
+    for j in 0..4 {
+        let mut plus = coords;
+        let mut minus = coords;
+        plus[j] += step;
+        minus[j] -= step;
+        tracker.track_element(ele, &mut plus);
+        tracker.track_element(ele, &mut minus);
+        for i in 0..4 {
+            retval[i][j] = (plus[i] - minus[i]) / (2.0 * step);
+        }
+    }
+    retval
+}
+
+#[test]
+fn test_feed_down_agrees_with_tracking() {
+    let line = fodo_ring(false);
+    let mut ele = make_sext("s".to_string(), 0.2, 30.0);
+    let mut multipoles = Multipoles::default();
+    multipoles.normal[3] = 500.0;
+    multipoles.skew[2] = 20.0;
+    multipoles.skew[1] = 0.05;
+    ele.set_multipoles(multipoles);
+    ele.set_misalignment(Misalignment {
+        dx: 1e-3,
+        dy: -5e-4,
+        ..Default::default()
+    });
+
+    let matrix = tracked_matrix(&line, &ele, [0.0; 6]);
+    for (i, row) in matrix.iter().enumerate() {
+        for (j, value) in row.iter().enumerate() {
+            assert!((value - ele.r_matrix[[i, j]]).abs() < 1e-4);
+        }
+    }
+    assert!(ele.r_matrix[[1, 0]].abs() > 1e-3);
+    assert!(ele.r_matrix[[3, 0]].abs() > 1e-3);
+
+    let mut tracked = [0.0; 6];
+    let mut linear = tracked;
+    Tracker::new(&line, TrackingSettings::default()).track_element(&ele, &mut tracked);
+    ele.transport(&mut linear);
+    for i in 0..4 {
+        assert!((tracked[i] - linear[i]).abs() < 1e-7);
+    }
+    assert!(linear[1].abs() > 1e-6);
+}
+
+#[test]
+fn test_multipoles_in_line() {
+    let line = fodo_ring(false);
+
+    // A normal quadrupole component acts like a change of gradient.
+    let mut with_b2 = line.clone();
+    with_b2.set_family_parameter("qf", ElementParameter::Normal(2), 0.01);
+    let mut with_k1 = line.clone();
+    with_k1.set_family_parameter("qf", ElementParameter::K1, 1.21);
+    let tune = |l: &Line| l.normal_modes.as_ref().unwrap().tunes[0];
+    assert!((tune(&with_b2) - tune(&with_k1)).abs() < 1e-2 * (tune(&with_k1) - tune(&line)).abs());
+
+    // A skew quadrupole component couples the planes.
+    let mut with_a2 = line.clone();
+    with_a2.set_family_parameter("qd", ElementParameter::Skew(2), 0.01);
+    assert!(with_a2.line_matrix[[0, 2]].abs() > 1e-4);
+    assert_eq!(
+        element_type(&with_a2.line[5]) as usize,
+        EleType::EleTypeQuad as usize
+    );
+
+    // A thin multipole on a marker.
+    let mut elements = fodo_cell(false);
+    elements[0].set_parameter(ElementParameter::Normal(3), 2.0);
+    let thin = Line::from_elements(elements, FODO_PERIODICITY, FODO_ENERGY);
+    let tracker = Tracker::new(&thin, TrackingSettings::default());
+    let mut particle = [1e-3, 0.0, 2e-3, 0.0, 0.0, 0.0];
+    tracker.track_element(&thin.line[0], &mut particle);
+    assert!((particle[1] + 2.0 * (1e-6 - 4e-6)).abs() < 1e-15);
+    assert!((particle[3] - 2.0 * 2.0 * 2e-6).abs() < 1e-15);
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::*;
+use ndarray::Array2;
+use num_complex::Complex64;
+use rust_lattice_analysis::*;
+
+#[test]
+fn test_decoupled_normal_modes_match_uncoupled_optics() {
+    let line = fodo_ring(false);
+    let modes = line.normal_modes.as_ref().unwrap();
+
+    assert_eq!(modes.dimension, 4);
+    assert!((modes.frac_tunes[0] - line.x_frac_tune).abs() < 1e-9);
+    assert!((modes.frac_tunes[1] - line.y_frac_tune).abs() < 1e-9);
+    assert_eq!(modes.optics.len(), line.beta_x_vec.len());
+
+    for (i, optics) in modes.optics.iter().enumerate() {
+        assert!((optics.beta[0][0] - line.beta_x_vec[i]).abs() < 1e-9);
+        assert!((optics.beta[1][1] - line.beta_y_vec[i]).abs() < 1e-9);
+        assert!((optics.eta[0] - line.eta_x_vec[i]).abs() < 1e-9);
+        assert!(optics.beta[0][1].abs() < 1e-12);
+        assert!(optics.beta[1][0].abs() < 1e-12);
+    }
+}
+
+#[test]
+fn test_six_d_normal_modes() {
+    let line = fodo_ring(true);
+    let modes = line.normal_modes.as_ref().unwrap();
+
+    assert_eq!(modes.dimension, 6);
+    assert!(modes.tunes[2] > 0.0 && modes.tunes[2] < 0.5);
+
+    let matrix = line.line_matrix.mapv(|x| Complex64::new(x, 0.0));
+    for (lambda, vec) in modes.eigenvalues.iter().zip(modes.eigenvectors.iter()) {
+        assert!((lambda.norm() - 1.0).abs() < 1e-9);
+        let residual = matrix.dot(vec) - vec.mapv(|x| x * lambda);
+        assert!(residual.iter().all(|x| x.norm() < 1e-9));
+    }
+
+    let rf_off = fodo_ring(false);
+    let eta_4d = rf_off.normal_modes.as_ref().unwrap().optics[0].eta[0];
+    let eta_6d = modes.optics[0].eta[0];
+    assert!(((eta_6d - eta_4d) / eta_4d).abs() < 1e-2);
+}
+
+#[test]
+fn test_coupled_symplectic_eigenmodes() {
+    let quad = make_quad("qf".to_string(), 0.15, 1.2);
+    let (c, s) = (0.1f64.cos(), 0.1f64.sin());
+    let mut rotation = Array2::<f64>::eye(6);
+    rotation[[0, 0]] = c;
+    rotation[[0, 2]] = s;
+    rotation[[2, 0]] = -s;
+    rotation[[2, 2]] = c;
+    rotation[[1, 1]] = c;
+    rotation[[1, 3]] = s;
+    rotation[[3, 1]] = -s;
+    rotation[[3, 3]] = c;
+    let skew = rotation.t().dot(&quad.r_matrix).dot(&rotation);
+
+    let mut cell = fodo_cell(false);
+    cell[1].r_matrix = skew;
+    let matrix = get_line_matrix(&cell);
+    let m4 = matrix.slice(ndarray::s![0..4, 0..4]).to_owned();
+
+    let modes = symplectic_eigenmodes(&m4).unwrap();
+    let s_mat = symplectic_form(4).mapv(|x| Complex64::new(x, 0.0));
+    for (lambda, vec) in modes.iter() {
+        let norm = vec.mapv(|x| x.conj()).dot(&s_mat.dot(vec));
+        assert!((norm - Complex64::new(0.0, 1.0)).norm() < 1e-9);
+        let residual = m4.mapv(|x| Complex64::new(x, 0.0)).dot(vec) - vec.mapv(|x| x * lambda);
+        assert!(residual.iter().all(|x| x.norm() < 1e-9));
+    }
+    assert!(modes[0].1[2].norm() > 1e-6);
+}
+
+#[test]
+fn test_optics_table_inside_elements() {
+    for with_cavity in [false, true] {
+        let line = fodo_ring(with_cavity);
+        let modes = line.normal_modes.as_ref().unwrap();
+        let table = optics_table(&line, 4).unwrap();
+
+        let s = line.s_positions();
+        for (i, optics) in modes.optics.iter().enumerate() {
+            let point = table.iter().find(|p| (p.s - s[i]).abs() < 1e-12).unwrap();
+            for k in 0..2 {
+                assert!((point.optics.beta[k][k] - optics.beta[k][k]).abs() < 1e-9);
+                assert!((point.optics.alpha[k][k] - optics.alpha[k][k]).abs() < 1e-9);
+                assert!((point.optics.phase[k] - optics.phase[k]).abs() < 1e-9);
+            }
+            assert!((point.optics.eta[0] - optics.eta[0]).abs() < 1e-6);
+        }
+    }
+}
+
+#[test]
+fn test_optics_at_magnet_centre() {
+    let line = fodo_ring(false);
+    let modes = line.normal_modes.as_ref().unwrap();
+    let s_qd = line.find_s("qd")[0];
+    let centre = optics_at(&line, s_qd + 0.15).unwrap();
+
+    assert_eq!(centre.element, 5);
+    assert!(centre.optics.beta[1][1] > modes.optics[5].beta[1][1]);
+    assert!(centre.optics.beta[1][1] > modes.optics[6].beta[1][1]);
+    assert!(centre.optics.alpha[1][1].abs() < 1e-9);
+
+    let table = optics_table(&line, 2).unwrap();
+    let point = table
+        .iter()
+        .find(|p| (p.s - centre.s).abs() < 1e-12)
+        .unwrap();
+    assert!((point.optics.beta[1][1] - centre.optics.beta[1][1]).abs() < 1e-9);
+
+    let later = optics_at(&line, centre.s + 3.0 * line.line_length).unwrap();
+    assert!((later.optics.beta[1][1] - centre.optics.beta[1][1]).abs() < 1e-9);
+    let cell_phase = modes.optics[line.line.len()].phase[1];
+    assert!((later.optics.phase[1] - centre.optics.phase[1] - 3.0 * cell_phase).abs() < 1e-9);
+    assert!(optics_at(&line, -1.0).is_none());
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use common::*;
+use ndarray::{Array2, array};
+use rust_lattice_analysis::*;
+
+fn misaligned_ring(line: &Line) -> Line {
+    let error = |parameter| ErrorSpec {
+        pattern: "q*".to_string(),
+        parameter,
+        rms: 50e-6,
+        relative: false,
+    };
+    let settings = ErrorSettings {
+        errors: vec![error(ElementParameter::Dx), error(ElementParameter::Dy)],
+        cutoff: 2.0,
+    };
+    apply_errors(line, &settings, 7)
+}
+
+#[test]
+fn test_svd_reconstructs_matrix() {
+    let a = array![
+        [1.0, 2.0, 0.5],
+        [0.0, -1.0, 3.0],
+        [4.0, 0.2, 1.0],
+        [2.0, 2.0, 2.0]
+    ];
+    for matrix in [a.clone(), a.t().to_owned()] {
+        let (u, s, v) = svd(&matrix).unwrap();
+        assert!(s.windows(2).into_iter().all(|w| w[0] >= w[1]));
+        let reconstructed = u.dot(&Array2::from_diag(&s)).dot(&v.t());
+        let error = (&reconstructed - &matrix)
+            .iter()
+            .fold(0.0, |acc: f64, x| acc.max(x.abs()));
+        assert!(error < 1e-12);
+        let identity: Array2<f64> = Array2::eye(3);
+        assert!((&u.t().dot(&u) - &identity).iter().all(|x| x.abs() < 1e-12));
+        assert!((&v.t().dot(&v) - &identity).iter().all(|x| x.abs() < 1e-12));
+    }
+}
+
+#[test]
+fn test_cutoff_and_regularisation() {
+    let response = array![[2.0, 0.0], [0.0, 1e-4]];
+    let readings = [1.0, 1.0];
+
+    let (kicks, used) =
+        correction_kicks(&response, &readings, &CorrectionSettings::default()).unwrap();
+    assert_eq!(used, 1);
+    assert!((kicks[0] + 0.5).abs() < 1e-12);
+    assert_eq!(kicks[1], 0.0);
+
+    let all = CorrectionSettings {
+        cutoff: 0.0,
+        regularisation: 0.0,
+    };
+    let (kicks, used) = correction_kicks(&response, &readings, &all).unwrap();
+    assert_eq!(used, 2);
+    assert!((kicks[1] + 1e4).abs() < 1e-6);
+
+    let regularised = CorrectionSettings {
+        regularisation: 1e-2,
+        ..all
+    };
+    let (damped, _) = correction_kicks(&response, &readings, &regularised).unwrap();
+    assert!(damped[1].abs() < 1.0);
+    assert!((damped[0] + 0.5).abs() < 1e-4);
+
+    assert_eq!(
+        correction_kicks(&response, &[1.0], &all),
+        Err(CorrectionError::DimensionMismatch)
+    );
+}
+
+#[test]
+fn test_correct_misaligned_ring() {
+    let line = instrumented_ring(FODO_PERIODICITY, 2);
+    let orm_settings = OrmSettings {
+        fixed_path_length: false,
+        ..Default::default()
+    };
+    let response = analytic_orbit_response(&line, &orm_settings).unwrap();
+    let ring = misaligned_ring(&line);
+
+    let result = correct_orbit(&ring, &response, &CorrectionSettings::default()).unwrap();
+    assert_eq!(result.h_kicks.len(), 2 * FODO_PERIODICITY);
+    assert_eq!(result.v_kicks.len(), 2 * FODO_PERIODICITY);
+    for plane in 0..2 {
+        assert!(result.before[plane].rms > 1e-5);
+        assert!(result.after[plane].rms < 0.1 * result.before[plane].rms);
+        assert!(result.after[plane].max <= result.before[plane].max);
+    }
+    assert_eq!(
+        result.line.line[response.h_correctors[0]].kick[0],
+        result.h_kicks[0]
+    );
+
+    // A stronger regularisation gives weaker kicks at the cost of a larger residual.
+    let regularised = correct_orbit(
+        &ring,
+        &response,
+        &CorrectionSettings {
+            regularisation: 20.0,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let norm = |kicks: &[f64]| kicks.iter().map(|k| k * k).sum::<f64>().sqrt();
+    assert!(norm(&regularised.h_kicks) < norm(&result.h_kicks));
+    assert!(regularised.after[0].rms > result.after[0].rms);
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::*;
+use ndarray::Array2;
+use rust_lattice_analysis::*;
+
+fn max_difference(a: &Array2<f64>, b: &Array2<f64>) -> f64 {
+    (a - b).iter().fold(0.0, |acc, x| acc.max(x.abs()))
+}
+
+#[test]
+fn test_analytic_and_numeric_response_agree() {
+    // Also with the cells starting at bpm1, where eta' is not zero.
+    let line = instrumented_ring(FODO_PERIODICITY, 1);
+    let mut cell = line.line.clone();
+    cell.rotate_left(line.find("bpm1")[0]);
+    let rotated = Line::from_elements(cell, FODO_PERIODICITY, FODO_ENERGY);
+
+    for (line, fixed_path_length) in [(&line, false), (&line, true), (&rotated, true)] {
+        let settings = OrmSettings {
+            fixed_path_length,
+            ..Default::default()
+        };
+        let analytic = analytic_orbit_response(line, &settings).unwrap();
+        let numeric = orbit_response(line, &settings, 1e-5).unwrap();
+
+        assert_eq!(analytic.bpms.len(), 2 * FODO_PERIODICITY);
+        assert_eq!(analytic.h_correctors.len(), 2 * FODO_PERIODICITY);
+        assert_eq!(analytic.v_correctors.len(), FODO_PERIODICITY);
+        assert_eq!(analytic.bpms, numeric.bpms);
+        assert_eq!(analytic.horizontal.dim(), (32, 32));
+        assert_eq!(analytic.vertical.dim(), (32, 16));
+
+        let scale = analytic
+            .horizontal
+            .iter()
+            .fold(0.0, |a: f64, x| a.max(x.abs()));
+        assert!(max_difference(&analytic.horizontal, &numeric.horizontal) < 1e-6 * scale);
+        assert!(max_difference(&analytic.vertical, &numeric.vertical) < 1e-6 * scale);
+    }
+}
+
+#[test]
+fn test_fixed_path_length_term() {
+    let line = instrumented_ring(FODO_PERIODICITY, 1);
+    let free = analytic_orbit_response(
+        &line,
+        &OrmSettings {
+            fixed_path_length: false,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let fixed = analytic_orbit_response(&line, &OrmSettings::default()).unwrap();
+
+    // The dispersive term only changes the horizontal response, reducing it where the
+    // dispersion is positive at both the BPM and the corrector.
+    assert_eq!(free.vertical, fixed.vertical);
+    let difference = &free.horizontal - &fixed.horizontal;
+    assert!(difference.iter().all(|x| *x > 0.0));
+}
+
+#[test]
+fn test_coupled_response_matches_closed_orbit() {
+    let mut line = instrumented_ring(FODO_PERIODICITY, 1).unrolled();
+    let qd = line.find("qd")[0];
+    line.set_misalignment(
+        qd,
+        Misalignment {
+            roll: 2e-3,
+            ..Default::default()
+        },
+    );
+    for fixed_path_length in [false, true] {
+        let settings = OrmSettings {
+            fixed_path_length,
+            ..Default::default()
+        };
+        let coupled = coupled_orbit_response(&line, &settings).unwrap();
+        let numeric = orbit_response(&line, &settings, 1e-5).unwrap();
+        let (n_bpm, n_h) = numeric.horizontal.dim();
+        assert_eq!(coupled.dim(), (2 * n_bpm, n_h + numeric.vertical.ncols()));
+
+        let horizontal = coupled.slice(ndarray::s![..n_bpm, ..n_h]).to_owned();
+        let vertical = coupled.slice(ndarray::s![n_bpm.., n_h..]).to_owned();
+        let scale = horizontal.iter().fold(0.0, |a: f64, x| a.max(x.abs()));
+        assert!(max_difference(&horizontal, &numeric.horizontal) < 1e-6 * scale);
+        assert!(max_difference(&vertical, &numeric.vertical) < 1e-6 * scale);
+
+        // The rolled quadrupole couples the planes.
+        let coupling = coupled.slice(ndarray::s![n_bpm.., ..n_h]);
+        assert!(coupling.iter().any(|x| x.abs() > 1e-3 * scale));
+    }
+}
+
+#[test]
+fn test_coupled_response_with_thick_correctors() {
+    let mut cell = instrumented_ring(FODO_PERIODICITY, 1).line;
+    for ele in cell.iter_mut().filter(|ele| ele.name.starts_with('c')) {
+        *ele = make_drift(ele.name.clone(), 0.4);
+    }
+    let line = Line::from_elements(cell, FODO_PERIODICITY, FODO_ENERGY);
+    let settings = OrmSettings::default();
+    let coupled = coupled_orbit_response(&line, &settings).unwrap();
+    let numeric = orbit_response(&line, &settings, 1e-5).unwrap();
+
+    let (n_bpm, n_h) = numeric.horizontal.dim();
+    let horizontal = coupled.slice(ndarray::s![..n_bpm, ..n_h]).to_owned();
+    let vertical = coupled.slice(ndarray::s![n_bpm.., n_h..]).to_owned();
+    let scale = horizontal.iter().fold(0.0, |a: f64, x| a.max(x.abs()));
+    assert!(max_difference(&horizontal, &numeric.horizontal) < 1e-6 * scale);
+    assert!(max_difference(&vertical, &numeric.vertical) < 1e-6 * scale);
+}
@@ -146,7 +146,7 @@ fn test_parse_statement() {
 fn test_parse_tracy_file() {
     use Statement::*;
 
-    let mut input = r#"h_rf = 176;
+    let input = r#"h_rf = 176;
     C    = 528.0/20.0;
     
     
@@ -161,7 +161,7 @@ fn test_parse_tracy_file() {
     
     USE: sp;
     "#;
-    let output = parse_tracy_file(&mut input);
+    let output = parse_tracy_file(input);
     assert_eq!(
         output,
         vec![
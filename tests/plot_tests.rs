@@ -0,0 +1,29 @@
+mod common;
+
+use common::*;
+use rust_lattice_analysis::*;
+
+#[test]
+fn test_optics_svg() {
+    let line = fodo_ring(false);
+    let svg = optics_svg(&line);
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.trim_end().ends_with("</svg>"));
+    assert_eq!(svg.matches("<polyline").count(), 3);
+    // Two bends and three quadrupoles in the layout bar.
+    assert_eq!(svg.matches("fill=\"royalblue\"").count(), 2);
+    assert_eq!(svg.matches("fill=\"crimson\"").count(), 3);
+    assert!(!svg.contains("NaN"));
+}
+
+#[test]
+fn test_floor_plan_svg() {
+    let line = fodo_ring(true);
+    let svg = floor_plan_svg(&line);
+    assert!(svg.starts_with("<svg"));
+    assert_eq!(
+        svg.matches("stroke=\"royalblue\"").count(),
+        2 * FODO_PERIODICITY
+    );
+    assert!(!svg.contains("NaN"));
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use common::*;
+use num_complex::Complex64;
+use rust_lattice_analysis::*;
+use std::f64::consts::PI;
+
+fn assert_close(a: Complex64, b: Complex64, tolerance: f64) {
+    assert!((a - b).norm() < tolerance * b.norm(), "{a} != {b}");
+}
+
+#[test]
+fn test_first_order_sextupole_terms() {
+    let line = nonlinear_ring(0.01, 2000.0, 0.0);
+    let modes = line.normal_modes.as_ref().unwrap();
+    let cell_phase = modes.optics[line.line.len()].phase;
+    let terms = driving_terms(&line).unwrap();
+
+    let mut h21000 = Complex64::default();
+    let mut h10020 = Complex64::default();
+    let mut h30000_cell = Complex64::default();
+    for cell in 0..line.periodicity {
+        for (i, ele) in line.line.iter().enumerate() {
+            if ele.k[2] == 0.0 {
+                continue;
+            }
+            let optics = &modes.optics[i];
+            let b3l = ele.k[2] * ele.length;
+            let (beta_x, beta_y) = (optics.beta[0][0], optics.beta[1][1]);
+            let phase_x = optics.phase[0] + cell as f64 * cell_phase[0];
+            let phase_y = optics.phase[1] + cell as f64 * cell_phase[1];
+            h21000 -= b3l * beta_x.powf(1.5) * Complex64::from_polar(0.125, phase_x);
+            h10020 += b3l
+                * beta_x.sqrt()
+                * beta_y
+                * Complex64::from_polar(0.125, phase_x - 2.0 * phase_y);
+            if cell == 0 {
+                h30000_cell -=
+                    b3l * beta_x.powf(1.5) * Complex64::from_polar(1.0 / 24.0, 3.0 * phase_x);
+            }
+        }
+    }
+    assert_close(terms.h21000, h21000, 1e-2);
+    assert_close(terms.h10020, h10020, 1e-2);
+
+    // The one-turn value is the single cell value summed with the phase advance of each cell.
+    let cells: Complex64 = (0..line.periodicity)
+        .map(|c| Complex64::from_polar(1.0, 3.0 * c as f64 * cell_phase[0]))
+        .sum();
+    assert!(
+        (terms.h30000.norm() - (h30000_cell * cells).norm()).abs() < 1e-2 * terms.h30000.norm()
+    );
+}
+
+#[test]
+fn test_octupole_terms_match_detuning() {
+    let line = nonlinear_ring(0.01, 0.0, 1e5);
+    let terms = driving_terms(&line).unwrap();
+    let settings = DetuningSettings {
+        x_max: 5e-4,
+        y_max: 5e-4,
+        n_amplitudes: 3,
+        n_turns: 256,
+        tracking: TrackingSettings {
+            slices: Some(10),
+            cavities: false,
+            ..Default::default()
+        },
+    };
+    let tracked = tracked_detuning(&line, &settings).unwrap();
+
+    let close = |a: f64, b: f64| ((a - b) / b).abs() < 0.02;
+    assert!(close(-4.0 * terms.h22000.re / PI, tracked.dqx_djx));
+    assert!(close(-2.0 * terms.h11110.re / PI, tracked.dqx_djy));
+    assert!(close(-4.0 * terms.h00220.re / PI, tracked.dqy_djy));
+}
+
+// Horizontal and vertical beta at the start of the line from its tracked off-momentum matrix.
+fn tracked_beta(line: &Line, delta: f64) -> [f64; 2] {
+    let tracker = Tracker::new(
+        line,
+        TrackingSettings {
+            slices: Some(10),
+            ..Default::default()
+        },
+    );
+    let orbit = find_closed_orbit_with(line, OrbitDimension::FourD, delta, |ele, coords| {
+        tracker.track_element(ele, coords);
+    })
+    .unwrap();
+    let m = &orbit.line_matrix;
+    [0, 2].map(|i| {
+        let cos_mu = 0.5 * (m[[i, i]] + m[[i + 1, i + 1]]);
+        m[[i, i + 1]].abs() / (1.0 - cos_mu * cos_mu).sqrt()
+    })
+}
+
+#[test]
+fn test_chromatic_terms_match_beta_beat() {
+    // Without sextupoles only the quadrupoles and the weak focusing of the bends contribute.
+    for b3 in [0.0, 2000.0] {
+        let line = nonlinear_ring(0.01, b3, 0.0);
+        let modes = line.normal_modes.as_ref().unwrap();
+        let terms = driving_terms(&line).unwrap();
+        let delta = 1e-5;
+        let (plus, minus) = (tracked_beta(&line, delta), tracked_beta(&line, -delta));
+        for (plane, h) in [terms.h20001, terms.h00201].iter().enumerate() {
+            // d(beta)/d(delta) / beta = 4 Re(h e^(-2 pi i Q)) / sin(2 pi Q) at the start, less one
+            // as the tracked beta is in terms of the momentum rather than the angle.
+            let mu = 2.0 * PI * modes.tunes[plane];
+            let expected = 4.0 * (h * Complex64::from_polar(1.0, -mu)).re / mu.sin() - 1.0;
+            let beta = modes.optics[0].beta[plane][plane];
+            let beat = (plus[plane] - minus[plane]) / (2.0 * delta * beta);
+            assert!(
+                (beat - expected).abs() < 1e-3 * expected.abs(),
+                "{beat} != {expected}"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_build_up() {
+    let line = nonlinear_ring(0.01, 2000.0, 0.0);
+    let build_up = driving_term_build_up(&line).unwrap();
+    let terms = driving_terms(&line).unwrap();
+
+    assert_eq!(build_up.len(), line.line.len() * line.periodicity + 1);
+    assert_eq!(build_up[0].terms.h21000.norm(), 0.0);
+    assert!((build_up.last().unwrap().s - line.total_length).abs() < 1e-9);
+    assert_eq!(build_up.last().unwrap().terms.h21000, terms.h21000);
+
+    // Terms of a real Hamiltonian with equal powers of the + and - variables are real.
+    assert!(terms.h22000.norm() > 0.0);
+    assert!(terms.h22000.im.abs() < 1e-9 * terms.h22000.norm());
+    assert!(terms.h11110.im.abs() < 1e-9 * terms.h11110.norm());
+}
@@ -0,0 +1,171 @@
+mod common;
+
+use common::*;
+use ndarray::Array2;
+use rust_lattice_analysis::*;
+use std::fs;
+
+fn settings(order: IntegratorOrder, slices: usize) -> TrackingSettings {
+    TrackingSettings {
+        order,
+        slices: Some(slices),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_small_amplitude_tracking_matches_transfer_matrix() {
+    let line = fodo_ring(false);
+    let tracker = Tracker::new(&line, settings(IntegratorOrder::Fourth, 20));
+
+    let mut tracked: Particle = [1e-4, -2e-5, 5e-5, 1e-5, 0.0, 0.0];
+    let mut linear = tracked;
+    assert!(tracker.track_line(&mut tracked).is_none());
+    for ele in line.line.iter() {
+        ele.transport(&mut linear);
+    }
+
+    for (a, b) in tracked.iter().zip(linear.iter()).take(4) {
+        assert!((a - b).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_fourth_order_is_more_accurate() {
+    let line = fodo_ring(false);
+    let quad = make_quad("q".to_string(), 0.5, 2.0);
+    let start: Particle = [1e-3, 0.0, 1e-3, 0.0, 0.0, 0.0];
+
+    let mut exact = start;
+    quad.transport(&mut exact);
+
+    let error = |order| {
+        let mut particle = start;
+        Tracker::new(&line, settings(order, 4)).track_element(&quad, &mut particle);
+        (particle[0] - exact[0]).abs() + (particle[1] - exact[1]).abs()
+    };
+    assert!(error(IntegratorOrder::Fourth) < 0.1 * error(IntegratorOrder::Second));
+}
+
+#[test]
+fn test_sextupole_kick() {
+    let line = fodo_ring(false);
+    let tracker = Tracker::new(&line, settings(IntegratorOrder::Fourth, 10));
+    let sext = make_sext("s".to_string(), 0.1, 20.0);
+
+    let mut particle: Particle = [1e-3, 0.0, 0.0, 0.0, 0.0, 0.0];
+    tracker.track_element(&sext, &mut particle);
+    let expected = -20.0 * 1e-6 * 0.1;
+    assert!(((particle[1] - expected) / expected).abs() < 1e-2);
+
+    let mut particle: Particle = [1e-3, 0.0, 1e-3, 0.0, 0.0, 0.0];
+    tracker.track_element(&sext, &mut particle);
+    assert!(((particle[3] - 2.0 * 20.0 * 1e-6 * 0.1) / particle[3]).abs() < 1e-2);
+}
+
+#[test]
+fn test_thin_sextupole_kick() {
+    let line = fodo_ring(false);
+    let tracker = Tracker::new(&line, settings(IntegratorOrder::Fourth, 10));
+    let mut sext = make_sext("s".to_string(), 0.0, 2.0);
+    sext.kick = [1e-6, -1e-6];
+    assert!(matches!(element_type(&sext), EleType::EleTypeMult));
+
+    let mut particle: Particle = [1e-3, 0.0, 2e-3, 0.0, 0.0, 0.0];
+    assert!(tracker.track_element(&sext, &mut particle));
+    assert!((particle[1] - (1e-6 - 2.0 * (1e-6 - 4e-6))).abs() < 1e-15);
+    assert!((particle[3] - (-1e-6 + 2.0 * 2.0 * 2e-6)).abs() < 1e-15);
+    assert_eq!([particle[0], particle[2]], [1e-3, 2e-3]);
+}
+
+#[test]
+fn test_tracking_is_symplectic() {
+    let line = fodo_ring(false);
+    let tracker = Tracker::new(&line, settings(IntegratorOrder::Fourth, 3));
+    let mut cell = fodo_cell(false);
+    cell.insert(3, make_sext("s".to_string(), 0.2, 50.0));
+    cell.insert(3, make_oct("o".to_string(), 0.2, 500.0));
+
+    let start: Particle = [2e-3, 1e-4, -1e-3, 2e-4, 0.0, 1e-3];
+    let map = |coords: &Particle| {
+        let mut retval = *coords;
+        for ele in cell.iter() {
+            tracker.track_element(ele, &mut retval);
+        }
+        retval
+    };
+
+    let step = 1e-7;
+    let mut jacobian = Array2::<f64>::zeros((6, 6));
+    for j in 0..6 {
+        let mut plus = start;
+        let mut minus = start;
+        plus[j] += step;
+        minus[j] -= step;
+        let (plus, minus) = (map(&plus), map(&minus));
+        for i in 0..6 {
+            let sign = if (i == 4) != (j == 4) { -1.0 } else { 1.0 };
+            jacobian[[i, j]] = sign * (plus[i] - minus[i]) / (2.0 * step);
+        }
+    }
+
+    let s_mat = symplectic_form(6);
+    let error = jacobian.t().dot(&s_mat).dot(&jacobian) - &s_mat;
+    assert!(error.iter().all(|x| x.abs() < 1e-7));
+}
+
+#[test]
+fn test_particle_loss() {
+    let line = fodo_ring(false);
+    let tracker = Tracker::new(
+        &line,
+        TrackingSettings {
+            aperture: 0.05,
+            ..Default::default()
+        },
+    );
+
+    let mut particle: Particle = [1e-4, 0.0, 1e-4, 0.0, 0.0, 0.0];
+    assert!(tracker.track_turns(&mut particle, 10).is_none());
+
+    let mut particle: Particle = [0.0, 0.0, 0.04, 0.0, 0.0, 0.0];
+    assert_eq!(tracker.track_turns(&mut particle, 10), Some(0));
+}
+
+#[test]
+fn test_nonlinear_closed_orbit() {
+    let mut cell = fodo_cell(false);
+    cell.insert(2, make_sext("s".to_string(), 0.2, 20.0));
+    let line = Line::from_elements(cell, FODO_PERIODICITY, FODO_ENERGY);
+    let tracker = Tracker::new(&line, TrackingSettings::default());
+
+    let delta = 1e-4;
+    let linear = find_closed_orbit(&line, OrbitDimension::FourD, delta).unwrap();
+    let tracked = find_closed_orbit_with(&line, OrbitDimension::FourD, delta, |ele, coords| {
+        tracker.track_element(ele, coords);
+    })
+    .unwrap();
+
+    let (x_lin, x_tracked) = (linear.orbit[0][0], tracked.orbit[0][0]);
+    assert!(((x_tracked - x_lin) / x_lin).abs() < 1e-2);
+}
+
+#[test]
+fn test_parse_n_slices() {
+    let file_path = std::env::temp_dir().join("rust_lattice_analysis_n_slices.lat");
+    fs::write(
+        &file_path,
+        "n_quad = 4;
+        d1: Drift, L = 0.5;
+        q1: Quadrupole, L = 0.2, B_2 = 1.0, N = n_quad;
+        s1: Sextupole, L = 0.1, B_3 = 10.0, N = 2;
+        cell: LINE = (d1, q1, s1);
+        USE: cell;",
+    )
+    .unwrap();
+
+    let line = parse_lattice_from_tracy_file(file_path.to_str().unwrap()).unwrap();
+    assert_eq!(line[0].n_slices, 1);
+    assert_eq!(line[1].n_slices, 4);
+    assert_eq!(line[2].n_slices, 2);
+}